@@ -0,0 +1,520 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use richter::{
+    client::demo::{ConversionIssue, DemoMessageView, DemoProtocol, DemoServer},
+    common::{
+        self, engine,
+        net::{msglog::cmd_name, ClientStat, ServerCmd},
+        vfs::Vfs,
+    },
+};
+
+use chrono::Duration;
+use serde::Serialize;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    version: bool,
+
+    /// Directory containing `id1/`, used to find demos that aren't paths on disk.
+    #[structopt(long, parse(from_os_str))]
+    base_dir: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print every command in the demo.
+    Dump {
+        /// Print the messages as JSON. Each command's "data" is its Debug representation, which
+        /// is meant for reading rather than parsing and may change between versions.
+        #[structopt(long)]
+        json: bool,
+
+        /// Pretty-print each command.
+        #[structopt(short, long)]
+        pretty: bool,
+
+        #[structopt(name = "DEMO")]
+        demo: String,
+    },
+
+    /// Print summary statistics for the demo.
+    Info {
+        #[structopt(name = "DEMO")]
+        demo: String,
+    },
+
+    /// Copy a time range of the demo into a new demo file.
+    Extract {
+        /// Start of the range, in seconds from the start of the demo.
+        #[structopt(long, default_value = "0")]
+        start: f32,
+
+        /// End of the range, in seconds from the start of the demo. Defaults to the end.
+        #[structopt(long)]
+        end: Option<f32>,
+
+        #[structopt(name = "DEMO")]
+        demo: String,
+
+        #[structopt(name = "OUTPUT_DEMO", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Re-encode the demo with a different network protocol.
+    Convert {
+        /// Protocol to convert to: 15 (NetQuake) or 666 (FitzQuake).
+        #[structopt(long)]
+        protocol: DemoProtocol,
+
+        /// Print every command that couldn't be converted exactly.
+        #[structopt(short, long)]
+        verbose: bool,
+
+        #[structopt(name = "DEMO")]
+        demo: String,
+
+        #[structopt(name = "OUTPUT_DEMO", parse(from_os_str))]
+        output: PathBuf,
+    },
+}
+
+const VERSION: &'static str = "
+demotool 0.1
+Copyright © 2020 Cormac O'Brien
+Released under the terms of the MIT License
+";
+
+#[derive(Serialize)]
+struct CommandJson {
+    /// The command's variant name, e.g. `Print`.
+    name: String,
+
+    /// The command's `Debug` representation. This is opaque: its format follows the Rust types
+    /// in `common::net` and isn't a stable interface.
+    data: String,
+}
+
+#[derive(Serialize)]
+struct MessageJson {
+    id: usize,
+    time: f32,
+    view_angles: [f32; 3],
+    size: usize,
+    commands: Vec<CommandJson>,
+    error: Option<String>,
+}
+
+struct LevelSummary {
+    map: String,
+    title: String,
+    start: Duration,
+    killed_monsters: i32,
+    total_monsters: i32,
+    found_secrets: i32,
+    total_secrets: i32,
+}
+
+/// Creates an output demo file, exiting on failure.
+fn create_output(output: &Path) -> BufWriter<File> {
+    match File::create(output) {
+        Ok(f) => BufWriter::new(f),
+        Err(why) => {
+            eprintln!("Couldn't create {}: {}", output.display(), why);
+            exit(1);
+        }
+    }
+}
+
+/// Opens a demo from a path on disk, or failing that, from the virtual filesystem.
+fn open_demo(base_dir: Option<&Path>, demo: &str) -> DemoServer {
+    let result = if Path::new(demo).is_file() {
+        File::open(demo)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| DemoServer::new(&mut f).map_err(|e| e.to_string()))
+    } else {
+        let base_dir = base_dir
+            .map(Path::to_path_buf)
+            .unwrap_or_else(common::default_base_dir);
+        if !base_dir.join("id1").is_dir() {
+            eprintln!(
+                "{} is not a file and {} has no id1/ directory to search",
+                demo,
+                base_dir.display()
+            );
+            exit(1);
+        }
+
+        let vfs = Vfs::with_base_dir(base_dir);
+        let name = if demo.ends_with(".dem") {
+            demo.to_owned()
+        } else {
+            format!("{}.dem", demo)
+        };
+
+        vfs.open(&name)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| DemoServer::new(&mut f).map_err(|e| e.to_string()))
+    };
+
+    match result {
+        Ok(d) => d,
+        Err(why) => {
+            eprintln!("Couldn't open {}: {}", demo, why);
+            exit(1);
+        }
+    }
+}
+
+/// Decodes every command in a demo message.
+///
+/// If the message is invalid, the commands decoded before the error are returned with it.
+fn decode(msg: &DemoMessageView) -> (Vec<ServerCmd>, Option<String>) {
+    let mut reader = Cursor::new(msg.message());
+    let mut cmds = Vec::new();
+
+    loop {
+        match ServerCmd::deserialize(&mut reader) {
+            Ok(Some(cmd)) => cmds.push(cmd),
+            Ok(None) => return (cmds, None),
+            Err(e) => return (cmds, Some(e.to_string())),
+        }
+    }
+}
+
+fn dump<W>(out: &mut W, demo_srv: &DemoServer, json: bool, pretty: bool) -> std::io::Result<()>
+where
+    W: Write,
+{
+    if json {
+        let messages: Vec<MessageJson> = demo_srv
+            .messages()
+            .enumerate()
+            .map(|(id, msg)| {
+                let (cmds, error) = decode(&msg);
+                let angles = msg.view_angles();
+                MessageJson {
+                    id,
+                    time: engine::duration_to_f32(msg.time()),
+                    view_angles: [angles.x.0, angles.y.0, angles.z.0],
+                    size: msg.message().len(),
+                    commands: cmds
+                        .iter()
+                        .map(|cmd| {
+                            let data = format!("{:?}", cmd);
+                            CommandJson {
+                                name: cmd_name(&data).to_owned(),
+                                data,
+                            }
+                        })
+                        .collect(),
+                    error,
+                }
+            })
+            .collect();
+
+        let result = if pretty {
+            serde_json::to_writer_pretty(&mut *out, &messages)
+        } else {
+            serde_json::to_writer(&mut *out, &messages)
+        };
+        result.map_err(std::io::Error::from)?;
+        return writeln!(out);
+    }
+
+    for (id, msg) in demo_srv.messages().enumerate() {
+        let angles = msg.view_angles();
+        writeln!(
+            out,
+            "[{:>10.4}] message {} ({} bytes, view angles {} {} {})",
+            engine::duration_to_f32(msg.time()),
+            id,
+            msg.message().len(),
+            angles.x.0,
+            angles.y.0,
+            angles.z.0,
+        )?;
+
+        let (cmds, error) = decode(&msg);
+        for cmd in cmds {
+            if pretty {
+                writeln!(out, "{:#?}", cmd)?;
+            } else {
+                writeln!(out, "    {:?}", cmd)?;
+            }
+        }
+
+        if let Some(e) = error {
+            writeln!(out, "    (failed to decode: {})", e)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn info<W>(out: &mut W, demo_srv: &DemoServer) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let mut levels: Vec<LevelSummary> = Vec::new();
+    let mut players: BTreeMap<u8, (String, i16)> = BTreeMap::new();
+    let mut cmd_counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut messages = 0;
+    let mut bad_messages = 0;
+
+    for msg in demo_srv.messages() {
+        messages += 1;
+
+        let (cmds, error) = decode(&msg);
+        if error.is_some() {
+            bad_messages += 1;
+        }
+
+        for cmd in cmds {
+            *cmd_counts
+                .entry(cmd_name(&format!("{:?}", cmd)).to_owned())
+                .or_insert(0) += 1;
+
+            match cmd {
+                ServerCmd::ServerInfo {
+                    message,
+                    model_precache,
+                    ..
+                } => levels.push(LevelSummary {
+                    map: model_precache.into_iter().next().unwrap_or_default(),
+                    title: message,
+                    start: msg.time(),
+                    killed_monsters: 0,
+                    total_monsters: 0,
+                    found_secrets: 0,
+                    total_secrets: 0,
+                }),
+
+                ServerCmd::UpdateName {
+                    player_id,
+                    new_name,
+                } => players.entry(player_id).or_insert((String::new(), 0)).0 = new_name,
+
+                ServerCmd::UpdateFrags {
+                    player_id,
+                    new_frags,
+                } => players.entry(player_id).or_insert((String::new(), 0)).1 = new_frags,
+
+                cmd => {
+                    let level = match levels.last_mut() {
+                        Some(l) => l,
+                        None => continue,
+                    };
+
+                    match cmd {
+                        ServerCmd::KilledMonster => level.killed_monsters += 1,
+                        ServerCmd::FoundSecret => level.found_secrets += 1,
+                        ServerCmd::UpdateStat { stat, value } => match stat {
+                            ClientStat::KilledMonsters => level.killed_monsters = value,
+                            ClientStat::TotalMonsters => level.total_monsters = value,
+                            ClientStat::FoundSecrets => level.found_secrets = value,
+                            ClientStat::TotalSecrets => level.total_secrets = value,
+                            _ => (),
+                        },
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "Duration: {:.1}s",
+        engine::duration_to_f32(demo_srv.duration())
+    )?;
+    writeln!(out, "Messages: {} ({} invalid)", messages, bad_messages)?;
+    match demo_srv.track_override() {
+        Some(t) => writeln!(out, "CD track: {}", t)?,
+        None => writeln!(out, "CD track: set by demo")?,
+    }
+
+    writeln!(out, "\nLevels:")?;
+    for level in levels.iter() {
+        writeln!(
+            out,
+            "  [{:>8.1}] {} ({}): {}/{} kills, {}/{} secrets",
+            engine::duration_to_f32(level.start),
+            level.map,
+            level.title.trim(),
+            level.killed_monsters,
+            level.total_monsters,
+            level.found_secrets,
+            level.total_secrets,
+        )?;
+    }
+
+    if !players.is_empty() {
+        writeln!(out, "\nPlayers:")?;
+        for (id, (name, frags)) in players.iter() {
+            writeln!(out, "  {:>2} {:<16} {:>4} frags", id, name, frags)?;
+        }
+    }
+
+    writeln!(out, "\nCommands:")?;
+    for (name, count) in cmd_counts.iter() {
+        writeln!(out, "  {:<20} {:>8}", name, count)?;
+    }
+
+    Ok(())
+}
+
+fn print_issues<W>(out: &mut W, issues: &[ConversionIssue], verbose: bool) -> std::io::Result<()>
+where
+    W: Write,
+{
+    if verbose {
+        for issue in issues.iter() {
+            writeln!(out, "{}", issue)?;
+        }
+
+        return Ok(());
+    }
+
+    // summarize by command, since the same loss tends to repeat every frame
+    let mut counts: BTreeMap<(&str, bool), usize> = BTreeMap::new();
+    for issue in issues.iter() {
+        *counts
+            .entry((issue.cmd.as_str(), issue.dropped))
+            .or_insert(0) += 1;
+    }
+
+    if counts.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out, "Commands not converted exactly:")?;
+    for ((cmd, dropped), count) in counts.iter() {
+        writeln!(
+            out,
+            "  {:<20} {:<10} {:>8}",
+            cmd,
+            if *dropped { "dropped" } else { "downgraded" },
+            count
+        )?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if opt.version {
+        println!("{}", VERSION);
+        exit(0);
+    }
+
+    let cmd = match opt.cmd {
+        Some(c) => c,
+        None => {
+            Opt::clap().print_help().unwrap();
+            println!();
+            exit(1);
+        }
+    };
+
+    let base_dir = opt.base_dir.as_deref();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    let result = match cmd {
+        Command::Dump { json, pretty, demo } => {
+            dump(&mut out, &open_demo(base_dir, &demo), json, pretty)
+        }
+
+        Command::Info { demo } => info(&mut out, &open_demo(base_dir, &demo)),
+
+        Command::Extract {
+            start,
+            end,
+            demo,
+            output,
+        } => {
+            let demo_srv = open_demo(base_dir, &demo);
+            let start = engine::duration_from_f32(start);
+            let end = end
+                .map(engine::duration_from_f32)
+                .unwrap_or_else(|| demo_srv.duration());
+
+            if end < start {
+                eprintln!("End of range is before the start");
+                exit(1);
+            }
+
+            match demo_srv.extract(start, end, create_output(&output)) {
+                Ok(mut writer) => writer.flush(),
+                Err(why) => {
+                    eprintln!("Couldn't extract demo: {}", why);
+                    exit(1);
+                }
+            }
+        }
+
+        Command::Convert {
+            protocol,
+            verbose,
+            demo,
+            output,
+        } => {
+            let demo_srv = open_demo(base_dir, &demo);
+            let issues = match demo_srv.convert(protocol, create_output(&output)) {
+                Ok((mut writer, issues)) => {
+                    if let Err(why) = writer.flush() {
+                        eprintln!("Couldn't write {}: {}", output.display(), why);
+                        exit(1);
+                    }
+
+                    issues
+                }
+
+                Err(why) => {
+                    eprintln!("Couldn't convert demo: {}", why);
+                    exit(1);
+                }
+            };
+
+            print_issues(&mut out, &issues, verbose)
+        }
+    };
+
+    if let Err(why) = result {
+        eprintln!("Couldn't write output: {}", why);
+        exit(1);
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    path::PathBuf,
+    process::exit,
+};
+
+use richter::common::{
+    engine,
+    net::msglog::{Direction, MessageLogEntry, MessageLogReader},
+};
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    version: bool,
+
+    /// Only show commands travelling in this direction ("recv" or "send").
+    #[structopt(short, long)]
+    direction: Option<Direction>,
+
+    /// Only show commands with this name (e.g. "Print"). May be repeated.
+    #[structopt(short, long = "cmd")]
+    cmds: Vec<String>,
+
+    /// Hide commands with this name. May be repeated.
+    #[structopt(short = "x", long = "exclude")]
+    excludes: Vec<String>,
+
+    /// Print a hex dump of each command's wire representation.
+    #[structopt(short, long)]
+    raw: bool,
+
+    /// Decode each command again and pretty-print it.
+    #[structopt(short, long)]
+    pretty: bool,
+
+    #[structopt(name = "INPUT_LOG", parse(from_os_str))]
+    input_log: PathBuf,
+}
+
+const VERSION: &'static str = "
+netlog 0.1
+Copyright © 2020 Cormac O'Brien
+Released under the terms of the MIT License
+";
+
+fn matches(opt: &Opt, entry: &MessageLogEntry) -> bool {
+    if let Some(d) = opt.direction {
+        if d != entry.direction {
+            return false;
+        }
+    }
+
+    if !opt.cmds.is_empty() && !opt.cmds.iter().any(|c| c.eq_ignore_ascii_case(&entry.name)) {
+        return false;
+    }
+
+    !opt.excludes
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(&entry.name))
+}
+
+fn print_entry<W>(opt: &Opt, out: &mut W, entry: &MessageLogEntry) -> std::io::Result<()>
+where
+    W: Write,
+{
+    writeln!(
+        out,
+        "[{:>10.4}] {} {} ({} bytes)",
+        engine::duration_to_f32(entry.time),
+        entry.direction.as_str(),
+        entry.name,
+        entry.raw.len(),
+    )?;
+
+    if opt.raw {
+        for chunk in entry.raw.chunks(16) {
+            write!(out, "   ")?;
+            for b in chunk {
+                write!(out, " {:02x}", b)?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    if opt.pretty {
+        let decoded = match entry.direction {
+            Direction::Recv => entry.server_cmd().map(|c| format!("{:#?}", c)),
+            Direction::Send => entry.client_cmd().map(|c| format!("{:#?}", c)),
+        };
+
+        match decoded {
+            Ok(d) => writeln!(out, "{}", d)?,
+            Err(e) => writeln!(out, "    (failed to decode: {})", e)?,
+        }
+    } else {
+        writeln!(out, "    {}", entry.decoded)?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if opt.version {
+        println!("{}", VERSION);
+        exit(0);
+    }
+
+    let file = match File::open(&opt.input_log) {
+        Ok(f) => f,
+        Err(why) => {
+            eprintln!("Couldn't open {:#?}: {}", &opt.input_log, why);
+            exit(1);
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    for (i, entry) in MessageLogReader::new(BufReader::new(file)).enumerate() {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(why) => {
+                eprintln!("Bad log entry (#{}): {}", i, why);
+                exit(1);
+            }
+        };
+
+        if !matches(&opt, &entry) {
+            continue;
+        }
+
+        if let Err(why) = print_entry(&opt, &mut out, &entry) {
+            eprintln!("Couldn't write output: {}", why);
+            exit(1);
+        }
+    }
+}
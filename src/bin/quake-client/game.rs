@@ -94,7 +94,7 @@ impl Game {
     pub fn frame(&mut self, gfx_state: &GraphicsState, frame_duration: Duration) {
         use ClientError::*;
 
-        match self.client.frame(frame_duration, gfx_state) {
+        match self.client.frame(frame_duration, Some(gfx_state)) {
             Ok(()) => (),
             Err(e) => match e {
                 Cvar(_)
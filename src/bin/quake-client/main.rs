@@ -25,9 +25,6 @@ mod trace;
 
 use std::{
     cell::{Ref, RefCell, RefMut},
-    fs::File,
-    io::{Cursor, Read, Write},
-    net::SocketAddr,
     path::{Path, PathBuf},
     process::exit,
     rc::Rc,
@@ -36,11 +33,10 @@ use std::{
 use game::Game;
 
 use chrono::Duration;
-use common::net::ServerCmd;
 use richter::{
     client::{
         self,
-        demo::DemoServer,
+        headless::HeadlessClient,
         input::{Input, InputFocus},
         menu::Menu,
         render::{self, Extent2d, GraphicsState, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT},
@@ -48,10 +44,11 @@ use richter::{
     },
     common::{
         self,
-        console::{CmdRegistry, Console, CvarRegistry},
+        console::{cmd_exec, CmdRegistry, Console, CvarRegistry},
         host::{Host, Program},
         vfs::Vfs,
     },
+    server,
 };
 use structopt::StructOpt;
 use winit::{
@@ -88,6 +85,8 @@ impl ClientProgram {
         let cvars = Rc::new(RefCell::new(CvarRegistry::new(con_names.clone())));
         client::register_cvars(&cvars.borrow()).unwrap();
         render::register_cvars(&cvars.borrow());
+        // the client reads some server cvars, and they notify clients when changed
+        server::register_cvars(&cvars.borrow()).unwrap();
 
         let cmds = Rc::new(RefCell::new(CmdRegistry::new(con_names)));
         // TODO: register commands as other subsystems come online
@@ -148,43 +147,15 @@ impl ClientProgram {
 
         let vfs = Rc::new(vfs);
 
-        // TODO: warn user if r_msaa_samples is invalid
-        let mut sample_count = cvars.borrow().get_value("r_msaa_samples").unwrap_or(2.0) as u32;
-        if !&[2, 4].contains(&sample_count) {
-            sample_count = 2;
-        }
+        // r_msaa_samples only accepts supported sample counts
+        let sample_count = cvars.borrow().get_value("r_msaa_samples").unwrap() as u32;
 
         let gfx_state = GraphicsState::new(device, queue, size, sample_count, vfs.clone()).unwrap();
         let ui_renderer = Rc::new(UiRenderer::new(&gfx_state, &menu.borrow()));
 
-        // TODO: factor this out
-        // implements "exec" command
-        let exec_vfs = vfs.clone();
-        let exec_console = console.clone();
-        cmds.borrow_mut().insert_or_replace(
-            "exec",
-            Box::new(move |args| {
-                match args.len() {
-                    // exec (filename): execute a script file
-                    1 => {
-                        let mut script_file = match exec_vfs.open(args[0]) {
-                            Ok(s) => s,
-                            Err(e) => {
-                                return format!("Couldn't exec {}: {:?}", args[0], e);
-                            }
-                        };
-
-                        let mut script = String::new();
-                        script_file.read_to_string(&mut script).unwrap();
-
-                        exec_console.borrow().stuff_text(script);
-                        String::new()
-                    }
-
-                    _ => format!("exec (filename): execute a script file"),
-                }
-            }),
-        ).unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("exec", cmd_exec(vfs.clone(), console.clone()))
+            .unwrap();
 
         // this will also execute config.cfg and autoexec.cfg (assuming an unmodified quake.rc)
         console.borrow().stuff_text("exec quake.rc\n");
@@ -276,15 +247,11 @@ impl Program for ClientProgram {
 
         let size: Extent2d = self.window.inner_size().into();
 
-        // TODO: warn user if r_msaa_samples is invalid
-        let mut sample_count = self
+        let sample_count = self
             .cvars
             .borrow()
             .get_value("r_msaa_samples")
-            .unwrap_or(2.0) as u32;
-        if !&[2, 4].contains(&sample_count) {
-            sample_count = 2;
-        }
+            .unwrap() as u32;
 
         // recreate attachments and rebuild pipelines if necessary
         self.gfx_state.borrow_mut().update(size, sample_count);
@@ -316,7 +283,13 @@ impl Program for ClientProgram {
     }
 
     fn shutdown(&mut self) {
-        // TODO: do cleanup things here
+        if let Err(e) = client::config::save_config(
+            &self.vfs,
+            &self.cvars.borrow(),
+            &self.input.borrow(),
+        ) {
+            log::error!("Couldn't write {}: {}", client::config::CONFIG_FILE, e);
+        }
     }
 
     fn cvars(&self) -> Ref<CvarRegistry> {
@@ -334,22 +307,80 @@ struct Opt {
     trace: bool,
 
     #[structopt(long)]
-    connect: Option<SocketAddr>,
+    connect: Option<String>,
 
     #[structopt(long)]
-    dump_demo: Option<String>,
+    netlog: Option<PathBuf>,
 
     #[structopt(long)]
     demo: Option<String>,
 
+    /// Play a demo as fast as possible and print frame time statistics.
+    #[structopt(long)]
+    timedemo: Option<String>,
+
+    /// Run without a window or graphics. Only useful with --timedemo.
+    #[structopt(long)]
+    headless: bool,
+
     #[structopt(long)]
     base_dir: Option<PathBuf>,
 }
 
+/// Runs a timedemo without a window or graphics and prints the results.
+fn headless_timedemo(base_dir: Option<PathBuf>, demo: &str) -> i32 {
+    let vfs = Rc::new(Vfs::with_base_dir(
+        base_dir.unwrap_or(common::default_base_dir()),
+    ));
+
+    let mut client = match HeadlessClient::new(vfs) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Timedemo failed: {}", e);
+            return 1;
+        }
+    };
+
+    client.exec(format!("timedemo {}", demo));
+
+    while client.connected() {
+        if let Err(e) = client.step(Duration::zero()) {
+            eprintln!("Timedemo failed: {}", e);
+            return 1;
+        }
+    }
+
+    match client.client_mut().take_timedemo_summary() {
+        Some(summary) => {
+            println!("{}", summary);
+            0
+        }
+
+        // the demo never started, so show why
+        None => {
+            for line in client.console_lines() {
+                eprintln!("{}", line);
+            }
+
+            1
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
     let opt = Opt::from_args();
 
+    if opt.headless {
+        match opt.timedemo {
+            Some(ref demo) => exit(headless_timedemo(opt.base_dir, demo)),
+            None => {
+                eprintln!("--headless requires --timedemo");
+                exit(1);
+            }
+        }
+    }
+
     let event_loop = EventLoop::new();
     let window = {
         #[cfg(target_os = "windows")]
@@ -377,56 +408,28 @@ fn main() {
     let client_program =
         futures::executor::block_on(ClientProgram::new(window, opt.base_dir, opt.trace));
 
-    // TODO: make dump_demo part of top-level binary and allow choosing file name
-    if let Some(ref demo) = opt.dump_demo {
-        let mut demfile = match client_program.vfs.open(demo) {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("error opening demofile: {}", e);
-                std::process::exit(1);
-            }
-        };
-
-        let mut demserv = match DemoServer::new(&mut demfile) {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("error starting demo server: {}", e);
-                std::process::exit(1);
-            }
-        };
-
-        let mut outfile = File::create("demodump.txt").unwrap();
-        loop {
-            match demserv.next() {
-                Some(msg) => {
-                    let mut curs = Cursor::new(msg.message());
-                    loop {
-                        match ServerCmd::deserialize(&mut curs) {
-                            Ok(Some(cmd)) => write!(&mut outfile, "{:#?}\n", cmd).unwrap(),
-                            Ok(None) => break,
-                            Err(e) => {
-                                eprintln!("error processing demo: {}", e);
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                }
-                None => break,
-            }
-        }
-
-        std::process::exit(0);
-    }
     if let Some(ref server) = opt.connect {
         client_program
             .console
             .borrow_mut()
             .stuff_text(format!("connect {}", server));
+
+        if let Some(ref log) = opt.netlog {
+            client_program
+                .console
+                .borrow_mut()
+                .stuff_text(format!("netlog {}", log.display()));
+        }
     } else if let Some(ref demo) = opt.demo {
         client_program
             .console
             .borrow_mut()
             .stuff_text(format!("playdemo {}", demo));
+    } else if let Some(ref demo) = opt.timedemo {
+        client_program
+            .console
+            .borrow_mut()
+            .stuff_text(format!("timedemo {}", demo));
     }
 
     let mut host = Host::new(client_program);
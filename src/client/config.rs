@@ -0,0 +1,62 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Saving the client configuration.
+//!
+//! Like the original, the configuration is a console script that rebinds every key and sets each
+//! archived cvar. `quake.rc` executes it at startup.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    client::input::Input,
+    common::{console::CvarRegistry, vfs::Vfs},
+};
+
+/// The name of the saved configuration in the game directory.
+pub const CONFIG_FILE: &str = "config.cfg";
+
+/// Writes the key bindings and archived cvars to `writer`.
+pub fn write_config<W>(writer: &mut W, cvars: &CvarRegistry, input: &Input) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "// generated by richter, do not modify")?;
+    input.write_bindings(writer)?;
+    cvars.write_archived(writer)?;
+    Ok(())
+}
+
+/// Saves the configuration to `config.cfg` in the game directory.
+///
+/// Returns the path of the file written.
+pub fn save_config(vfs: &Vfs, cvars: &CvarRegistry, input: &Input) -> io::Result<PathBuf> {
+    let path = vfs
+        .game_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No game directory"))?
+        .join(CONFIG_FILE);
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    write_config(&mut writer, cvars, input)?;
+    writer.flush()?;
+
+    Ok(path)
+}
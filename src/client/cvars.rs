@@ -18,15 +18,63 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::common::console::{CvarRegistry, ConsoleError};
+use crate::common::console::{ConsoleError, CvarDef, CvarKind, CvarRegistry};
 
 pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
+    cvars.define(
+        CvarDef::new(
+            "ambient_fade",
+            "100",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: None,
+            },
+        )
+        .help("How fast ambient sounds fade between leaves, in levels per second"),
+    )?;
+    cvars.define(
+        CvarDef::new(
+            "ambient_level",
+            "0.3",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: None,
+            },
+        )
+        .help("Volume of ambient water and wind sounds"),
+    )?;
+    cvars.define(
+        CvarDef::new(
+            "bgm_crossfade",
+            "2",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: None,
+            },
+        )
+        .archive()
+        .help("How long a new music track takes to fade in over the old one, in seconds"),
+    )?;
+    cvars.define(
+        CvarDef::new(
+            "bgmvolume",
+            "1",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: Some(1.0),
+            },
+        )
+        .archive()
+        .help("Volume of music"),
+    )?;
     cvars.register("cl_anglespeedkey", "1.5")?;
     cvars.register_archive("cl_backspeed", "200")?;
     cvars.register("cl_bob", "0.02")?;
     cvars.register("cl_bobcycle", "0.6")?;
     cvars.register("cl_bobup", "0.5")?;
     cvars.register_archive("_cl_color", "0")?;
+    cvars.register("cl_connect_attempts", "3")?;
+    cvars.register("cl_connect_timeout", "2.5")?;
     cvars.register("cl_crossx", "0")?;
     cvars.register("cl_crossy", "0")?;
     cvars.register_archive("cl_forwardspeed", "400")?;
@@ -40,10 +88,47 @@ pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register("cl_sidespeed", "350")?;
     cvars.register("cl_upspeed", "200")?;
     cvars.register("cl_yawspeed", "140")?;
+    cvars.register("demo_timescale", "1")?;
     cvars.register("fov", "90")?;
     cvars.register_archive("m_pitch", "0.022")?;
     cvars.register_archive("m_yaw", "0.022")?;
     cvars.register_archive("sensitivity", "3")?;
+    cvars.define(
+        CvarDef::new(
+            "snd_maxchannels",
+            "128",
+            CvarKind::Int {
+                min: Some(1),
+                max: Some(128),
+            },
+        )
+        .archive()
+        .help("Maximum number of entity sounds that can play at once"),
+    )?;
+    cvars.define(
+        CvarDef::new(
+            "snd_reverb",
+            "0.3",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: Some(1.0),
+            },
+        )
+        .archive()
+        .help("Amount of echo added to sound inside water, slime or lava"),
+    )?;
+    cvars.define(
+        CvarDef::new(
+            "snd_waterfx",
+            "1",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: Some(1.0),
+            },
+        )
+        .archive()
+        .help("How muffled sound is inside water, slime or lava, or 0 to disable"),
+    )?;
     cvars.register("v_idlescale", "0")?;
     cvars.register("v_ipitch_cycle", "1")?;
     cvars.register("v_ipitch_level", "0.3")?;
@@ -54,11 +139,25 @@ pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register("v_kickpitch", "0.6")?;
     cvars.register("v_kickroll", "0.6")?;
     cvars.register("v_kicktime", "0.5")?;
+    cvars.define(
+        CvarDef::new(
+            "volume",
+            "0.7",
+            CvarKind::Float {
+                min: Some(0.0),
+                max: Some(1.0),
+            },
+        )
+        .archive()
+        .help("Volume of sound effects"),
+    )?;
 
     // some server cvars are needed by the client, but if the server is running
     // in the same process they will have been set already, so we can ignore
-    // the duplicate cvar error
+    // the duplicate cvar error. if the server registers them afterward, they
+    // become notify cvars then.
     let _ = cvars.register("sv_gravity", "800");
+    let _ = cvars.register("net_messagetimeout", "300");
 
     Ok(())
 }
@@ -1,14 +1,27 @@
-use std::{io, ops::Range};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Cursor, Read, Write},
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
 
 use crate::common::{
-    net::{self, NetError},
+    engine,
+    net::{
+        self,
+        fitz::{self, Downgrade},
+        msglog::cmd_name,
+        NetError, ServerCmd,
+    },
     util::read_f32_3,
-    vfs::VirtualFile,
 };
 
 use arrayvec::ArrayVec;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3};
+use chrono::Duration;
 use io::BufReader;
 use thiserror::Error;
 
@@ -21,6 +34,10 @@ pub enum DemoServerError {
     NoSuchCdTrack(i32),
     #[error("Message size ({0}) exceeds maximum allowed size {}", net::MAX_MESSAGE)]
     MessageTooLong(u32),
+    #[error("Unknown protocol: {0}")]
+    UnknownProtocol(String),
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedProtocol(i32),
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
     #[error("Network error: {0}")]
@@ -30,12 +47,16 @@ pub enum DemoServerError {
 struct DemoMessage {
     view_angles: Vector3<Deg<f32>>,
     msg_range: Range<usize>,
+
+    // position of this message on the demo timeline
+    time: Duration,
 }
 
 /// A view of a server message from a demo.
 pub struct DemoMessageView<'a> {
     view_angles: Vector3<Deg<f32>>,
     message: &'a [u8],
+    time: Duration,
 }
 
 impl<'a> DemoMessageView<'a> {
@@ -45,12 +66,100 @@ impl<'a> DemoMessageView<'a> {
     }
 
     /// Returns the server message for this demo message as a slice of bytes.
-    pub fn message(&self) -> &[u8] {
+    pub fn message(&self) -> &'a [u8] {
         self.message
     }
+
+    /// Returns the position of this message on the demo timeline.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+}
+
+/// A network protocol that demo messages can be encoded with.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DemoProtocol {
+    /// The original Quake protocol (version 15).
+    NetQuake,
+
+    /// The FitzQuake protocol (version 666), which raises several of the original's limits.
+    FitzQuake,
+}
+
+impl DemoProtocol {
+    /// Returns the protocol with the given version number, if it's supported.
+    pub fn from_version(version: i32) -> Option<DemoProtocol> {
+        match version {
+            v if v == net::PROTOCOL_VERSION as i32 => Some(DemoProtocol::NetQuake),
+            fitz::PROTOCOL_VERSION => Some(DemoProtocol::FitzQuake),
+            _ => None,
+        }
+    }
+
+    /// Returns the version number sent in `ServerInfo` for this protocol.
+    pub fn version(&self) -> i32 {
+        match self {
+            DemoProtocol::NetQuake => net::PROTOCOL_VERSION as i32,
+            DemoProtocol::FitzQuake => fitz::PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl FromStr for DemoProtocol {
+    type Err = DemoServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "15" | "netquake" => Ok(DemoProtocol::NetQuake),
+            "666" | "fitzquake" => Ok(DemoProtocol::FitzQuake),
+            _ => Err(DemoServerError::UnknownProtocol(s.to_owned())),
+        }
+    }
+}
+
+/// A command that couldn't be carried over exactly when converting a demo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionIssue {
+    /// The index of the demo message containing the command.
+    pub message_id: usize,
+
+    /// The name of the command.
+    pub cmd: String,
+
+    /// What couldn't be represented in the target protocol.
+    pub reason: String,
+
+    /// Whether the command was left out of the converted demo entirely.
+    pub dropped: bool,
+}
+
+impl fmt::Display for ConversionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "message {}: {} {}: {}",
+            self.message_id,
+            if self.dropped {
+                "dropped"
+            } else {
+                "downgraded"
+            },
+            self.cmd,
+            self.reason
+        )
+    }
 }
 
 /// A server that yields commands from a demo file.
+///
+/// Demos are indexed on load so that playback can be paused and seeked. Positions are given on
+/// the demo timeline, which is the server time elapsed since the start of the demo summed across
+/// level changes.
+///
+/// Seeking restarts playback from a keyframe. There is one at the start of each level, and one
+/// every [`KEYFRAME_INTERVAL_SECS`] seconds after the level's sign-on. The latter hold a snapshot
+/// of the lasting effects of everything played since the sign-on, so a seek only has to replay
+/// the sign-on, the snapshot and the messages between the keyframe and its target.
 pub struct DemoServer {
     track_override: Option<u32>,
 
@@ -61,11 +170,22 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    keyframes: Vec<Keyframe>,
+
+    // keyframe to skip ahead to once a rewind has replayed its level's sign-on
+    resume: Option<usize>,
+
+    paused: bool,
+    seek_request: Option<Duration>,
 }
 
 impl DemoServer {
     /// Construct a new `DemoServer` from the specified demo file.
-    pub fn new(file: &mut VirtualFile) -> Result<DemoServer, DemoServerError> {
+    pub fn new<R>(file: &mut R) -> Result<DemoServer, DemoServerError>
+    where
+        R: Read,
+    {
         let mut dem_reader = BufReader::new(file);
 
         let mut buf = ArrayVec::<u8, 3>::new();
@@ -127,14 +247,21 @@ impl DemoServer {
             messages.push(DemoMessage {
                 view_angles,
                 msg_range: msg_start..msg_end,
+                time: Duration::zero(),
             });
         }
 
+        let keyframes = index_messages(&mut messages, &message_data);
+
         Ok(DemoServer {
             track_override,
             message_id: 0,
             messages,
             message_data,
+            keyframes,
+            resume: None,
+            paused: false,
+            seek_request: None,
         })
     }
 
@@ -142,6 +269,23 @@ impl DemoServer {
     ///
     /// If this returns `None`, the demo is complete.
     pub fn next(&mut self) -> Option<DemoMessageView> {
+        if let Some(kf_id) = self.resume {
+            let kf = &self.keyframes[kf_id];
+            if self.message_id >= kf.signon_end {
+                self.resume = None;
+                self.message_id = kf.message_id;
+
+                if !kf.snapshot.is_empty() {
+                    let prev = &self.messages[kf.message_id - 1];
+                    return Some(DemoMessageView {
+                        view_angles: prev.view_angles,
+                        message: &kf.snapshot,
+                        time: prev.time,
+                    });
+                }
+            }
+        }
+
         if self.message_id >= self.messages.len() {
             return None;
         }
@@ -149,10 +293,25 @@ impl DemoServer {
         let msg = &self.messages[self.message_id];
         self.message_id += 1;
 
-        Some(DemoMessageView {
+        Some(self.view(msg))
+    }
+
+    fn view<'a>(&'a self, msg: &DemoMessage) -> DemoMessageView<'a> {
+        DemoMessageView {
             view_angles: msg.view_angles,
             message: &self.message_data[msg.msg_range.clone()],
-        })
+            time: msg.time,
+        }
+    }
+
+    /// Returns an iterator over every message in the demo, regardless of playback position.
+    pub fn messages(&self) -> impl Iterator<Item = DemoMessageView> {
+        self.messages.iter().map(move |msg| self.view(msg))
+    }
+
+    /// Returns the ids of the messages from which playback can be restarted.
+    pub fn keyframes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.keyframes.iter().map(|kf| kf.message_id)
     }
 
     /// Returns the currently playing demo's music track override, if any.
@@ -163,4 +322,1057 @@ impl DemoServer {
     pub fn track_override(&self) -> Option<u32> {
         self.track_override
     }
+
+    /// Returns the id of the next message to be played.
+    pub fn message_id(&self) -> usize {
+        self.message_id
+    }
+
+    /// Returns the timeline position of the most recently played message.
+    pub fn time(&self) -> Duration {
+        match self.message_id {
+            0 => Duration::zero(),
+            id => self.messages[id - 1].time,
+        }
+    }
+
+    /// Returns the length of the demo timeline.
+    pub fn duration(&self) -> Duration {
+        self.messages
+            .last()
+            .map(|msg| msg.time)
+            .unwrap_or_else(Duration::zero)
+    }
+
+    /// Returns whether playback is paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes playback.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Asks the client to seek to `time` on its next frame.
+    ///
+    /// The time is clamped to the length of the demo.
+    pub fn request_seek(&mut self, time: Duration) {
+        let time = if time < Duration::zero() {
+            Duration::zero()
+        } else if time > self.duration() {
+            self.duration()
+        } else {
+            time
+        };
+
+        self.seek_request = Some(time);
+    }
+
+    /// Returns the target of a pending seek, if there is one.
+    ///
+    /// This is the position playback will resume from once the seek is carried out.
+    pub fn seek_request(&self) -> Option<Duration> {
+        self.seek_request
+    }
+
+    /// Removes and returns the target of a pending seek, if there is one.
+    pub fn take_seek_request(&mut self) -> Option<Duration> {
+        self.seek_request.take()
+    }
+
+    /// Rewinds the demo to the last keyframe before `time`.
+    ///
+    /// Returns the id of the first message past `time`. Playing messages from a fresh client
+    /// state until [`message_id`](DemoServer::message_id) reaches that id reconstructs the state
+    /// of the game at `time`. If the keyframe has a snapshot, the level's sign-on is played
+    /// first, followed by the snapshot and the messages after the keyframe.
+    pub fn rewind(&mut self, time: Duration) -> usize {
+        let target_id = self
+            .messages
+            .iter()
+            .position(|msg| msg.time > time)
+            .unwrap_or(self.messages.len());
+
+        self.resume = self
+            .keyframes
+            .iter()
+            .rposition(|kf| kf.message_id < target_id);
+        self.message_id = self
+            .resume
+            .map(|kf_id| self.keyframes[kf_id].level_start)
+            .unwrap_or(0);
+
+        target_id
+    }
+}
+
+impl DemoServer {
+    /// Writes the portion of the demo between `start` and `end` to a new demo.
+    ///
+    /// The new demo begins with the sign-on of the level being played at `start`. Anything that
+    /// happened between the sign-on and `start` which still affects the game (light styles,
+    /// scores, stats and so on) is condensed into a few messages so the extract plays back
+    /// correctly from its first frame.
+    pub fn extract<W>(
+        &self,
+        start: Duration,
+        end: Duration,
+        writer: W,
+    ) -> Result<W, DemoServerError>
+    where
+        W: Write,
+    {
+        let first_id = self
+            .messages
+            .iter()
+            .position(|msg| msg.time >= start)
+            .unwrap_or(self.messages.len());
+        let end_id = self
+            .messages
+            .iter()
+            .position(|msg| msg.time > end)
+            .unwrap_or(self.messages.len())
+            .max(first_id);
+        let keyframe_id = self
+            .keyframes
+            .iter()
+            .rev()
+            .find(|kf| kf.message_id <= first_id)
+            .map(|kf| kf.level_start)
+            .unwrap_or(0);
+
+        let mut recorder = DemoRecorder::new(writer, self.track_override)?;
+
+        // keep everything up to the first entity update, which marks the end of the sign-on
+        let mut id = keyframe_id;
+        while id < first_id {
+            let msg = self.view(&self.messages[id]);
+            let mut reader = Cursor::new(msg.message());
+            let mut signon = true;
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
+                if let ServerCmd::FastUpdate(_) = cmd {
+                    signon = false;
+                    break;
+                }
+            }
+
+            if !signon {
+                break;
+            }
+
+            recorder.write_message(msg.view_angles(), msg.message())?;
+            id += 1;
+        }
+
+        // condense the rest of the lead-up into its lasting effects
+        if id < first_id {
+            let mut catch_up = CatchUp::default();
+            for msg in self.messages[id..first_id].iter() {
+                let mut reader = Cursor::new(&self.message_data[msg.msg_range.clone()]);
+                while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
+                    catch_up.apply(cmd);
+                }
+            }
+
+            let view_angles = self.messages[first_id - 1].view_angles;
+            let mut msg = Vec::new();
+            for cmd in catch_up.cmds {
+                let mut cmd_data = Vec::new();
+                cmd.serialize(&mut cmd_data)?;
+
+                if msg.len() + cmd_data.len() > net::MAX_MESSAGE {
+                    recorder.write_message(view_angles, &msg)?;
+                    msg.clear();
+                }
+
+                msg.extend_from_slice(&cmd_data);
+            }
+
+            if !msg.is_empty() {
+                recorder.write_message(view_angles, &msg)?;
+            }
+        }
+
+        for msg in self.messages[first_id..end_id].iter() {
+            let msg = self.view(msg);
+            recorder.write_message(msg.view_angles(), msg.message())?;
+        }
+
+        recorder.finish()
+    }
+}
+
+impl DemoServer {
+    /// Re-encodes the demo with the specified network protocol.
+    ///
+    /// Each level is read with the protocol named by its `ServerInfo`. Commands that have no
+    /// exact equivalent in the target protocol are downgraded where possible and dropped
+    /// otherwise, and each one is reported in the returned list of issues. Converted messages
+    /// which no longer fit in a single demo message are split between commands.
+    pub fn convert<W>(
+        &self,
+        protocol: DemoProtocol,
+        writer: W,
+    ) -> Result<(W, Vec<ConversionIssue>), DemoServerError>
+    where
+        W: Write,
+    {
+        let mut recorder = DemoRecorder::new(writer, self.track_override)?;
+        let mut issues = Vec::new();
+
+        // until a ServerInfo says otherwise, assume the original protocol
+        let mut source = DemoProtocol::NetQuake;
+
+        for (message_id, msg) in self.messages().enumerate() {
+            let data = msg.message();
+            let mut reader = Cursor::new(data);
+            let mut converted = Vec::new();
+
+            loop {
+                let start = reader.position() as usize;
+                let mut passthrough = source == protocol;
+
+                let cmd = match source {
+                    DemoProtocol::NetQuake => ServerCmd::deserialize(&mut reader)?,
+                    DemoProtocol::FitzQuake => match fitz::read_downgraded(&mut reader)? {
+                        Some(Downgrade::Cmd { cmd, lost }) => {
+                            if !passthrough {
+                                let name = cmd_name(&format!("{:?}", cmd)).to_owned();
+                                issues.extend(lost.into_iter().map(|reason| ConversionIssue {
+                                    message_id,
+                                    cmd: name.clone(),
+                                    reason,
+                                    dropped: false,
+                                }));
+                            }
+
+                            Some(cmd)
+                        }
+
+                        Some(Downgrade::Unrepresentable { name, reason }) => {
+                            if passthrough {
+                                converted.push(data[start..reader.position() as usize].to_vec());
+                            } else {
+                                issues.push(ConversionIssue {
+                                    message_id,
+                                    cmd: name.to_owned(),
+                                    reason,
+                                    dropped: true,
+                                });
+                            }
+
+                            continue;
+                        }
+
+                        None => None,
+                    },
+                };
+
+                let cmd = match cmd {
+                    Some(c) => c,
+                    None => break,
+                };
+
+                if let ServerCmd::ServerInfo {
+                    protocol_version, ..
+                } = cmd
+                {
+                    source = DemoProtocol::from_version(protocol_version)
+                        .ok_or(DemoServerError::UnsupportedProtocol(protocol_version))?;
+                    passthrough = source == protocol;
+                }
+
+                if passthrough {
+                    converted.push(data[start..reader.position() as usize].to_vec());
+                    continue;
+                }
+
+                let cmd = match cmd {
+                    ServerCmd::ServerInfo {
+                        max_clients,
+                        game_type,
+                        message,
+                        mut model_precache,
+                        mut sound_precache,
+                        ..
+                    } => {
+                        // precache indices are sent as bytes, and index 0 is reserved
+                        if protocol == DemoProtocol::NetQuake {
+                            for (what, precache) in &mut [
+                                ("model", &mut model_precache),
+                                ("sound", &mut sound_precache),
+                            ] {
+                                if precache.len() > 255 {
+                                    issues.push(ConversionIssue {
+                                        message_id,
+                                        cmd: "ServerInfo".to_owned(),
+                                        reason: format!(
+                                            "{} {} precaches exceed the limit of 255",
+                                            precache.len() - 255,
+                                            what
+                                        ),
+                                        dropped: false,
+                                    });
+                                    precache.truncate(255);
+                                }
+                            }
+                        }
+
+                        ServerCmd::ServerInfo {
+                            protocol_version: protocol.version(),
+                            max_clients,
+                            game_type,
+                            message,
+                            model_precache,
+                            sound_precache,
+                        }
+                    }
+
+                    ServerCmd::Version { .. } => ServerCmd::Version {
+                        version: protocol.version(),
+                    },
+
+                    cmd => cmd,
+                };
+
+                let mut cmd_data = Vec::new();
+                cmd.serialize(&mut cmd_data)?;
+                converted.push(cmd_data);
+            }
+
+            let mut out = Vec::new();
+            for cmd_data in converted {
+                if !out.is_empty() && out.len() + cmd_data.len() > net::MAX_MESSAGE {
+                    recorder.write_message(msg.view_angles(), &out)?;
+                    out.clear();
+                }
+
+                out.extend_from_slice(&cmd_data);
+            }
+            recorder.write_message(msg.view_angles(), &out)?;
+        }
+
+        Ok((recorder.finish()?, issues))
+    }
+}
+
+/// The number of seconds of demo time between snapshot keyframes.
+pub const KEYFRAME_INTERVAL_SECS: i64 = 10;
+
+/// A point in a demo from which playback can be restarted.
+struct Keyframe {
+    // id of the message with the ServerInfo that starts the level
+    level_start: usize,
+
+    // id of the message with the level's first entity update, which ends the sign-on
+    signon_end: usize,
+
+    // id of the first message to play after the snapshot
+    message_id: usize,
+
+    // the condensed commands from signon_end up to message_id, empty at the start of a level
+    snapshot: Vec<u8>,
+}
+
+/// The commands skipped over by a demo extract that still affect the game after they're sent.
+#[derive(Default)]
+struct CatchUp {
+    cmds: Vec<ServerCmd>,
+}
+
+impl CatchUp {
+    fn apply(&mut self, cmd: ServerCmd) {
+        use ServerCmd::*;
+
+        // drop the commands this one overrides
+        match cmd {
+            Time { .. } => self.cmds.retain(|c| !matches!(c, Time { .. })),
+            LightStyle { id, .. } => self
+                .cmds
+                .retain(|c| !matches!(c, LightStyle { id: i, .. } if *i == id)),
+            UpdateName { player_id, .. } => self
+                .cmds
+                .retain(|c| !matches!(c, UpdateName { player_id: p, .. } if *p == player_id)),
+            UpdateFrags { player_id, .. } => self
+                .cmds
+                .retain(|c| !matches!(c, UpdateFrags { player_id: p, .. } if *p == player_id)),
+            UpdateColors { player_id, .. } => self
+                .cmds
+                .retain(|c| !matches!(c, UpdateColors { player_id: p, .. } if *p == player_id)),
+            UpdateStat { stat, .. } => self.cmds.retain(|c| match c {
+                UpdateStat { stat: s, .. } => *s != stat,
+                KilledMonster => stat != net::ClientStat::KilledMonsters,
+                FoundSecret => stat != net::ClientStat::FoundSecrets,
+                _ => true,
+            }),
+            SetView { .. } => self.cmds.retain(|c| !matches!(c, SetView { .. })),
+            SetAngle { .. } => self.cmds.retain(|c| !matches!(c, SetAngle { .. })),
+            CdTrack { .. } => self.cmds.retain(|c| !matches!(c, CdTrack { .. })),
+            Intermission | Finale { .. } | Cutscene { .. } => self
+                .cmds
+                .retain(|c| !matches!(c, Intermission | Finale { .. } | Cutscene { .. })),
+
+            // these accumulate
+            KilledMonster
+            | FoundSecret
+            | SpawnStatic { .. }
+            | SpawnBaseline { .. }
+            | SpawnStaticSound { .. } => (),
+
+            // everything else is transient or superseded by the next entity update
+            _ => return,
+        }
+
+        self.cmds.push(cmd);
+    }
+
+    fn to_message(&self) -> Result<Vec<u8>, NetError> {
+        let mut msg = Vec::new();
+        for cmd in self.cmds.iter() {
+            cmd.serialize(&mut msg)?;
+        }
+
+        Ok(msg)
+    }
+}
+
+/// Computes the timeline position of each message and returns the keyframes.
+fn index_messages(messages: &mut [DemoMessage], message_data: &[u8]) -> Vec<Keyframe> {
+    let mut keyframes: Vec<Keyframe> = Vec::new();
+    let mut elapsed = Duration::zero();
+    let mut last_time = None;
+
+    // start of the current level and the end of its sign-on, once that's been reached
+    let mut level: Option<(usize, Option<usize>)> = None;
+    let mut catch_up = CatchUp::default();
+    let mut last_keyframe = Duration::zero();
+
+    for (id, msg) in messages.iter_mut().enumerate() {
+        if let Some((level_start, Some(signon_end))) = level {
+            if elapsed - last_keyframe >= Duration::seconds(KEYFRAME_INTERVAL_SECS) {
+                // commands that fail to serialize would fail to play, so just skip the snapshot
+                if let Ok(snapshot) = catch_up.to_message() {
+                    keyframes.push(Keyframe {
+                        level_start,
+                        signon_end,
+                        message_id: id,
+                        snapshot,
+                    });
+                    last_keyframe = elapsed;
+                }
+            }
+        }
+
+        let mut reader = Cursor::new(&message_data[msg.msg_range.clone()]);
+        let mut cmds = Vec::new();
+
+        loop {
+            match ServerCmd::deserialize(&mut reader) {
+                Ok(Some(ServerCmd::Time { time })) => {
+                    let t = engine::duration_from_f32(time);
+
+                    // server time starts over on each level, so only count forward progress
+                    if let Some(last) = last_time {
+                        if t > last {
+                            elapsed = elapsed + (t - last);
+                        }
+                    }
+
+                    last_time = Some(t);
+                    cmds.push(ServerCmd::Time { time });
+                }
+
+                Ok(Some(ServerCmd::ServerInfo { .. })) => {
+                    keyframes.push(Keyframe {
+                        level_start: id,
+                        signon_end: id,
+                        message_id: id,
+                        snapshot: Vec::new(),
+                    });
+                    last_time = None;
+
+                    // anything before this belongs to the previous level
+                    level = Some((id, None));
+                    catch_up = CatchUp::default();
+                    cmds.clear();
+                    last_keyframe = elapsed;
+                }
+
+                Ok(Some(cmd)) => cmds.push(cmd),
+
+                Ok(None) => break,
+
+                // invalid messages are reported when they're played
+                Err(_) => break,
+            }
+        }
+
+        if let Some((level_start, ref mut signon_end @ None)) = level {
+            if id > level_start && cmds.iter().any(|c| matches!(c, ServerCmd::FastUpdate(_))) {
+                *signon_end = Some(id);
+            }
+        }
+
+        if let Some((_, Some(_))) = level {
+            for cmd in cmds {
+                catch_up.apply(cmd);
+            }
+        }
+
+        msg.time = elapsed;
+    }
+
+    keyframes
+}
+
+/// Records server messages to a demo file.
+///
+/// The output can be played back by [`DemoServer`].
+pub struct DemoRecorder<W>
+where
+    W: Write,
+{
+    writer: W,
+
+    // view angles written with the most recent message
+    view_angles: Vector3<Deg<f32>>,
+}
+
+impl DemoRecorder<BufWriter<File>> {
+    /// Creates a new demo file at the specified path.
+    pub fn create<P>(
+        path: P,
+        track_override: Option<u32>,
+    ) -> Result<DemoRecorder<BufWriter<File>>, DemoServerError>
+    where
+        P: AsRef<Path>,
+    {
+        DemoRecorder::new(BufWriter::new(File::create(path)?), track_override)
+    }
+}
+
+impl<W> DemoRecorder<W>
+where
+    W: Write,
+{
+    /// Begins a demo by writing the CD track header to `writer`.
+    ///
+    /// If `track_override` is `None`, the demo plays whatever tracks the recorded `CdTrack`
+    /// commands specify.
+    pub fn new(
+        mut writer: W,
+        track_override: Option<u32>,
+    ) -> Result<DemoRecorder<W>, DemoServerError> {
+        match track_override {
+            // the header is at most two digits long
+            Some(t) if t > 99 => Err(DemoServerError::InvalidCdTrack)?,
+            Some(t) => writeln!(writer, "{}", t)?,
+            None => writeln!(writer, "-1")?,
+        }
+
+        Ok(DemoRecorder {
+            writer,
+            view_angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+        })
+    }
+
+    /// Appends a server message to the demo along with the client's view angles.
+    pub fn write_message(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+    ) -> Result<(), DemoServerError> {
+        if message.len() > net::MAX_MESSAGE {
+            Err(DemoServerError::MessageTooLong(message.len() as u32))?;
+        }
+
+        self.writer
+            .write_u32::<LittleEndian>(message.len() as u32)?;
+        for angle in &[view_angles.x, view_angles.y, view_angles.z] {
+            self.writer.write_f32::<LittleEndian>(angle.0)?;
+        }
+        self.writer.write_all(message)?;
+        self.view_angles = view_angles;
+
+        Ok(())
+    }
+
+    /// Ends the demo with a `Disconnect` command and flushes it.
+    ///
+    /// Returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, DemoServerError> {
+        let mut msg = Vec::new();
+        ServerCmd::Disconnect.serialize(&mut msg)?;
+        self.write_message(self.view_angles, &msg)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The maximum number of demos in the demo loop.
+pub const MAX_DEMOS: usize = 8;
+
+/// The demos played in a loop while the client is idle, as set by `startdemos`.
+///
+/// Like the original attract mode, the loop keeps its position while it is stopped, so resuming
+/// it continues with the demo after the last one played.
+#[derive(Debug, Default)]
+pub struct DemoPlaylist {
+    demos: Vec<String>,
+
+    // index of the next demo to play
+    position: usize,
+
+    // whether the next demo should be played when the client is idle
+    running: bool,
+}
+
+impl DemoPlaylist {
+    pub fn new() -> DemoPlaylist {
+        DemoPlaylist::default()
+    }
+
+    /// Replaces the demos in the loop, keeping at most `MAX_DEMOS` of them.
+    ///
+    /// The loop starts over from the first demo and runs only if `start` is true.
+    pub fn set_demos<S>(&mut self, demos: &[S], start: bool)
+    where
+        S: AsRef<str>,
+    {
+        self.demos = demos
+            .iter()
+            .take(MAX_DEMOS)
+            .map(|d| d.as_ref().to_owned())
+            .collect();
+        self.position = 0;
+        self.running = start && !self.demos.is_empty();
+    }
+
+    pub fn demos(&self) -> &[String] {
+        &self.demos
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts the loop again from where it stopped.
+    ///
+    /// Returns `false` if there are no demos to play.
+    pub fn resume(&mut self) -> bool {
+        self.running = !self.demos.is_empty();
+        self.running
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Returns the next demo to play and advances the loop, wrapping back to the first demo
+    /// after the last one.
+    ///
+    /// Returns `None` if the loop is stopped.
+    pub fn next_demo(&mut self) -> Option<String> {
+        if !self.running {
+            return None;
+        }
+
+        let demo = self.demos[self.position].clone();
+        self.position = (self.position + 1) % self.demos.len();
+        Some(demo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_demo_recorder_round_trip() {
+        let messages: Vec<(Vector3<Deg<f32>>, Vec<u8>)> = vec![
+            (Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)), vec![1, 2, 3]),
+            (Vector3::new(Deg(-10.0), Deg(45.5), Deg(2.0)), vec![]),
+            (
+                Vector3::new(Deg(30.0), Deg(180.0), Deg(-2.0)),
+                vec![0xff; 100],
+            ),
+        ];
+
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        for (angles, msg) in messages.iter() {
+            recorder.write_message(*angles, msg).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+
+        let mut demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+        assert_eq!(demo_srv.track_override(), None);
+
+        for (angles, msg) in messages.iter() {
+            let view = demo_srv.next().unwrap();
+            assert_eq!(view.view_angles(), *angles);
+            assert_eq!(view.message(), msg.as_slice());
+        }
+
+        // the demo ends with a disconnect at the last view angles
+        let view = demo_srv.next().unwrap();
+        assert_eq!(view.view_angles(), messages[2].0);
+        let mut reader = Cursor::new(view.message());
+        assert!(matches!(
+            ServerCmd::deserialize(&mut reader).unwrap(),
+            Some(ServerCmd::Disconnect)
+        ));
+
+        assert!(demo_srv.next().is_none());
+    }
+
+    fn server_info_msg(time: f32) -> Vec<u8> {
+        let mut msg = Vec::new();
+        ServerCmd::ServerInfo {
+            protocol_version: net::PROTOCOL_VERSION as i32,
+            max_clients: 1,
+            game_type: net::GameType::CoOp,
+            message: "test".to_owned(),
+            model_precache: vec!["maps/test.bsp".to_owned()],
+            sound_precache: Vec::new(),
+        }
+        .serialize(&mut msg)
+        .unwrap();
+        ServerCmd::Time { time }.serialize(&mut msg).unwrap();
+        msg
+    }
+
+    fn time_msg(time: f32) -> Vec<u8> {
+        let mut msg = Vec::new();
+        ServerCmd::Time { time }.serialize(&mut msg).unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_demo_server_seek() {
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+
+        // two levels, each starting over at server time 1
+        let msgs = vec![
+            server_info_msg(1.0),
+            time_msg(2.0),
+            time_msg(3.0),
+            server_info_msg(1.0),
+            time_msg(1.5),
+            time_msg(2.5),
+        ];
+        for msg in msgs.iter() {
+            recorder.write_message(angles, msg).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+
+        let mut demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+        assert_eq!(demo_srv.keyframes().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(demo_srv.duration(), Duration::milliseconds(3500));
+
+        // within the first level
+        assert_eq!(demo_srv.rewind(Duration::milliseconds(1500)), 2);
+        assert_eq!(demo_srv.message_id(), 0);
+
+        // within the second level
+        assert_eq!(demo_srv.rewind(Duration::seconds(3)), 5);
+        assert_eq!(demo_srv.message_id(), 3);
+        demo_srv.next().unwrap();
+        assert_eq!(demo_srv.time(), Duration::seconds(2));
+
+        // past the end
+        assert_eq!(demo_srv.rewind(Duration::seconds(10)), 7);
+        assert_eq!(demo_srv.message_id(), 3);
+
+        demo_srv.request_seek(Duration::seconds(-1));
+        assert_eq!(demo_srv.take_seek_request(), Some(Duration::zero()));
+        demo_srv.request_seek(Duration::seconds(10));
+        assert_eq!(demo_srv.take_seek_request(), Some(demo_srv.duration()));
+        assert_eq!(demo_srv.take_seek_request(), None);
+    }
+
+    fn light_style_msg(time: f32, value: &str) -> Vec<u8> {
+        let mut msg = time_msg(time);
+        ServerCmd::LightStyle {
+            id: 0,
+            value: value.to_owned(),
+        }
+        .serialize(&mut msg)
+        .unwrap();
+        ServerCmd::KilledMonster.serialize(&mut msg).unwrap();
+        msg
+    }
+
+    fn read_cmds(msg: &[u8]) -> Vec<ServerCmd> {
+        let mut reader = Cursor::new(msg);
+        let mut cmds = Vec::new();
+        while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+            cmds.push(cmd);
+        }
+        cmds
+    }
+
+    fn entity_update_msg() -> Vec<u8> {
+        let mut msg = Vec::new();
+        ServerCmd::FastUpdate(net::EntityUpdate {
+            ent_id: 1,
+            model_id: None,
+            frame_id: None,
+            colormap: None,
+            skin_id: None,
+            effects: None,
+            origin_x: None,
+            pitch: None,
+            origin_y: None,
+            yaw: None,
+            origin_z: None,
+            roll: None,
+            no_lerp: false,
+        })
+        .serialize(&mut msg)
+        .unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_demo_server_seek_snapshot() {
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+
+        // a level lasting 25 seconds past its sign-on
+        let mut msgs = vec![server_info_msg(1.0), entity_update_msg()];
+        for t in 2..=26 {
+            msgs.push(light_style_msg(t as f32, &t.to_string()));
+        }
+        for msg in msgs.iter() {
+            recorder.write_message(angles, msg).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+
+        let mut demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+        assert_eq!(demo_srv.keyframes().collect::<Vec<_>>(), vec![0, 12, 22]);
+
+        // the level's sign-on is replayed first
+        assert_eq!(demo_srv.rewind(Duration::milliseconds(23500)), 25);
+        assert_eq!(demo_srv.message_id(), 0);
+        assert_eq!(demo_srv.next().unwrap().message(), msgs[0].as_slice());
+
+        // then the snapshot of everything since the sign-on
+        let snapshot = demo_srv.next().unwrap();
+        assert_eq!(snapshot.time(), Duration::seconds(20));
+        let cmds = read_cmds(snapshot.message());
+        assert_eq!(demo_srv.message_id(), 22);
+        assert_eq!(
+            cmds.iter()
+                .filter(|c| matches!(c, ServerCmd::KilledMonster))
+                .count(),
+            20
+        );
+        assert!(cmds
+            .iter()
+            .any(|c| matches!(c, ServerCmd::Time { time } if *time == 21.0)));
+        assert!(cmds
+            .iter()
+            .any(|c| matches!(c, ServerCmd::LightStyle { value, .. } if value == "21")));
+
+        // then the messages from the keyframe on
+        assert_eq!(demo_srv.next().unwrap().message(), msgs[22].as_slice());
+
+        // seeking before the first snapshot starts over from the beginning of the level
+        assert_eq!(demo_srv.rewind(Duration::seconds(5)), 7);
+        assert_eq!(demo_srv.next().unwrap().message(), msgs[0].as_slice());
+        assert_eq!(demo_srv.next().unwrap().message(), msgs[1].as_slice());
+    }
+
+    #[test]
+    fn test_demo_server_extract() {
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        let update = entity_update_msg();
+
+        let msgs = vec![
+            server_info_msg(1.0),
+            update.clone(),
+            light_style_msg(2.0, "a"),
+            light_style_msg(3.0, "b"),
+            time_msg(4.0),
+            time_msg(5.0),
+        ];
+        for msg in msgs.iter() {
+            recorder.write_message(angles, msg).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+        let demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+
+        let extract = demo_srv
+            .extract(Duration::seconds(3), Duration::seconds(3), Vec::new())
+            .unwrap();
+        let extract_srv = DemoServer::new(&mut Cursor::new(extract)).unwrap();
+        let extract_msgs: Vec<_> = extract_srv.messages().map(|m| m.message()).collect();
+
+        // sign-on, catch-up, the extracted message and the final disconnect
+        assert_eq!(extract_msgs.len(), 4);
+        assert_eq!(extract_msgs[0], msgs[0].as_slice());
+        assert_eq!(extract_msgs[2], msgs[4].as_slice());
+
+        // the entity update is dropped and only the latest light style survives
+        let catch_up = read_cmds(extract_msgs[1]);
+        assert_eq!(catch_up.len(), 4);
+        assert!(matches!(catch_up[0], ServerCmd::KilledMonster));
+        assert!(matches!(catch_up[1], ServerCmd::Time { time } if time == 3.0));
+        assert!(matches!(catch_up[2], ServerCmd::LightStyle { ref value, .. } if value == "b"));
+        assert!(matches!(catch_up[3], ServerCmd::KilledMonster));
+    }
+
+    #[test]
+    fn test_demo_server_convert() {
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+
+        let mut server_info = Vec::new();
+        ServerCmd::ServerInfo {
+            protocol_version: fitz::PROTOCOL_VERSION,
+            max_clients: 1,
+            game_type: net::GameType::CoOp,
+            message: "test".to_owned(),
+            model_precache: vec!["maps/test.bsp".to_owned()],
+            sound_precache: Vec::new(),
+        }
+        .serialize(&mut server_info)
+        .unwrap();
+
+        // fog, a bonus flash and an update to entity 1 with frame 256
+        let mut frame = vec![41, 0, 0, 0, 0, 0, 0];
+        frame.push(40);
+        frame.extend_from_slice(&[0x80 | 0x41, 0x80, 0x02, 1, 0, 1]);
+
+        let msgs = vec![server_info, time_msg(1.0), frame];
+        for msg in msgs.iter() {
+            recorder.write_message(angles, msg).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+        let demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+
+        let (netquake, issues) = demo_srv
+            .convert(DemoProtocol::NetQuake, Vec::new())
+            .unwrap();
+        assert_eq!(
+            issues
+                .iter()
+                .map(|i| (i.message_id, i.cmd.as_str(), i.dropped))
+                .collect::<Vec<_>>(),
+            vec![(2, "Fog", true), (2, "FastUpdate", false)]
+        );
+
+        let netquake_srv = DemoServer::new(&mut Cursor::new(netquake)).unwrap();
+        let netquake_msgs: Vec<_> = netquake_srv.messages().map(|m| m.message()).collect();
+        assert!(matches!(
+            read_cmds(netquake_msgs[0])[0],
+            ServerCmd::ServerInfo {
+                protocol_version: 15,
+                ..
+            }
+        ));
+        assert_eq!(netquake_msgs[1], msgs[1].as_slice());
+
+        let frame_cmds = read_cmds(netquake_msgs[2]);
+        assert_eq!(frame_cmds.len(), 2);
+        assert!(matches!(frame_cmds[0], ServerCmd::StuffText { ref text } if text == "bf\n"));
+        assert!(matches!(
+            frame_cmds[1],
+            ServerCmd::FastUpdate(net::EntityUpdate {
+                ent_id: 1,
+                frame_id: None,
+                ..
+            })
+        ));
+
+        // upgrading loses nothing
+        let (fitzquake, issues) = netquake_srv
+            .convert(DemoProtocol::FitzQuake, Vec::new())
+            .unwrap();
+        assert!(issues.is_empty());
+        let fitzquake_srv = DemoServer::new(&mut Cursor::new(fitzquake)).unwrap();
+        let fitzquake_msgs: Vec<_> = fitzquake_srv.messages().map(|m| m.message()).collect();
+        assert!(matches!(
+            read_cmds(fitzquake_msgs[0])[0],
+            ServerCmd::ServerInfo {
+                protocol_version: 666,
+                ..
+            }
+        ));
+        assert_eq!(fitzquake_msgs[2], netquake_msgs[2]);
+
+        // converting to the same protocol copies the messages as they are
+        let (copy, issues) = demo_srv
+            .convert(DemoProtocol::FitzQuake, Vec::new())
+            .unwrap();
+        assert!(issues.is_empty());
+        let copy_srv = DemoServer::new(&mut Cursor::new(copy)).unwrap();
+        for (id, msg) in msgs.iter().enumerate() {
+            assert_eq!(
+                copy_srv.messages().nth(id).unwrap().message(),
+                msg.as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn test_demo_recorder_track_override() {
+        let data = DemoRecorder::new(Vec::new(), Some(4))
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert!(data.starts_with(b"4\n"));
+
+        let demo_srv = DemoServer::new(&mut Cursor::new(data)).unwrap();
+        assert_eq!(demo_srv.track_override(), Some(4));
+
+        assert!(DemoRecorder::new(Vec::new(), Some(100)).is_err());
+    }
+
+    #[test]
+    fn test_demo_recorder_message_too_long() {
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        let msg = vec![0; net::MAX_MESSAGE + 1];
+        assert!(matches!(
+            recorder.write_message(Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)), &msg),
+            Err(DemoServerError::MessageTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_demo_playlist_loop() {
+        let mut playlist = DemoPlaylist::new();
+        assert_eq!(playlist.next_demo(), None);
+
+        playlist.set_demos(&["demo1", "demo2", "demo3"], true);
+        assert_eq!(playlist.next_demo().as_deref(), Some("demo1"));
+        assert_eq!(playlist.next_demo().as_deref(), Some("demo2"));
+
+        // stopping keeps the position in the loop
+        playlist.stop();
+        assert_eq!(playlist.next_demo(), None);
+        assert!(playlist.resume());
+        assert_eq!(playlist.next_demo().as_deref(), Some("demo3"));
+        assert_eq!(playlist.next_demo().as_deref(), Some("demo1"));
+    }
+
+    #[test]
+    fn test_demo_playlist_set_demos() {
+        let mut playlist = DemoPlaylist::new();
+        assert!(!playlist.resume());
+
+        let demos: Vec<String> = (0..MAX_DEMOS + 2).map(|i| format!("demo{}", i)).collect();
+        playlist.set_demos(&demos, false);
+        assert_eq!(playlist.demos().len(), MAX_DEMOS);
+        assert!(!playlist.running());
+        assert_eq!(playlist.next_demo(), None);
+
+        playlist.set_demos::<&str>(&[], true);
+        assert!(!playlist.running());
+    }
 }
@@ -0,0 +1,487 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A client without a window or audio device, driven entirely from code.
+//!
+//! [`HeadlessClient`] runs on a fake clock: time only passes when [`HeadlessClient::step`] is
+//! called, so tests and bots can play a connection frame by frame and inspect the resulting
+//! state. Random effects are seeded with [`DEFAULT_SEED`] unless told otherwise, so runs are
+//! reproducible.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    client::{
+        input::{
+            game::{Action, GameInput},
+            Input, InputFocus,
+        },
+        menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
+        register_cvars,
+        sound::AudioBackend,
+        state::ClientState,
+        Client, ClientError,
+    },
+    common::{
+        console::{CmdRegistry, Console, CvarRegistry},
+        net::ClientStat,
+        vfs::Vfs,
+    },
+    server,
+};
+
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+
+/// The seed used for random effects until [`HeadlessClient::set_rng_seed`] is called.
+pub const DEFAULT_SEED: u64 = 0;
+
+/// A snapshot of a visible entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntitySnapshot {
+    pub id: usize,
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+    pub model_id: usize,
+    pub frame_id: usize,
+}
+
+/// A client with no graphics or sound that advances only when stepped.
+pub struct HeadlessClient {
+    cvars: Rc<RefCell<CvarRegistry>>,
+    cmds: Rc<RefCell<CmdRegistry>>,
+    console: Rc<RefCell<Console>>,
+    game_input: GameInput,
+    client: Client,
+
+    // total time passed to `step`
+    time: Duration,
+}
+
+impl HeadlessClient {
+    pub fn new(vfs: Rc<Vfs>) -> Result<HeadlessClient, ClientError> {
+        let con_names = Rc::new(RefCell::new(Vec::new()));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(con_names.clone())));
+        register_cvars(&cvars.borrow()).map_err(ClientError::Cvar)?;
+        server::register_cvars(&cvars.borrow()).map_err(ClientError::Cvar)?;
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(con_names)));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
+
+        // the client switches input focus as it connects and disconnects, but moves are always
+        // taken from our own game input regardless of focus
+        let input = Rc::new(RefCell::new(Input::new(
+            InputFocus::Console,
+            console.clone(),
+            Rc::new(RefCell::new(empty_menu())),
+        )));
+        let game_input = GameInput::new(console.clone());
+        game_input.register_cmds(&mut cmds.borrow_mut());
+
+        let mut client = Client::headless(vfs, cvars.clone(), cmds.clone(), console.clone(), input);
+        client.set_rng_seed(Some(DEFAULT_SEED));
+
+        Ok(HeadlessClient {
+            cvars,
+            cmds,
+            console,
+            game_input,
+            client,
+            time: Duration::zero(),
+        })
+    }
+
+    pub fn cvars(&self) -> &Rc<RefCell<CvarRegistry>> {
+        &self.cvars
+    }
+
+    pub fn cmds(&self) -> &Rc<RefCell<CmdRegistry>> {
+        &self.cmds
+    }
+
+    pub fn console(&self) -> &Rc<RefCell<Console>> {
+        &self.console
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Returns the total time the client has been stepped.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Runs console commands immediately.
+    pub fn exec<S>(&self, text: S)
+    where
+        S: AsRef<str>,
+    {
+        let console = self.console.borrow();
+        console.stuff_text(format!("{}\n", text.as_ref()));
+        console.execute();
+    }
+
+    /// Begins connecting to a server. The connection is established over the following steps.
+    pub fn connect<S>(&self, server: S)
+    where
+        S: AsRef<str>,
+    {
+        self.exec(format!("connect {}", server.as_ref()));
+    }
+
+    pub fn disconnect(&mut self) {
+        self.client.disconnect();
+    }
+
+    /// Sends the sound mixed on following steps to `backend` instead of discarding it.
+    pub fn set_audio_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.client.set_audio_backend(backend);
+    }
+
+    /// Seeds the random number generator used for particles and other effects on each level.
+    ///
+    /// Two clients with the same seed given the same input produce the same state.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.client.set_rng_seed(Some(seed));
+    }
+
+    /// Holds or releases an action for the moves sent on following steps.
+    pub fn set_action(&mut self, action: Action, active: bool) {
+        self.game_input.set_action_state(action, active);
+    }
+
+    /// Sends an impulse with the next move.
+    pub fn impulse(&mut self, impulse: u8) {
+        self.game_input.set_impulse(impulse);
+    }
+
+    /// Turns the view with the next move as if the mouse had moved by `(dx, dy)`.
+    pub fn look(&mut self, dx: f64, dy: f64) {
+        self.game_input.add_mouse_delta(dx, dy);
+    }
+
+    /// Advances the client by `frame_time`.
+    ///
+    /// This processes messages from the server, sends a move built from the current actions and
+    /// runs any commands the server stuffed into the console.
+    pub fn step(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        self.client.frame(frame_time, None)?;
+        self.client.handle_input(&mut self.game_input, frame_time)?;
+        self.console.borrow().execute();
+        self.time = self.time + frame_time;
+        Ok(())
+    }
+
+    /// Steps the client until `done` returns `true`, at most `max_frames` times.
+    ///
+    /// Returns whether `done` was satisfied.
+    pub fn run_until<F>(
+        &mut self,
+        frame_time: Duration,
+        max_frames: usize,
+        mut done: F,
+    ) -> Result<bool, ClientError>
+    where
+        F: FnMut(&HeadlessClient) -> bool,
+    {
+        for _ in 0..max_frames {
+            if done(self) {
+                return Ok(true);
+            }
+
+            self.step(frame_time)?;
+        }
+
+        Ok(done(self))
+    }
+
+    /// Returns whether the client is connected to a server or playing a demo.
+    pub fn connected(&self) -> bool {
+        self.client.connected()
+    }
+
+    /// Returns whether the client has finished signing on.
+    pub fn signed_on(&self) -> bool {
+        self.client.signed_on()
+    }
+
+    /// Applies `f` to the client state, if the client is connected.
+    pub fn with_state<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&ClientState) -> R,
+    {
+        self.client.with_state(f)
+    }
+
+    /// Returns the origin of the entity the view is attached to.
+    pub fn player_origin(&self) -> Option<Vector3<f32>> {
+        self.with_state(|state| {
+            state
+                .entities
+                .get(state.view_entity_id())
+                .map(|ent| ent.origin)
+        })
+        .flatten()
+    }
+
+    pub fn stat(&self, stat: ClientStat) -> Option<i32> {
+        self.with_state(|state| state.stats()[stat as usize])
+    }
+
+    /// Returns the entities visible in the most recent frame.
+    pub fn entities(&self) -> Vec<EntitySnapshot> {
+        self.with_state(|state| {
+            state
+                .visible_entity_ids
+                .iter()
+                .filter_map(|&id| {
+                    state.entities.get(id).map(|ent| EntitySnapshot {
+                        id,
+                        origin: ent.origin,
+                        angles: ent.angles,
+                        model_id: ent.model_id,
+                        frame_id: ent.frame_id,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Returns the console output, oldest line first.
+    pub fn console_lines(&self) -> Vec<String> {
+        let console = self.console.borrow();
+        let mut lines: Vec<String> = console
+            .output()
+            .lines()
+            .map(|l| l.iter().collect())
+            .collect();
+        lines.reverse();
+        lines
+    }
+}
+
+// the client needs a menu to switch focus to, but nothing is ever drawn
+fn empty_menu() -> Menu {
+    MenuBuilder::new().build(MenuView {
+        draw_plaque: false,
+        title_path: String::new(),
+        body: MenuBodyView::Dynamic,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{fs, io::BufReader, path::Path};
+
+    use crate::{
+        client::demo::DemoRecorder,
+        common::{
+            bsp,
+            net::{
+                msglog::{MessageLogEntry, MessageLogReader, DECODE_ERROR},
+                EntityUpdate, GameType, ServerCmd, SignOnStage, PROTOCOL_VERSION,
+            },
+            util::TempDir,
+        },
+    };
+
+    // records a demo that signs on to the test map and places the player at `origin`
+    fn write_test_demo(path: &Path, origin: Vector3<f32>) {
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let messages = vec![
+            vec![
+                ServerCmd::ServerInfo {
+                    protocol_version: PROTOCOL_VERSION as i32,
+                    max_clients: 1,
+                    game_type: GameType::CoOp,
+                    message: "Test Level".to_owned(),
+                    model_precache: vec!["maps/test.bsp".to_owned()],
+                    sound_precache: Vec::new(),
+                },
+                ServerCmd::SignOnStage {
+                    stage: SignOnStage::Prespawn,
+                },
+            ],
+            vec![
+                ServerCmd::SpawnBaseline {
+                    ent_id: 0,
+                    model_id: 1,
+                    frame_id: 0,
+                    colormap: 0,
+                    skin_id: 0,
+                    origin: Vector3::new(0.0, 0.0, 0.0),
+                    angles,
+                },
+                ServerCmd::SpawnBaseline {
+                    ent_id: 1,
+                    model_id: 0,
+                    frame_id: 0,
+                    colormap: 0,
+                    skin_id: 0,
+                    origin: Vector3::new(0.0, 0.0, 0.0),
+                    angles,
+                },
+                ServerCmd::SignOnStage {
+                    stage: SignOnStage::ClientInfo,
+                },
+            ],
+            vec![
+                ServerCmd::UpdateStat {
+                    stat: ClientStat::Health,
+                    value: 100,
+                },
+                ServerCmd::UpdateStat {
+                    stat: ClientStat::Armor,
+                    value: 50,
+                },
+                ServerCmd::SetView { ent_id: 1 },
+                ServerCmd::SignOnStage {
+                    stage: SignOnStage::Begin,
+                },
+            ],
+            vec![
+                ServerCmd::Time { time: 0.1 },
+                ServerCmd::FastUpdate(EntityUpdate {
+                    ent_id: 1,
+                    model_id: None,
+                    frame_id: None,
+                    colormap: None,
+                    skin_id: None,
+                    effects: None,
+                    origin_x: Some(origin.x),
+                    pitch: None,
+                    origin_y: Some(origin.y),
+                    yaw: None,
+                    origin_z: Some(origin.z),
+                    roll: None,
+                    no_lerp: false,
+                }),
+            ],
+        ];
+
+        let mut recorder = DemoRecorder::new(fs::File::create(path).unwrap(), None).unwrap();
+        for cmds in messages {
+            let mut msg = Vec::new();
+            for cmd in cmds {
+                cmd.serialize(&mut msg).unwrap();
+            }
+            recorder.write_message(angles, &msg).unwrap();
+        }
+        recorder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_headless_client_signon() {
+        let dir = TempDir::new("headless");
+        dir.write("maps/test.bsp", bsp::test_bsp());
+        let origin = Vector3::new(64.0, -32.0, 24.0);
+        write_test_demo(&dir.path().join("signon.dem"), origin);
+
+        let mut client = HeadlessClient::new(Rc::new(dir.vfs())).unwrap();
+        client.set_rng_seed(1234);
+        client.exec("cl_nolerp 1");
+        client.exec("playdemo signon");
+        assert!(client.connected());
+
+        let signed_on = client
+            .run_until(Duration::milliseconds(50), 20, |c| c.signed_on())
+            .unwrap();
+        assert!(signed_on);
+        assert_eq!(client.player_origin(), Some(origin));
+        assert_eq!(client.stat(ClientStat::Health), Some(100));
+        assert_eq!(client.stat(ClientStat::Armor), Some(50));
+        assert_eq!(client.stat(ClientStat::Ammo), Some(0));
+
+        // the end of the demo disconnects
+        client
+            .run_until(Duration::milliseconds(50), 20, |c| !c.connected())
+            .unwrap();
+        assert!(!client.connected());
+        assert_eq!(client.player_origin(), None);
+    }
+
+    #[test]
+    fn test_headless_client_netlog_decode_error() {
+        let dir = TempDir::new("headless");
+        let log_path = dir.path().join("net.log");
+
+        // a print followed by a time command cut off after its first byte
+        let mut msg = Vec::new();
+        ServerCmd::Print {
+            text: "before\n".to_owned(),
+        }
+        .serialize(&mut msg)
+        .unwrap();
+        let bad_start = msg.len();
+        ServerCmd::Time { time: 1.0 }.serialize(&mut msg).unwrap();
+        msg.truncate(bad_start + 1);
+
+        let mut recorder =
+            DemoRecorder::new(fs::File::create(dir.path().join("bad.dem")).unwrap(), None).unwrap();
+        recorder
+            .write_message(Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)), &msg)
+            .unwrap();
+        recorder.finish().unwrap();
+
+        let mut client = HeadlessClient::new(Rc::new(dir.vfs())).unwrap();
+        client.exec("playdemo bad");
+        client.exec(format!("netlog \"{}\"", log_path.display()));
+        assert!(client
+            .run_until(Duration::milliseconds(50), 5, |_| false)
+            .is_err());
+
+        let entries: Vec<MessageLogEntry> =
+            MessageLogReader::new(BufReader::new(fs::File::open(&log_path).unwrap()))
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "Print");
+        assert_eq!(entries[1].name, DECODE_ERROR);
+        assert_eq!(entries[1].raw, &msg[bad_start..]);
+    }
+
+    #[test]
+    fn test_headless_client_step_idle() {
+        let mut client = HeadlessClient::new(Rc::new(Vfs::new())).unwrap();
+        for _ in 0..10 {
+            client.step(Duration::milliseconds(10)).unwrap();
+        }
+
+        assert_eq!(client.time(), Duration::milliseconds(100));
+        assert!(!client.connected());
+        assert_eq!(client.player_origin(), None);
+        assert!(client.entities().is_empty());
+    }
+
+    #[test]
+    fn test_headless_client_actions() {
+        let mut client = HeadlessClient::new(Rc::new(Vfs::new())).unwrap();
+        client.set_action(Action::Forward, true);
+        assert!(client.game_input.action_state(Action::Forward));
+
+        // console commands drive the same input
+        client.exec("-forward");
+        assert!(!client.game_input.action_state(Action::Forward));
+    }
+}
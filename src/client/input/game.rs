@@ -18,6 +18,7 @@
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
+    io::{self, Write},
     rc::Rc,
     str::FromStr,
     string::ToString,
@@ -41,8 +42,9 @@ use winit::{
 
 const ACTION_COUNT: usize = 19;
 
-static INPUT_NAMES: [&'static str; 79] = [
+static INPUT_NAMES: [&'static str; 82] = [
     ",",
+    "-",
     ".",
     "/",
     "0",
@@ -55,6 +57,7 @@ static INPUT_NAMES: [&'static str; 79] = [
     "7",
     "8",
     "9",
+    "=",
     "A",
     "ALT",
     "B",
@@ -99,6 +102,7 @@ static INPUT_NAMES: [&'static str; 79] = [
     "N",
     "O",
     "P",
+    "PAUSE",
     "PGDN",
     "PGUP",
     "Q",
@@ -123,8 +127,9 @@ static INPUT_NAMES: [&'static str; 79] = [
     "`",
 ];
 
-static INPUT_VALUES: [BindInput; 79] = [
+static INPUT_VALUES: [BindInput; 82] = [
     BindInput::Key(Key::Comma),
+    BindInput::Key(Key::Minus),
     BindInput::Key(Key::Period),
     BindInput::Key(Key::Slash),
     BindInput::Key(Key::Key0),
@@ -137,6 +142,7 @@ static INPUT_VALUES: [BindInput; 79] = [
     BindInput::Key(Key::Key7),
     BindInput::Key(Key::Key8),
     BindInput::Key(Key::Key9),
+    BindInput::Key(Key::Equals),
     BindInput::Key(Key::A),
     BindInput::Key(Key::LAlt),
     BindInput::Key(Key::B),
@@ -181,6 +187,7 @@ static INPUT_VALUES: [BindInput; 79] = [
     BindInput::Key(Key::N),
     BindInput::Key(Key::O),
     BindInput::Key(Key::P),
+    BindInput::Key(Key::Pause),
     BindInput::Key(Key::PageDown),
     BindInput::Key(Key::PageUp),
     BindInput::Key(Key::Q),
@@ -474,10 +481,32 @@ impl ToString for BindTarget {
     }
 }
 
+// keyboard shortcuts for demo playback
+fn demo_bindings() -> HashMap<BindInput, BindTarget> {
+    let mut bindings = HashMap::new();
+    for (key, cmd) in &[
+        (Key::Pause, "demo_pause"),
+        (Key::PageUp, "demo_skip 10"),
+        (Key::PageDown, "demo_skip -10"),
+        (Key::Equals, "demo_faster"),
+        (Key::Minus, "demo_slower"),
+    ] {
+        bindings.insert((*key).into(), BindTarget::from_str(cmd).unwrap());
+    }
+
+    bindings
+}
+
 #[derive(Clone)]
 pub struct GameInput {
     console: Rc<RefCell<Console>>,
     bindings: Rc<RefCell<HashMap<BindInput, BindTarget>>>,
+
+    // transport controls which take precedence over `bindings` during demo playback. These aren't
+    // user bindings, so they're never saved.
+    demo_bindings: HashMap<BindInput, BindTarget>,
+    demo_playing: bool,
+
     action_states: Rc<RefCell<[bool; ACTION_COUNT]>>,
     mouse_delta: (f64, f64),
     impulse: Rc<Cell<u8>>,
@@ -488,6 +517,8 @@ impl GameInput {
         GameInput {
             console,
             bindings: Rc::new(RefCell::new(HashMap::new())),
+            demo_bindings: demo_bindings(),
+            demo_playing: false,
             action_states: Rc::new(RefCell::new([false; ACTION_COUNT])),
             mouse_delta: (0.0, 0.0),
             impulse: Rc::new(Cell::new(0)),
@@ -527,6 +558,11 @@ impl GameInput {
         self.bind(Key::Key9, BindTarget::from_str("impulse 9").unwrap());
     }
 
+    /// Sets whether a demo is playing, which enables the demo transport controls.
+    pub fn set_demo_playing(&mut self, demo_playing: bool) {
+        self.demo_playing = demo_playing;
+    }
+
     /// Bind a `BindInput` to a `BindTarget`.
     pub fn bind<I, T>(&mut self, input: I, target: T) -> Option<BindTarget>
     where
@@ -546,6 +582,39 @@ impl GameInput {
         self.bindings.borrow().get(&input.into()).map(|t| t.clone())
     }
 
+    /// Writes the current bindings to `writer` as commands that restore them.
+    ///
+    /// The commands begin with `unbindall`, so executing them also removes any bindings made
+    /// since.
+    pub fn write_bindings<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let mut lines: Vec<String> = self
+            .bindings
+            .borrow()
+            .iter()
+            // skip inputs that can't be named in a bind command
+            .filter(|(input, _)| !input.to_string().is_empty())
+            .map(|(input, target)| {
+                let target = match *target {
+                    BindTarget::Action { .. } => target.to_string(),
+                    BindTarget::ConsoleInput { ref text } => text.to_owned(),
+                };
+
+                format!("bind \"{}\" \"{}\"", input.to_string(), target)
+            })
+            .collect();
+        lines.sort();
+
+        writeln!(writer, "unbindall")?;
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
     pub fn handle_event<T>(&mut self, outer_event: Event<T>) {
         let (input, state): (BindInput, _) = match outer_event {
             Event::WindowEvent { event, .. } => match event {
@@ -586,9 +655,14 @@ impl GameInput {
     {
         let bind_input = input.into();
 
+        let target = match self.demo_bindings.get(&bind_input) {
+            Some(target) if self.demo_playing => Some(target.clone()),
+            _ => self.bindings.borrow().get(&bind_input).cloned(),
+        };
+
         // debug!("handle input {:?}: {:?}", &bind_input, state);
-        if let Some(target) = self.bindings.borrow().get(&bind_input) {
-            match *target {
+        if let Some(target) = target {
+            match target {
                 BindTarget::Action { trigger, action } => {
                     self.action_states.borrow_mut()[action as usize] = state == trigger;
                     debug!(
@@ -598,11 +672,17 @@ impl GameInput {
                     );
                 }
 
-                BindTarget::ConsoleInput { ref text } => {
-                    if state == ElementState::Pressed {
-                        self.console.borrow_mut().stuff_text(text);
+                BindTarget::ConsoleInput { ref text } => match state {
+                    ElementState::Pressed => self.console.borrow_mut().stuff_text(text),
+
+                    // like actions, a "+" command bound to a key runs its "-" counterpart when
+                    // the key is released, so "+" aliases work
+                    ElementState::Released => {
+                        if let Some(name) = text.strip_prefix('+') {
+                            self.console.borrow_mut().stuff_text(format!("-{}", name));
+                        }
                     }
-                }
+                },
             }
         }
     }
@@ -611,6 +691,21 @@ impl GameInput {
         self.action_states.borrow()[action as usize]
     }
 
+    /// Sets the state of `action` directly, like the `+`/`-` commands.
+    pub fn set_action_state(&mut self, action: Action, state: bool) {
+        self.action_states.borrow_mut()[action as usize] = state;
+    }
+
+    /// Adds to the mouse movement accumulated since the last call to `refresh`.
+    pub fn add_mouse_delta(&mut self, dx: f64, dy: f64) {
+        self.mouse_delta.0 += dx;
+        self.mouse_delta.1 += dy;
+    }
+
+    pub fn set_impulse(&mut self, impulse: u8) {
+        self.impulse.set(impulse);
+    }
+
     // TODO: roll actions into a loop
     pub fn register_cmds(&self, cmds: &mut CmdRegistry) {
         let states = [("+", true), ("-", false)];
@@ -667,6 +762,13 @@ impl GameInput {
             }),
         )
         .unwrap();
+        self.console.borrow().register_completer(
+            "bind",
+            Box::new(|args| match args.len() {
+                0 => INPUT_NAMES.iter().map(|n| n.to_string()).collect(),
+                _ => Vec::new(),
+            }),
+        );
 
         // "unbindall"
         let bindings = self.bindings.clone();
@@ -725,6 +827,8 @@ impl GameInput {
 mod test {
     use super::*;
 
+    use crate::common::console::CvarRegistry;
+
     #[test]
     fn test_action_to_string() {
         let act = Action::Forward;
@@ -740,4 +844,85 @@ mod test {
 
         assert_eq!(target.to_string(), "+forward");
     }
+
+    #[test]
+    fn test_game_input_demo_bindings() {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars)));
+
+        let skipped = Rc::new(Cell::new(false));
+        let demo_skip = skipped.clone();
+        cmds.borrow_mut()
+            .insert(
+                "demo_skip",
+                Box::new(move |_| {
+                    demo_skip.set(true);
+                    String::new()
+                }),
+            )
+            .unwrap();
+
+        let mut game_input = GameInput::new(console.clone());
+        game_input.bind_defaults();
+        game_input.bind(Key::PageUp, BindTarget::from_str("+lookup").unwrap());
+
+        // in normal play, the user's binding applies
+        game_input.handle_input(Key::PageUp, ElementState::Pressed);
+        console.borrow().execute();
+        assert!(game_input.action_state(Action::LookUp));
+        assert!(!skipped.get());
+        game_input.handle_input(Key::PageUp, ElementState::Released);
+
+        // during a demo, the transport controls take over
+        game_input.set_demo_playing(true);
+        game_input.handle_input(Key::PageUp, ElementState::Pressed);
+        console.borrow().execute();
+        assert!(skipped.get());
+        assert!(!game_input.action_state(Action::LookUp));
+
+        // and they're never saved
+        let mut config = Vec::new();
+        game_input.write_bindings(&mut config).unwrap();
+        assert!(!String::from_utf8(config).unwrap().contains("demo_"));
+    }
+
+    #[test]
+    fn test_game_input_write_bindings() {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars)));
+
+        let mut game_input = GameInput::new(console.clone());
+        game_input.register_cmds(&mut cmds.borrow_mut());
+        game_input.bind(Key::W, BindTarget::from_str("+forward").unwrap());
+        game_input.bind(Key::Space, BindTarget::from_str("+jump").unwrap());
+        game_input.bind(Key::Key1, BindTarget::from_str("impulse 1").unwrap());
+
+        let mut config = Vec::new();
+        game_input.write_bindings(&mut config).unwrap();
+        let config = String::from_utf8(config).unwrap();
+        assert_eq!(
+            config,
+            concat!(
+                "unbindall\n",
+                "bind \"1\" \"impulse 1\"\n",
+                "bind \"SPACE\" \"+jump\"\n",
+                "bind \"W\" \"+forward\"\n",
+            )
+        );
+
+        // executing the config replaces any other bindings
+        game_input.bind(Key::E, BindTarget::from_str("+use").unwrap());
+        console.borrow().stuff_text(config);
+        console.borrow().execute();
+        assert!(game_input.binding(Key::E).is_none());
+        assert_eq!(
+            game_input.binding(Key::Key1).unwrap().to_string(),
+            "\"impulse 1\""
+        );
+        assert_eq!(game_input.binding(Key::W).unwrap().to_string(), "+forward");
+    }
 }
@@ -19,7 +19,11 @@ pub mod console;
 pub mod game;
 pub mod menu;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
 
 use crate::{
     client::menu::Menu,
@@ -110,6 +114,19 @@ impl Input {
         self.game_input.bind_defaults();
     }
 
+    /// Sets whether a demo is playing, which enables the demo transport controls.
+    pub fn set_demo_playing(&mut self, demo_playing: bool) {
+        self.game_input.set_demo_playing(demo_playing);
+    }
+
+    /// Writes the current key bindings to `writer` as commands that restore them.
+    pub fn write_bindings<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        self.game_input.write_bindings(writer)
+    }
+
     pub fn game_input(&self) -> Option<&GameInput> {
         if let InputFocus::Game = self.focus {
             Some(&self.game_input)
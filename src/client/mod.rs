@@ -18,14 +18,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod config;
 mod cvars;
 pub mod demo;
 pub mod entity;
+pub mod headless;
 pub mod input;
 pub mod menu;
+pub mod precache;
 pub mod render;
 pub mod sound;
 pub mod state;
+pub mod timedemo;
 pub mod trace;
 pub mod view;
 
@@ -34,18 +38,25 @@ pub use self::cvars::register_cvars;
 use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
-    io::BufReader,
-    net::ToSocketAddrs,
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    net::SocketAddr,
     rc::Rc,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::Instant,
 };
 
 use crate::{
     client::{
-        demo::{DemoServer, DemoServerError},
+        demo::{DemoPlaylist, DemoRecorder, DemoServer, DemoServerError},
         entity::{ClientEntity, MAX_STATIC_ENTITIES},
         input::{game::GameInput, Input},
-        sound::{MusicPlayer, StaticSound},
+        sound::{
+            AmbientVars, AudioBackend, DeviceBackend, DspVars, Mixer, MusicPlayer, MusicVars,
+            NullBackend, PlayMode, StaticSound,
+        },
         state::{ClientState, PlayerInfo},
+        timedemo::{Timedemo, TimedemoSummary},
         trace::{TraceEntity, TraceFrame},
         view::{IdleVars, KickVars, MouseVars, RollVars},
     },
@@ -55,7 +66,11 @@ use crate::{
         model::ModelError,
         net::{
             self,
-            connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
+            connect::{
+                resolve_server_addr_async, ConnectSocket, Request, ResolveServerAddr, Response,
+                CONNECT_PROTOCOL_VERSION,
+            },
+            msglog::MessageLog,
             BlockingMode, ClientCmd, ClientStat, ColorShift, EntityEffects, EntityState, GameType,
             NetError, PlayerColor, QSocket, ServerCmd, SignOnStage,
         },
@@ -63,19 +78,17 @@ use crate::{
     },
 };
 
-use cgmath::Deg;
+use cgmath::{Deg, Vector3};
 use chrono::Duration;
 use input::InputFocus;
 use menu::Menu;
+use num::FromPrimitive as _;
 use render::{ClientRenderer, GraphicsState, WorldRenderer};
-use rodio::{OutputStream, OutputStreamHandle};
+use rodio::OutputStreamHandle;
 use sound::SoundError;
 use thiserror::Error;
 use view::BobVars;
 
-// connections are tried 3 times, see
-// https://github.com/id-Software/Quake/blob/master/WinQuake/net_dgrm.c#L1248
-const MAX_CONNECT_ATTEMPTS: usize = 3;
 const MAX_STATS: usize = 32;
 
 const DEFAULT_SOUND_PACKET_VOLUME: u8 = 255;
@@ -99,8 +112,6 @@ pub enum ClientError {
     InvalidConnectPort(i32),
     #[error("Server sent an invalid connect response")]
     InvalidConnectResponse,
-    #[error("Invalid server address")]
-    InvalidServerAddress,
     #[error("No response from server")]
     NoResponse,
     #[error("Unrecognized protocol: {0}")]
@@ -125,9 +136,8 @@ pub enum ClientError {
     TooManyStaticEntities,
     #[error("No such lightmap animation: {0}")]
     NoSuchLightmapAnimation(usize),
-    // TODO: wrap PlayError
-    #[error("Failed to open audio output stream")]
-    OutputStream,
+    #[error("Couldn't load BSP file {0}: {1}")]
+    BspFile(String, failure::Error),
     #[error("Demo server error: {0}")]
     DemoServer(#[from] DemoServerError),
     #[error("Model error: {0}")]
@@ -140,6 +150,11 @@ pub enum ClientError {
     Vfs(#[from] VfsError),
 }
 
+pub struct ConnectVars {
+    cl_connect_attempts: usize,
+    cl_connect_timeout: Duration,
+}
+
 pub struct MoveVars {
     cl_anglespeedkey: f32,
     cl_pitchspeed: f32,
@@ -190,7 +205,9 @@ enum ConnectionState {
     SignOn(SignOnStage),
 
     /// The client is fully connected.
-    Connected(WorldRenderer),
+    ///
+    /// There is no world renderer if the client is running without graphics.
+    Connected(Option<WorldRenderer>),
 }
 
 /// Possible targets that a client can be connected to.
@@ -215,13 +232,274 @@ pub struct Connection {
     state: ClientState,
     conn_state: ConnectionState,
     kind: ConnectionKind,
+
+    /// If present, every command received or sent over this connection is recorded here.
+    msg_log: Option<MessageLog<BufWriter<File>>>,
+
+    /// Time elapsed since the last message arrived from the server.
+    time_since_recv: Duration,
+
+    /// Messages received during sign-on, kept so that a demo recorded mid-game can replay them.
+    signon_msgs: Vec<Vec<u8>>,
+
+    /// Set while fast-forwarding through a demo to seek, so that sounds and other transient
+    /// effects aren't replayed.
+    seeking: bool,
+
+    /// If present, the demo is being played as fast as possible and frame times are recorded.
+    timedemo: Option<Timedemo>,
+
+    /// If present, the client state's random number generator is seeded with this on each level.
+    rng_seed: Option<u64>,
+}
+
+/// Applies `f` to the message log, if there is one.
+///
+/// Failing to write the log shouldn't take down the connection, so on error the log is closed.
+fn write_msg_log<F>(msg_log: &mut Option<MessageLog<BufWriter<File>>>, f: F)
+where
+    F: FnOnce(&mut MessageLog<BufWriter<File>>) -> Result<(), NetError>,
+{
+    let result = match msg_log {
+        Some(ref mut log) => f(log),
+        None => return,
+    };
+
+    if let Err(e) = result {
+        error!("Failed to write message log, closing it: {}", e);
+        *msg_log = None;
+    }
+}
+
+/// Appends a server message to the demo being recorded, if there is one.
+///
+/// As with the message log, a write error stops the recording rather than the connection.
+fn write_demo_msg(
+    demo_recorder: &mut Option<DemoRecorder<BufWriter<File>>>,
+    view_angles: Vector3<Deg<f32>>,
+    msg: &[u8],
+    console: &Console,
+) {
+    let result = match demo_recorder {
+        Some(ref mut recorder) => recorder.write_message(view_angles, msg),
+        None => return,
+    };
+
+    if let Err(e) = result {
+        console.println(format!("Failed to write demo, recording stopped: {}", e));
+        *demo_recorder = None;
+    }
+}
+
+/// Finishes the demo being recorded, if there is one, returning a message for the console.
+fn stop_recording(demo_recorder: &mut Option<DemoRecorder<BufWriter<File>>>) -> Option<String> {
+    let recorder = demo_recorder.take()?;
+
+    Some(match recorder.finish() {
+        Ok(_) => "Completed demo".to_owned(),
+        Err(e) => format!("Couldn't finish demo: {}", e),
+    })
+}
+
+/// Appends a command to the compose buffer, recording it in the message log.
+fn compose_cmd(
+    cmd: ClientCmd,
+    compose: &mut Vec<u8>,
+    msg_log: &mut Option<MessageLog<BufWriter<File>>>,
+) -> Result<(), ClientError> {
+    let start = compose.len();
+    cmd.serialize(compose)?;
+    write_msg_log(msg_log, |log| log.log_client_cmd(&cmd, &compose[start..]));
+    Ok(())
 }
 
 impl Connection {
+    fn new(kind: ConnectionKind) -> Connection {
+        Connection {
+            state: ClientState::new(),
+            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+            kind,
+            msg_log: None,
+            time_since_recv: Duration::zero(),
+            signon_msgs: Vec::new(),
+            seeking: false,
+            timedemo: None,
+            rng_seed: None,
+        }
+    }
+
+    /// Discards all client state and restarts the sign-on process.
+    ///
+    /// This is used when the server changes levels, after which it sends a new `ServerInfo`.
+    fn reset(&mut self) {
+        self.state = ClientState::new();
+        self.conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+        self.time_since_recv = Duration::zero();
+        self.signon_msgs.clear();
+    }
+
+    /// Scales the frame time for demo playback according to the pause state and timescale.
+    fn scale_frame_time(&self, frame_time: Duration, demo_timescale: f32) -> Duration {
+        match self.kind {
+            ConnectionKind::Demo(ref demo_srv) if demo_srv.paused() => Duration::zero(),
+            ConnectionKind::Demo(_) => engine::duration_from_f32(
+                engine::duration_to_f32(frame_time) * demo_timescale.max(0.0),
+            ),
+            ConnectionKind::Server { .. } => frame_time,
+        }
+    }
+
+    /// Rebuilds the client state at position `target` on the demo timeline.
+    ///
+    /// The demo is rewound to the last keyframe before `target`, and everything the demo server
+    /// yields from there up to `target` is played without sound. For a snapshot keyframe, that's
+    /// the level's sign-on, the snapshot, and the messages between the keyframe and `target`.
+    fn seek_demo(
+        &mut self,
+        target: Duration,
+        vfs: &Vfs,
+        gfx_state: Option<&GraphicsState>,
+        cmds: &mut CmdRegistry,
+        console: &mut Console,
+        music_player: &mut MusicPlayer,
+        kick_vars: KickVars,
+    ) -> Result<ConnectionStatus, ClientError> {
+        let target_id = match self.kind {
+            ConnectionKind::Demo(ref mut demo_srv) => demo_srv.rewind(target),
+            ConnectionKind::Server { .. } => return Ok(ConnectionStatus::Maintain),
+        };
+
+        self.reset();
+        self.seeking = true;
+
+        let mut status = Ok(ConnectionStatus::Maintain);
+        loop {
+            let message_id = match self.kind {
+                ConnectionKind::Demo(ref demo_srv) => demo_srv.message_id(),
+                ConnectionKind::Server { .. } => unreachable!(),
+            };
+
+            if message_id >= target_id {
+                break;
+            }
+
+            // catch up to each message as it's played so the next one is requested immediately
+            self.state.time = self.state.msg_times[0];
+            status = self.parse_server_msg(
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                &mut None,
+                kick_vars,
+            );
+
+            match status {
+                Ok(ConnectionStatus::Maintain) => (),
+                _ => break,
+            }
+        }
+
+        self.seeking = false;
+
+        // don't interpolate from wherever the replay left off
+        self.state.time = self.state.msg_times[0];
+        self.state.msg_times[1] = self.state.msg_times[0];
+
+        status
+    }
+
+    /// Returns the view angles to record alongside server messages in a demo.
+    ///
+    /// Demo playback inverts the roll angle, so it's inverted here to match.
+    fn demo_view_angles(&self) -> Vector3<Deg<f32>> {
+        let angles = self.state.view.input_angles();
+        Vector3::new(angles.pitch, angles.yaw, -angles.roll)
+    }
+
+    /// Writes the messages needed to start a demo from the current state of the connection.
+    ///
+    /// This replays the server's sign-on messages, then brings everything that may have changed
+    /// since sign-on up to date. Entities catch up with the next update from the server.
+    fn write_demo_signon<W>(&self, recorder: &mut DemoRecorder<W>) -> Result<(), DemoServerError>
+    where
+        W: Write,
+    {
+        // nothing to replay until the server has sent its info
+        if self.signon_msgs.is_empty() {
+            return Ok(());
+        }
+
+        let view_angles = self.demo_view_angles();
+        for msg in self.signon_msgs.iter() {
+            recorder.write_message(view_angles, msg)?;
+        }
+
+        let mut msg = Vec::new();
+        ServerCmd::Time {
+            time: engine::duration_to_f32(self.state.msg_times[0]),
+        }
+        .serialize(&mut msg)?;
+        for (id, value) in self.state.light_styles.iter() {
+            ServerCmd::LightStyle {
+                id: *id,
+                value: value.clone(),
+            }
+            .serialize(&mut msg)?;
+        }
+        recorder.write_message(view_angles, &msg)?;
+
+        msg.clear();
+        for (player_id, info) in self.state.player_info.iter().enumerate() {
+            if let Some(info) = info {
+                let player_id = player_id as u8;
+                ServerCmd::UpdateName {
+                    player_id,
+                    new_name: info.name.clone(),
+                }
+                .serialize(&mut msg)?;
+                ServerCmd::UpdateFrags {
+                    player_id,
+                    new_frags: info.frags as i16,
+                }
+                .serialize(&mut msg)?;
+                ServerCmd::UpdateColors {
+                    player_id,
+                    new_colors: info.colors,
+                }
+                .serialize(&mut msg)?;
+            }
+        }
+
+        for (stat_id, value) in self.state.stats.iter().enumerate() {
+            if let Some(stat) = ClientStat::from_usize(stat_id) {
+                ServerCmd::UpdateStat {
+                    stat,
+                    value: *value,
+                }
+                .serialize(&mut msg)?;
+            }
+        }
+
+        let angles = self.state.view.input_angles();
+        ServerCmd::SetView {
+            ent_id: self.state.view_entity_id() as i16,
+        }
+        .serialize(&mut msg)?;
+        ServerCmd::SetAngle {
+            angles: Vector3::new(angles.pitch, angles.yaw, angles.roll),
+        }
+        .serialize(&mut msg)?;
+        recorder.write_message(view_angles, &msg)?;
+
+        Ok(())
+    }
+
     fn handle_signon(
         &mut self,
         new_stage: SignOnStage,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
     ) -> Result<(), ClientError> {
         use SignOnStage::*;
 
@@ -235,32 +513,47 @@ impl Connection {
                     match new_stage {
                         Not => (), // TODO this is an error (invalid value)
                         Prespawn => {
-                            ClientCmd::StringCmd {
-                                cmd: String::from("prespawn"),
-                            }
-                            .serialize(compose)?;
+                            compose_cmd(
+                                ClientCmd::StringCmd {
+                                    cmd: String::from("prespawn"),
+                                },
+                                compose,
+                                &mut self.msg_log,
+                            )?;
                         }
                         ClientInfo => {
                             // TODO: fill in client info here
-                            ClientCmd::StringCmd {
-                                cmd: format!("name \"{}\"\n", "UNNAMED"),
-                            }
-                            .serialize(compose)?;
-                            ClientCmd::StringCmd {
-                                cmd: format!("color {} {}", 0, 0),
-                            }
-                            .serialize(compose)?;
+                            compose_cmd(
+                                ClientCmd::StringCmd {
+                                    cmd: format!("name \"{}\"\n", "UNNAMED"),
+                                },
+                                compose,
+                                &mut self.msg_log,
+                            )?;
+                            compose_cmd(
+                                ClientCmd::StringCmd {
+                                    cmd: format!("color {} {}", 0, 0),
+                                },
+                                compose,
+                                &mut self.msg_log,
+                            )?;
                             // TODO: need default spawn parameters?
-                            ClientCmd::StringCmd {
-                                cmd: format!("spawn {}", ""),
-                            }
-                            .serialize(compose)?;
+                            compose_cmd(
+                                ClientCmd::StringCmd {
+                                    cmd: format!("spawn {}", ""),
+                                },
+                                compose,
+                                &mut self.msg_log,
+                            )?;
                         }
                         SignOnStage::Begin => {
-                            ClientCmd::StringCmd {
-                                cmd: String::from("begin"),
-                            }
-                            .serialize(compose)?;
+                            compose_cmd(
+                                ClientCmd::StringCmd {
+                                    cmd: String::from("begin"),
+                                },
+                                compose,
+                                &mut self.msg_log,
+                            )?;
                         }
                         SignOnStage::Done => {
                             debug!("SignOn complete");
@@ -277,11 +570,9 @@ impl Connection {
                     Prespawn | ClientInfo | Begin => ConnectionState::SignOn(new_stage),
 
                     // finished signing on, build world renderer
-                    Done => ConnectionState::Connected(WorldRenderer::new(
-                        gfx_state,
-                        self.state.models(),
-                        1,
-                    )),
+                    Done => ConnectionState::Connected(
+                        gfx_state.map(|g| WorldRenderer::new(g, self.state.models(), 1)),
+                    ),
                 }
             }
 
@@ -297,24 +588,23 @@ impl Connection {
     fn parse_server_msg(
         &mut self,
         vfs: &Vfs,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
         cmds: &mut CmdRegistry,
         console: &mut Console,
         music_player: &mut MusicPlayer,
+        demo_recorder: &mut Option<DemoRecorder<BufWriter<File>>>,
         kick_vars: KickVars,
     ) -> Result<ConnectionStatus, ClientError> {
         use ConnectionStatus::*;
 
         let (msg, demo_view_angles, track_override) = match self.kind {
             ConnectionKind::Server { ref mut qsock, .. } => {
-                let msg = qsock.recv_msg(match self.conn_state {
-                    // if we're in the game, don't block waiting for messages
-                    ConnectionState::Connected(_) => BlockingMode::NonBlocking,
-
-                    // otherwise, give the server some time to respond
-                    // TODO: might make sense to make this a future or something
-                    ConnectionState::SignOn(_) => BlockingMode::Timeout(Duration::seconds(5)),
-                })?;
+                // never block waiting for messages so the loading screen stays responsive;
+                // if the server goes quiet for too long, frame() will time the connection out
+                let msg = qsock.recv_msg(BlockingMode::NonBlocking)?;
+                if !msg.is_empty() {
+                    self.time_since_recv = Duration::zero();
+                }
 
                 (msg, None, None)
             }
@@ -354,9 +644,36 @@ impl Connection {
             return Ok(Maintain);
         }
 
-        let mut reader = BufReader::new(msg.as_slice());
+        if let ConnectionKind::Server { .. } = self.kind {
+            write_demo_msg(demo_recorder, self.demo_view_angles(), &msg, console);
+        }
+
+        // messages that arrive before sign-on completes are kept for demo recording
+        let mut in_signon = match self.conn_state {
+            ConnectionState::SignOn(_) => true,
+            ConnectionState::Connected(_) => false,
+        };
+
+        let mut reader = Cursor::new(msg.as_slice());
+
+        loop {
+            let cmd_start = reader.position() as usize;
+            let cmd = match ServerCmd::deserialize(&mut reader) {
+                Ok(Some(c)) => c,
+                Ok(None) => break,
+                Err(e) => {
+                    write_msg_log(&mut self.msg_log, |log| {
+                        log.log_server_error(&msg[cmd_start..], &e)
+                    });
+                    return Err(e.into());
+                }
+            };
+            let cmd_end = reader.position() as usize;
+
+            write_msg_log(&mut self.msg_log, |log| {
+                log.log_server_cmd(&cmd, &msg[cmd_start..cmd_end])
+            });
 
-        while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
             match cmd {
                 // TODO: have an error for this instead of panicking
                 // once all other commands have placeholder handlers, just error
@@ -365,11 +682,27 @@ impl Connection {
 
                 ServerCmd::NoOp => (),
 
-                ServerCmd::CdTrack { track, .. } => {
-                    music_player.play_track(match track_override {
-                        Some(t) => t as usize,
-                        None => track as usize,
-                    })?;
+                // these would all fire at once while seeking, so skip them
+                ServerCmd::Sound { .. }
+                | ServerCmd::Particle { .. }
+                | ServerCmd::TempEntity { .. }
+                | ServerCmd::Print { .. }
+                | ServerCmd::CenterPrint { .. }
+                | ServerCmd::StuffText { .. }
+                    if self.seeking => {}
+
+                ServerCmd::CdTrack { track, loop_ } => {
+                    // as in the original, a track forced by the demo header always loops
+                    let (track, mode) = match track_override {
+                        Some(t) => (t as usize, PlayMode::Loop),
+                        None if loop_ != 0 => (track as usize, PlayMode::Loop),
+                        None => (track as usize, PlayMode::Once),
+                    };
+
+                    // missing music isn't worth disconnecting over
+                    if let Err(e) = music_player.play_track(track, mode) {
+                        console.println(format!("Couldn't play track {}: {}", track, e));
+                    }
                 }
 
                 ServerCmd::CenterPrint { text } => {
@@ -471,22 +804,35 @@ impl Connection {
                         Err(ClientError::UnrecognizedProtocol(protocol_version))?;
                     }
 
-                    console.println(CONSOLE_DIVIDER);
-                    console.println(message);
-                    console.println(CONSOLE_DIVIDER);
+                    if !self.seeking {
+                        console.println(CONSOLE_DIVIDER);
+                        console.println(message);
+                        console.println(CONSOLE_DIVIDER);
+                    }
 
                     let _server_info = ServerInfo {
                         _max_clients: max_clients,
                         _game_type: game_type,
                     };
 
+                    // a new level means a new sign-on
+                    self.signon_msgs.clear();
+                    in_signon = true;
+
                     self.state = ClientState::from_server_info(
                         vfs,
-                        self.state.mixer.stream(),
                         max_clients,
                         model_precache,
                         sound_precache,
                     )?;
+                    if let Some(seed) = self.rng_seed {
+                        self.state.seed_rng(seed);
+                    }
+
+                    // seeking replays the sign-on, so only report missing assets the first time
+                    if !self.seeking && !self.state.missing_assets.is_empty() {
+                        console.println(self.state.missing_assets.summary());
+                    }
 
                     let bonus_cshift =
                         self.state.color_shifts[ColorShiftCode::Bonus as usize].clone();
@@ -540,7 +886,6 @@ impl Connection {
 
                     let volume = volume.unwrap_or(DEFAULT_SOUND_PACKET_VOLUME);
                     let attenuation = attenuation.unwrap_or(DEFAULT_SOUND_PACKET_ATTENUATION);
-                    // TODO: apply volume, attenuation, spatialization
                     self.state.mixer.start_sound(
                         self.state.sounds[sound_id as usize].clone(),
                         self.state.msg_times[0],
@@ -607,7 +952,6 @@ impl Connection {
                     attenuation,
                 } => {
                     self.state.static_sounds.push(StaticSound::new(
-                        &self.state.mixer.stream(),
                         origin,
                         self.state.sounds[sound_id as usize].clone(),
                         volume as f32 / 255.0,
@@ -729,6 +1073,10 @@ impl Connection {
             }
         }
 
+        if in_signon {
+            self.signon_msgs.push(msg);
+        }
+
         Ok(Maintain)
     }
 
@@ -736,28 +1084,84 @@ impl Connection {
         &mut self,
         frame_time: Duration,
         vfs: &Vfs,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
         cmds: &mut CmdRegistry,
         console: &mut Console,
         music_player: &mut MusicPlayer,
+        demo_recorder: &mut Option<DemoRecorder<BufWriter<File>>>,
         idle_vars: IdleVars,
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
+        ambient_vars: AmbientVars,
+        snd_maxchannels: usize,
         cl_nolerp: f32,
         sv_gravity: f32,
+        msg_timeout: Duration,
     ) -> Result<ConnectionStatus, ClientError> {
+        let frame_start = Instant::now();
+
+        // play one message per frame, however long the frame actually took
+        let frame_time = match (&self.kind, &self.timedemo) {
+            (ConnectionKind::Demo(_), Some(_)) => {
+                (self.state.msg_times[0] - self.state.time).max(Duration::zero())
+            }
+            _ => frame_time,
+        };
+
         debug!("frame time: {}ms", frame_time.num_milliseconds());
 
+        let seek_request = match self.kind {
+            ConnectionKind::Demo(ref mut demo_srv) => demo_srv.take_seek_request(),
+            ConnectionKind::Server { .. } => None,
+        };
+
+        if let Some(target) = seek_request {
+            match self.seek_demo(
+                target,
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                kick_vars,
+            )? {
+                ConnectionStatus::Maintain => (),
+                s => return Ok(s),
+            }
+        }
+
+        // apply the channel limit before any new sounds start
+        self.state.mixer.set_max_channels(snd_maxchannels);
+
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.advance_time(frame_time);
-        match self.parse_server_msg(vfs, gfx_state, cmds, console, music_player, kick_vars)? {
+        self.time_since_recv = self.time_since_recv + frame_time;
+        match self.parse_server_msg(
+            vfs,
+            gfx_state,
+            cmds,
+            console,
+            music_player,
+            demo_recorder,
+            kick_vars,
+        )? {
             ConnectionStatus::Maintain => (),
             // if Disconnect or NextDemo, delegate up the chain
             s => return Ok(s),
         };
 
+        if let ConnectionKind::Server { .. } = self.kind {
+            if self.time_since_recv > msg_timeout {
+                console.println(format!(
+                    "Server connection timed out ({} seconds without a message)",
+                    self.time_since_recv.num_seconds()
+                ));
+                return Ok(ConnectionStatus::Disconnect);
+            }
+        }
+
         self.state.update_interp_ratio(cl_nolerp);
 
         // interpolate entity data and spawn particle effects, lights
@@ -770,9 +1174,11 @@ impl Connection {
         self.state.lights.update(self.state.time);
 
         // apply particle physics and remove expired particles
+        let particles_start = Instant::now();
         self.state
             .particles
             .update(self.state.time, frame_time, sv_gravity);
+        let particles_time = particles_start.elapsed();
 
         if let ConnectionKind::Server {
             ref mut qsock,
@@ -798,10 +1204,17 @@ impl Connection {
             // spatialize sounds for new ear positions
             self.state.update_sound_spatialization();
 
+            // fade ambient sounds toward the levels of the new leaf
+            self.state.update_ambient_sounds(ambient_vars, frame_time);
+
             // update camera color shifts for new position/effects
             self.state.update_color_shifts(frame_time)?;
         }
 
+        if let Some(ref mut timedemo) = self.timedemo {
+            timedemo.push_frame(frame_start.elapsed() - particles_time, particles_time);
+        }
+
         Ok(ConnectionStatus::Maintain)
     }
 }
@@ -812,12 +1225,16 @@ pub struct Client {
     cmds: Rc<RefCell<CmdRegistry>>,
     console: Rc<RefCell<Console>>,
     input: Rc<RefCell<Input>>,
-    _output_stream: OutputStream,
-    output_stream_handle: OutputStreamHandle,
+    mixer: Mixer,
     music_player: Rc<RefCell<MusicPlayer>>,
     conn: Rc<RefCell<Option<Connection>>>,
-    renderer: ClientRenderer,
-    demo_queue: Rc<RefCell<VecDeque<String>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+    last_server: Rc<RefCell<Option<String>>>,
+    renderer: Option<ClientRenderer>,
+    demo_playlist: Rc<RefCell<DemoPlaylist>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
+    timedemo_summary: Option<TimedemoSummary>,
+    rng_seed: Option<u64>,
 }
 
 impl Client {
@@ -830,14 +1247,70 @@ impl Client {
         gfx_state: &GraphicsState,
         menu: &Menu,
     ) -> Client {
-        let conn = Rc::new(RefCell::new(None));
-
-        let (stream, handle) = match OutputStream::try_default() {
-            Ok(o) => o,
-            // TODO: proceed without sound and allow configuration in menu
-            Err(_) => Err(ClientError::OutputStream).unwrap(),
+        // TODO: allow configuration in menu
+        let (backend, music_stream): (Box<dyn AudioBackend>, _) = match DeviceBackend::new() {
+            Ok(device) => {
+                let handle = device.handle();
+                (Box::new(device), Some(handle))
+            }
+            Err(e) => {
+                console
+                    .borrow()
+                    .println(format!("{}, running without sound", e));
+                (Box::new(NullBackend), None)
+            }
         };
 
+        Client::with_renderer(
+            vfs,
+            cvars,
+            cmds,
+            console,
+            input,
+            Some(ClientRenderer::new(gfx_state, menu)),
+            backend,
+            music_stream,
+        )
+    }
+
+    /// Creates a client that runs without graphics or sound.
+    ///
+    /// Pass `None` for the graphics state to [`Client::frame`]; [`Client::render`] does nothing.
+    pub fn headless(
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        input: Rc<RefCell<Input>>,
+    ) -> Client {
+        Client::with_renderer(
+            vfs,
+            cvars,
+            cmds,
+            console,
+            input,
+            None,
+            Box::new(NullBackend),
+            None,
+        )
+    }
+
+    fn with_renderer(
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        input: Rc<RefCell<Input>>,
+        renderer: Option<ClientRenderer>,
+        backend: Box<dyn AudioBackend>,
+        music_stream: Option<OutputStreamHandle>,
+    ) -> Client {
+        let conn = Rc::new(RefCell::new(None));
+        let connect_attempt = Rc::new(RefCell::new(None));
+        let last_server = Rc::new(RefCell::new(None));
+        let demo_recorder = Rc::new(RefCell::new(None));
+        let demo_playlist = Rc::new(RefCell::new(DemoPlaylist::new()));
+
         // set up overlay/ui toggles
         cmds.borrow_mut()
             .insert_or_replace(
@@ -853,39 +1326,134 @@ impl Client {
         cmds.borrow_mut()
             .insert_or_replace(
                 "connect",
-                cmd_connect(conn.clone(), input.clone(), handle.clone()),
+                cmd_connect(
+                    conn.clone(),
+                    connect_attempt.clone(),
+                    last_server.clone(),
+                    demo_recorder.clone(),
+                    input.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "reconnect",
+                cmd_reconnect(
+                    conn.clone(),
+                    connect_attempt.clone(),
+                    last_server.clone(),
+                    input.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "disconnect",
+                cmd_disconnect(
+                    conn.clone(),
+                    connect_attempt.clone(),
+                    demo_playlist.clone(),
+                    demo_recorder.clone(),
+                    input.clone(),
+                ),
             )
             .unwrap();
         cmds.borrow_mut()
-            .insert_or_replace("reconnect", cmd_reconnect(conn.clone(), input.clone()))
+            .insert_or_replace("netlog", cmd_netlog(conn.clone(), connect_attempt.clone()))
             .unwrap();
         cmds.borrow_mut()
-            .insert_or_replace("disconnect", cmd_disconnect(conn.clone(), input.clone()))
+            .insert_or_replace(
+                "host_writeconfig",
+                cmd_host_writeconfig(vfs.clone(), cvars.clone(), input.clone()),
+            )
             .unwrap();
 
         // set up demo playback
         cmds.borrow_mut()
             .insert_or_replace(
                 "playdemo",
-                cmd_playdemo(conn.clone(), vfs.clone(), input.clone(), handle.clone()),
+                cmd_playdemo(conn.clone(), vfs.clone(), input.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "timedemo",
+                cmd_timedemo(conn.clone(), vfs.clone(), input.clone()),
+            )
+            .unwrap();
+
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "record",
+                cmd_record(conn.clone(), vfs.clone(), demo_recorder.clone()),
             )
             .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("stop", cmd_stop(demo_recorder.clone()))
+            .unwrap();
 
-        let demo_queue = Rc::new(RefCell::new(VecDeque::new()));
         cmds.borrow_mut()
             .insert_or_replace(
                 "startdemos",
-                cmd_startdemos(
+                cmd_startdemos(conn.clone(), demo_playlist.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "demos",
+                cmd_demos(
                     conn.clone(),
-                    vfs.clone(),
+                    connect_attempt.clone(),
+                    demo_playlist.clone(),
+                    demo_recorder.clone(),
                     input.clone(),
-                    handle.clone(),
-                    demo_queue.clone(),
                 ),
             )
             .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "stopdemo",
+                cmd_stopdemo(conn.clone(), demo_playlist.clone(), input.clone()),
+            )
+            .unwrap();
+
+        // complete demo names from the virtual filesystem
+        for cmd in &["playdemo", "timedemo"] {
+            let vfs = vfs.clone();
+            console.borrow().register_completer(
+                cmd,
+                Box::new(move |args| match args.len() {
+                    0 => vfs.list("", "dem"),
+                    _ => Vec::new(),
+                }),
+            );
+        }
+        let demo_vfs = vfs.clone();
+        console
+            .borrow()
+            .register_completer("startdemos", Box::new(move |_| demo_vfs.list("", "dem")));
+
+        // set up demo transport controls
+        cmds.borrow_mut()
+            .insert_or_replace("demo_pause", cmd_demo_pause(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_seek", cmd_demo_seek(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_skip", cmd_demo_skip(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("missing_assets", cmd_missing_assets(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_faster", cmd_demo_timescale(cvars.clone(), 2.0))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_slower", cmd_demo_timescale(cvars.clone(), 0.5))
+            .unwrap();
 
-        let music_player = Rc::new(RefCell::new(MusicPlayer::new(vfs.clone(), handle.clone())));
+        let music_player = Rc::new(RefCell::new(MusicPlayer::new(vfs.clone(), music_stream)));
         cmds.borrow_mut()
             .insert_or_replace("music", cmd_music(music_player.clone()))
             .unwrap();
@@ -905,24 +1473,70 @@ impl Client {
             cmds,
             console,
             input,
-            _output_stream: stream,
-            output_stream_handle: handle,
+            mixer: Mixer::new(backend),
             music_player,
             conn,
-            renderer: ClientRenderer::new(gfx_state, menu),
-            demo_queue,
+            connect_attempt,
+            last_server,
+            renderer,
+            demo_playlist,
+            demo_recorder,
+            timedemo_summary: None,
+            rng_seed: None,
         }
     }
 
     pub fn disconnect(&mut self) {
-        self.conn.replace(None);
+        self.connect_attempt.replace(None);
+        if self.conn.replace(None).is_some() {
+            if let Some(msg) = stop_recording(&mut self.demo_recorder.borrow_mut()) {
+                self.console.borrow_mut().println(msg);
+            }
+        }
         self.input.borrow_mut().set_focus(InputFocus::Console);
     }
 
+    /// Advances a pending connection attempt, if there is one.
+    ///
+    /// Once the server accepts the connection, it replaces the current one. If the attempt fails,
+    /// the reason is printed to the console and the attempt is abandoned.
+    fn poll_connect_attempt(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        if self.connect_attempt.borrow().is_none() {
+            return Ok(());
+        }
+
+        let connect_vars = self.connect_vars()?;
+        let result = self.connect_attempt.borrow_mut().as_mut().unwrap().poll(
+            frame_time,
+            connect_vars,
+            &mut self.console.borrow_mut(),
+        );
+
+        match result {
+            Ok(None) => (),
+            Ok(Some(new_conn)) => {
+                self.connect_attempt.replace(None);
+                self.conn.replace(Some(new_conn));
+                self.input.borrow_mut().set_focus(InputFocus::Game);
+            }
+            Err(e) => {
+                let server = self.connect_attempt.replace(None).unwrap().server;
+                self.console
+                    .borrow_mut()
+                    .println(format!("Couldn't connect to {}: {}", server, e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Advances the client by `frame_time`.
+    ///
+    /// `gfx_state` may be `None` if the client is running without graphics.
     pub fn frame(
         &mut self,
         frame_time: Duration,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
     ) -> Result<(), ClientError> {
         let cl_nolerp = self.cvar_value("cl_nolerp")?;
         let sv_gravity = self.cvar_value("sv_gravity")?;
@@ -930,22 +1544,42 @@ impl Client {
         let kick_vars = self.kick_vars()?;
         let roll_vars = self.roll_vars()?;
         let bob_vars = self.bob_vars()?;
+        let ambient_vars = self.ambient_vars()?;
+        let dsp_vars = self.dsp_vars()?;
+        let music_vars = self.music_vars()?;
+        let volume = self.cvar_value("volume")?;
+        let snd_maxchannels = self.cvar_value("snd_maxchannels")? as usize;
+        let msg_timeout = engine::duration_from_f32(self.cvar_value("net_messagetimeout")?);
+        let demo_timescale = self.cvar_value("demo_timescale")?;
+
+        self.mixer.set_volume(volume);
+        self.music_player
+            .borrow_mut()
+            .update(frame_time, music_vars);
+        self.poll_connect_attempt(frame_time)?;
 
         let status = match *self.conn.borrow_mut() {
-            Some(ref mut conn) => conn.frame(
-                frame_time,
-                &self.vfs,
-                gfx_state,
-                &mut self.cmds.borrow_mut(),
-                &mut self.console.borrow_mut(),
-                &mut self.music_player.borrow_mut(),
-                idle_vars,
-                kick_vars,
-                roll_vars,
-                bob_vars,
-                cl_nolerp,
-                sv_gravity,
-            )?,
+            Some(ref mut conn) => {
+                conn.rng_seed = self.rng_seed;
+                conn.frame(
+                    conn.scale_frame_time(frame_time, demo_timescale),
+                    &self.vfs,
+                    gfx_state,
+                    &mut self.cmds.borrow_mut(),
+                    &mut self.console.borrow_mut(),
+                    &mut self.music_player.borrow_mut(),
+                    &mut self.demo_recorder.borrow_mut(),
+                    idle_vars,
+                    kick_vars,
+                    roll_vars,
+                    bob_vars,
+                    ambient_vars,
+                    snd_maxchannels,
+                    cl_nolerp,
+                    sv_gravity,
+                    msg_timeout,
+                )?
+            }
             None => ConnectionStatus::Disconnect,
         };
 
@@ -955,55 +1589,111 @@ impl Client {
             _ => {
                 let conn = match status {
                     // if client is already disconnected, this is a no-op
-                    Disconnect => None,
-
-                    // get the next demo from the queue
-                    NextDemo => match self.demo_queue.borrow_mut().pop_front() {
-                        Some(demo) => {
-                            let mut demo_file = match self.vfs.open(format!("{}.dem", demo)) {
-                                Ok(f) => Some(f),
-                                Err(e) => {
-                                    // log the error, dump the demo queue and disconnect
-                                    self.console.borrow_mut().println(format!("{}", e));
-                                    self.demo_queue.borrow_mut().clear();
-                                    None
-                                }
-                            };
-
-                            demo_file.as_mut().and_then(|df| match DemoServer::new(df) {
-                                Ok(d) => Some(Connection {
-                                    kind: ConnectionKind::Demo(d),
-                                    state: ClientState::new(self.output_stream_handle.clone()),
-                                    conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
-                                }),
-                                Err(e) => {
-                                    self.console.borrow_mut().println(format!("{}", e));
-                                    self.demo_queue.borrow_mut().clear();
-                                    None
-                                }
-                            })
+                    Disconnect => {
+                        if self.conn.borrow().is_some() {
+                            if let Some(msg) = stop_recording(&mut self.demo_recorder.borrow_mut())
+                            {
+                                self.console.borrow_mut().println(msg);
+                            }
                         }
 
-                        // if there are no more demos in the queue, disconnect
-                        None => None,
-                    },
+                        None
+                    }
+
+                    // play the next demo in the loop, if it's running
+                    NextDemo => self.next_demo(),
 
                     // covered in first match
                     Maintain => unreachable!(),
                 };
 
-                match conn {
-                    Some(_) => self.input.borrow_mut().set_focus(InputFocus::Game),
+                let timedemo = self
+                    .conn
+                    .borrow_mut()
+                    .as_mut()
+                    .and_then(|c| c.timedemo.take());
+                if let Some(timedemo) = timedemo {
+                    let summary = timedemo.finish();
+                    self.console.borrow().println(summary.to_string());
+                    self.timedemo_summary = Some(summary);
+                }
 
+                // the next demo in the loop keeps the focus of the last one
+                let was_connected = self.conn.borrow().is_some();
+                if conn.is_none() && was_connected {
                     // don't allow game focus when disconnected
-                    None => self.input.borrow_mut().set_focus(InputFocus::Console),
+                    self.input.borrow_mut().set_focus(InputFocus::Console);
                 }
 
                 self.conn.replace(conn);
             }
         }
 
-        Ok(())
+        // when idle, play the demo loop behind the main menu like the original title screen
+        if self.conn.borrow().is_none() && self.connect_attempt.borrow().is_none() {
+            if let Some(conn) = self.next_demo() {
+                self.conn.replace(Some(conn));
+                self.input.borrow_mut().set_focus(InputFocus::Menu);
+            }
+        }
+
+        // mix even while signing on or in the menus, so sounds play out and the device is fed
+        self.update_sound(frame_time, demo_timescale, dsp_vars);
+
+        // the demo transport controls are only bound during playback
+        let demo_playing = matches!(
+            *self.conn.borrow(),
+            Some(Connection {
+                kind: ConnectionKind::Demo(_),
+                ..
+            })
+        );
+        self.input.borrow_mut().set_demo_playing(demo_playing);
+
+        Ok(())
+    }
+
+    /// Mixes sound for `frame_time` and sends it to the audio backend.
+    ///
+    /// If the backend fails, sound is disabled rather than ending the game.
+    fn update_sound(&mut self, frame_time: Duration, demo_timescale: f32, dsp_vars: DspVars) {
+        let result = match *self.conn.borrow_mut() {
+            Some(ref mut conn) => {
+                let frame_time = conn.scale_frame_time(frame_time, demo_timescale);
+                let state = &mut conn.state;
+                self.mixer
+                    .update(frame_time, |out| state.paint_sound(out, dsp_vars))
+            }
+
+            // nothing is playing, but the backend still gets silence
+            None => self.mixer.update(frame_time, |_| ()),
+        };
+
+        if let Err(e) = result {
+            error!("Sound output failed: {}", e);
+            self.console
+                .borrow()
+                .println(format!("Sound output failed, disabling sound: {}", e));
+            self.mixer.set_backend(Box::new(NullBackend));
+        }
+    }
+
+    /// Opens the next demo in the demo loop.
+    ///
+    /// Returns `None` if the loop is stopped. If the demo can't be played, the loop is stopped so
+    /// it isn't retried every frame.
+    fn next_demo(&mut self) -> Option<Connection> {
+        let demo = self.demo_playlist.borrow_mut().next_demo()?;
+        match open_demo(&self.vfs, &demo) {
+            Ok(demo_server) => Some(Connection::new(ConnectionKind::Demo(demo_server))),
+            Err(e) => {
+                self.console
+                    .borrow_mut()
+                    .println(format!("Couldn't play demo {}: {}", demo, e));
+                self.demo_playlist.borrow_mut().stop();
+                None
+            }
+        }
     }
 
     pub fn render(
@@ -1016,13 +1706,24 @@ impl Client {
         focus: InputFocus,
     ) -> Result<(), ClientError> {
         let fov = Deg(self.cvar_value("fov")?);
+        let renderer = match self.renderer {
+            Some(ref mut r) => r,
+            None => return Ok(()),
+        };
+
         let cvars = self.cvars.borrow();
         let console = self.console.borrow();
+        let connect_status = self
+            .connect_attempt
+            .borrow()
+            .as_ref()
+            .map(|attempt| attempt.status());
 
-        self.renderer.render(
+        renderer.render(
             gfx_state,
             encoder,
             self.conn.borrow().as_ref(),
+            connect_status.as_deref(),
             width,
             height,
             fov,
@@ -1057,24 +1758,94 @@ impl Client {
             Some(Connection {
                 ref mut state,
                 kind: ConnectionKind::Server { ref mut qsock, .. },
+                ref mut msg_log,
                 ..
             }) => {
                 let move_cmd = state.handle_input(game_input, frame_time, move_vars, mouse_vars);
                 // TODO: arrayvec here
                 let mut msg = Vec::new();
                 move_cmd.serialize(&mut msg)?;
+                write_msg_log(msg_log, |log| log.log_client_cmd(&move_cmd, &msg));
                 qsock.send_msg_unreliable(&msg)?;
 
                 // clear mouse and impulse
                 game_input.refresh();
             }
 
+            Some(Connection {
+                ref mut state,
+                kind: ConnectionKind::Demo(_),
+                timedemo: Some(ref mut timedemo),
+                ..
+            }) => {
+                // demos don't take commands, but generate one anyway so it's measured
+                let start = Instant::now();
+                let move_cmd = state.handle_input(game_input, frame_time, move_vars, mouse_vars);
+                let mut msg = Vec::new();
+                move_cmd.serialize(&mut msg)?;
+                timedemo.record_commands(start.elapsed());
+
+                game_input.refresh();
+            }
+
             _ => (),
         }
 
         Ok(())
     }
 
+    /// Returns the results of the most recently completed timedemo, if there are any.
+    pub fn take_timedemo_summary(&mut self) -> Option<TimedemoSummary> {
+        self.timedemo_summary.take()
+    }
+
+    /// Returns whether the client is connected to a server or playing a demo.
+    pub fn connected(&self) -> bool {
+        self.conn.borrow().is_some()
+    }
+
+    /// Replaces the backend that mixed sound is sent to.
+    ///
+    /// This can be used to capture sound with a [`WavBackend`](sound::WavBackend).
+    pub fn set_audio_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.mixer.set_backend(backend);
+    }
+
+    /// Seeds the random number generator of each new level, making the client deterministic.
+    ///
+    /// If `seed` is `None`, the generator is seeded from system entropy.
+    pub fn set_rng_seed(&mut self, seed: Option<u64>) {
+        self.rng_seed = seed;
+    }
+
+    /// Returns whether the client has finished signing on to the server or demo.
+    pub fn signed_on(&self) -> bool {
+        match *self.conn.borrow() {
+            Some(Connection {
+                conn_state: ConnectionState::Connected(_),
+                ..
+            }) => true,
+            _ => false,
+        }
+    }
+
+    /// Applies `f` to the state of the current connection, if there is one.
+    pub fn with_state<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&ClientState) -> R,
+    {
+        self.conn.borrow().as_ref().map(|conn| f(&conn.state))
+    }
+
+    fn connect_vars(&self) -> Result<ConnectVars, ClientError> {
+        Ok(ConnectVars {
+            cl_connect_attempts: self.cvar_value("cl_connect_attempts")?.max(1.0) as usize,
+            cl_connect_timeout: engine::duration_from_f32(
+                self.cvar_value("cl_connect_timeout")?.max(0.0),
+            ),
+        })
+    }
+
     fn move_vars(&self) -> Result<MoveVars, ClientError> {
         Ok(MoveVars {
             cl_anglespeedkey: self.cvar_value("cl_anglespeedkey")?,
@@ -1131,6 +1902,27 @@ impl Client {
         })
     }
 
+    fn ambient_vars(&self) -> Result<AmbientVars, ClientError> {
+        Ok(AmbientVars {
+            ambient_level: self.cvar_value("ambient_level")?,
+            ambient_fade: self.cvar_value("ambient_fade")?,
+        })
+    }
+
+    fn dsp_vars(&self) -> Result<DspVars, ClientError> {
+        Ok(DspVars {
+            snd_waterfx: self.cvar_value("snd_waterfx")?,
+            snd_reverb: self.cvar_value("snd_reverb")?,
+        })
+    }
+
+    fn music_vars(&self) -> Result<MusicVars, ClientError> {
+        Ok(MusicVars {
+            bgmvolume: self.cvar_value("bgmvolume")?,
+            bgm_crossfade: self.cvar_value("bgm_crossfade")?,
+        })
+    }
+
     pub fn view_entity_id(&self) -> Option<usize> {
         match *self.conn.borrow() {
             Some(Connection { ref state, .. }) => Some(state.view_entity_id()),
@@ -1242,55 +2034,173 @@ fn cmd_togglemenu(
     })
 }
 
-fn connect<A>(server_addrs: A, stream: OutputStreamHandle) -> Result<Connection, ClientError>
-where
-    A: ToSocketAddrs,
-{
-    let mut con_sock = ConnectSocket::bind("0.0.0.0:0")?;
-    let server_addr = match server_addrs.to_socket_addrs() {
-        Ok(ref mut a) => a.next().ok_or(ClientError::InvalidServerAddress),
-        Err(_) => Err(ClientError::InvalidServerAddress),
-    }?;
-
-    let mut response = None;
-
-    for attempt in 0..MAX_CONNECT_ATTEMPTS {
-        println!(
-            "Connecting...(attempt {} of {})",
-            attempt + 1,
-            MAX_CONNECT_ATTEMPTS
-        );
-        con_sock.send_request(
-            Request::connect(net::GAME_NAME, CONNECT_PROTOCOL_VERSION),
-            server_addr,
-        )?;
-
-        // TODO: get rid of magic constant (2.5 seconds wait time for response)
-        match con_sock.recv_response(Some(Duration::milliseconds(2500))) {
-            Err(err) => {
-                match err {
-                    // if the message is invalid, log it but don't quit
-                    // TODO: this should probably disconnect
-                    NetError::InvalidData(msg) => error!("{}", msg),
+/// An in-progress attempt to connect to a server.
+///
+/// Connection requests are sent without blocking; `poll` is called once per frame to check for a
+/// response and resend the request when it times out. A hostname is resolved on another thread,
+/// and nothing is sent until it has been resolved. Like the original engine, which tries
+/// three times (see
+/// https://github.com/id-Software/Quake/blob/master/WinQuake/net_dgrm.c#L1248), the number of
+/// requests sent to each address is limited by `cl_connect_attempts`.
+struct ConnectAttempt {
+    /// The server address as given by the user.
+    server: String,
 
-                    // other errors are fatal
-                    e => return Err(e.into()),
+    /// The resolved addresses that have not been tried yet, starting with the current one.
+    addrs: VecDeque<SocketAddr>,
+
+    /// If the server's hostname is still being resolved, the addresses will arrive here.
+    resolving: Option<Receiver<Result<Vec<SocketAddr>, NetError>>>,
+
+    /// The socket used to contact the current address.
+    con_sock: Option<ConnectSocket>,
+
+    /// The number of requests sent to the current address.
+    requests_sent: usize,
+
+    /// Time elapsed since the last request was sent.
+    since_request: Duration,
+
+    /// A message log to attach to the connection once it's established.
+    msg_log: Option<MessageLog<BufWriter<File>>>,
+}
+
+impl ConnectAttempt {
+    /// Begins connecting to a server given as a hostname or IP address, optionally with a port.
+    ///
+    /// If the name resolves to several addresses, each is tried in turn until one responds.
+    fn new<S>(server: S) -> Result<ConnectAttempt, ClientError>
+    where
+        S: AsRef<str>,
+    {
+        let (addrs, resolving) = match resolve_server_addr_async(server.as_ref())? {
+            ResolveServerAddr::Done(addrs) => (addrs.into_iter().collect(), None),
+            ResolveServerAddr::Pending(rx) => (VecDeque::new(), Some(rx)),
+        };
+
+        Ok(ConnectAttempt {
+            server: server.as_ref().to_owned(),
+            addrs,
+            resolving,
+            con_sock: None,
+            requests_sent: 0,
+            since_request: Duration::zero(),
+            msg_log: None,
+        })
+    }
+
+    /// Returns a short description of the attempt's progress for the loading screen.
+    fn status(&self) -> String {
+        match self.addrs.front() {
+            Some(addr) if self.requests_sent > 1 => {
+                format!("Connecting to {} (attempt {})", addr, self.requests_sent)
+            }
+            _ => format!("Connecting to {}", self.server),
+        }
+    }
+
+    /// Checks whether the server's hostname has been resolved, returning `true` once it has.
+    fn poll_resolve(&mut self) -> Result<bool, ClientError> {
+        let addrs = match self.resolving {
+            None => return Ok(true),
+            Some(ref resolving) => match resolving.try_recv() {
+                Ok(result) => result?,
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(
+                        NetError::Resolve(format!("{}: resolver failed", self.server)).into(),
+                    )
                 }
+            },
+        };
+
+        self.addrs = addrs.into_iter().collect();
+        self.resolving = None;
+        Ok(true)
+    }
+
+    /// Advances the attempt by one frame.
+    ///
+    /// Returns the new connection once the server accepts it, or `None` if it is still pending.
+    fn poll(
+        &mut self,
+        frame_time: Duration,
+        connect_vars: ConnectVars,
+        console: &mut Console,
+    ) -> Result<Option<Connection>, ClientError> {
+        self.since_request = self.since_request + frame_time;
+
+        if !self.poll_resolve()? {
+            return Ok(None);
+        }
+
+        loop {
+            let server_addr = *self.addrs.front().ok_or(ClientError::NoResponse)?;
+
+            if self.con_sock.is_none() {
+                self.con_sock = Some(ConnectSocket::bind_for(server_addr)?);
+                self.requests_sent = 0;
             }
 
-            Ok(opt) => {
-                if let Some((resp, remote)) = opt {
+            let con_sock = self.con_sock.as_mut().unwrap();
+
+            if self.requests_sent > 0 {
+                match con_sock.recv_response(BlockingMode::NonBlocking) {
                     // if this response came from the right server, we're done
-                    if remote == server_addr {
-                        response = Some(resp);
-                        break;
+                    Ok(Some((response, remote))) if remote == server_addr => {
+                        let con_sock = self.con_sock.take().unwrap();
+                        let mut conn = accept_connection(con_sock, server_addr, response)?;
+                        conn.msg_log = self.msg_log.take();
+                        return Ok(Some(conn));
                     }
+
+                    Ok(Some((_, remote))) => debug!("Ignoring response from {}", remote),
+
+                    Ok(None) => (),
+
+                    // if the message is invalid, log it but keep waiting
+                    Err(NetError::InvalidData(msg)) => error!("{}", msg),
+
+                    // other errors are fatal
+                    Err(e) => return Err(e.into()),
+                }
+
+                if self.since_request < connect_vars.cl_connect_timeout {
+                    return Ok(None);
                 }
             }
+
+            if self.requests_sent >= connect_vars.cl_connect_attempts {
+                // this address isn't responding, move on to the next one
+                console.println(format!("No response from {}", server_addr));
+                self.addrs.pop_front();
+                self.con_sock = None;
+                continue;
+            }
+
+            self.requests_sent += 1;
+            console.println(format!(
+                "Connecting to {}...(attempt {} of {})",
+                server_addr, self.requests_sent, connect_vars.cl_connect_attempts
+            ));
+            con_sock.send_request(
+                Request::connect(net::GAME_NAME, CONNECT_PROTOCOL_VERSION),
+                server_addr,
+            )?;
+            self.since_request = Duration::zero();
+
+            return Ok(None);
         }
     }
+}
 
-    let port = match response.ok_or(ClientError::NoResponse)? {
+/// Handles the server's response to a connection request.
+fn accept_connection(
+    con_sock: ConnectSocket,
+    server_addr: SocketAddr,
+    response: Response,
+) -> Result<Connection, ClientError> {
+    let port = match response {
         Response::Accept(accept) => {
             // validate port number
             if accept.port < 0 || accept.port >= std::u16::MAX as i32 {
@@ -1315,71 +2225,93 @@ where
     // we're done with the connection socket, so turn it into a QSocket with the new address
     let qsock = con_sock.into_qsocket(new_addr);
 
-    Ok(Connection {
-        state: ClientState::new(stream),
-        kind: ConnectionKind::Server {
-            qsock,
-            compose: Vec::new(),
-        },
-        conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
-    })
+    Ok(Connection::new(ConnectionKind::Server {
+        qsock,
+        compose: Vec::new(),
+    }))
 }
 
-// TODO: when an audio device goes down, every command with an
-// OutputStreamHandle needs to be reconstructed so it doesn't pass out
-// references to a dead output stream
-
-// TODO: this will hang while connecting. ideally, input should be handled in a
-// separate thread so the OS doesn't think the client has gone unresponsive.
 fn cmd_connect(
     conn: Rc<RefCell<Option<Connection>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+    last_server: Rc<RefCell<Option<String>>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
     input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() < 1 {
             // TODO: print to console
-            return "usage: connect <server_ip>:<server_port>".to_owned();
+            return "usage: connect <server>[:<port>]".to_owned();
         }
 
-        match connect(args[0], stream.clone()) {
-            Ok(new_conn) => {
-                conn.replace(Some(new_conn));
-                input.borrow_mut().set_focus(InputFocus::Game);
-                String::new()
+        match ConnectAttempt::new(args[0]) {
+            Ok(attempt) => {
+                // drop any existing connection before connecting to the new server. a demo
+                // started before connecting keeps recording, but one of the old server stops
+                let mut output = String::new();
+                if conn.replace(None).is_some() {
+                    output = stop_recording(&mut demo_recorder.borrow_mut()).unwrap_or_default();
+                }
+                connect_attempt.replace(Some(attempt));
+                last_server.replace(Some(args[0].to_owned()));
+                if input.borrow().focus() == InputFocus::Game {
+                    input.borrow_mut().set_focus(InputFocus::Console);
+                }
+                output
             }
-            Err(e) => format!("{}", e),
+            Err(e) => format!("Couldn't connect to {}: {}", args[0], e),
         }
     })
 }
 
 fn cmd_reconnect(
     conn: Rc<RefCell<Option<Connection>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+    last_server: Rc<RefCell<Option<String>>>,
     input: Rc<RefCell<Input>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |_| {
-        match *conn.borrow_mut() {
-            Some(ref mut conn) => {
-                // TODO: clear client state
-                conn.conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
-                input.borrow_mut().set_focus(InputFocus::Game);
+        if let Some(ref mut conn) = *conn.borrow_mut() {
+            // the server sends this on level change, so sign on again over the same connection
+            conn.reset();
+            input.borrow_mut().set_focus(InputFocus::Game);
+            return String::new();
+        }
+
+        // if we're not connected, start over with the last server we tried
+        let server = match *last_server.borrow() {
+            Some(ref s) => s.clone(),
+            None => return "no server to reconnect to".to_owned(),
+        };
+
+        match ConnectAttempt::new(&server) {
+            Ok(attempt) => {
+                connect_attempt.replace(Some(attempt));
                 String::new()
             }
-            // TODO: log message, e.g. "can't reconnect while disconnected"
-            None => "not connected".to_string(),
+            Err(e) => format!("Couldn't connect to {}: {}", server, e),
         }
     })
 }
 
 fn cmd_disconnect(
     conn: Rc<RefCell<Option<Connection>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+    demo_playlist: Rc<RefCell<DemoPlaylist>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
     input: Rc<RefCell<Input>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |_| {
+        let connecting = connect_attempt.replace(None).is_some();
         let connected = conn.borrow().is_some();
         if connected {
-            conn.replace(None);
+            // leaving a demo stops the demo loop, but leaving a server lets it resume
+            if let Some(ConnectionKind::Demo(_)) = conn.replace(None).map(|c| c.kind) {
+                demo_playlist.borrow_mut().stop();
+            }
             input.borrow_mut().set_focus(InputFocus::Console);
+            stop_recording(&mut demo_recorder.borrow_mut()).unwrap_or_default()
+        } else if connecting {
             String::new()
         } else {
             "not connected".to_string()
@@ -1387,86 +2319,368 @@ fn cmd_disconnect(
     })
 }
 
+// implements the "host_writeconfig" command
+fn cmd_host_writeconfig(
+    vfs: Rc<Vfs>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+    input: Rc<RefCell<Input>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(
+        move |_| match config::save_config(&vfs, &cvars.borrow(), &input.borrow()) {
+            Ok(path) => format!("Wrote {}", path.display()),
+            Err(e) => format!("Couldn't write {}: {}", config::CONFIG_FILE, e),
+        },
+    )
+}
+
+// implements the "netlog" command
+fn cmd_netlog(
+    conn: Rc<RefCell<Option<Connection>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let mut conn = conn.borrow_mut();
+        let mut connect_attempt = connect_attempt.borrow_mut();
+
+        // if a connection is still being established, the log is attached once it's accepted
+        let msg_log = match (conn.as_mut(), connect_attempt.as_mut()) {
+            (Some(conn), _) => &mut conn.msg_log,
+            (None, Some(attempt)) => &mut attempt.msg_log,
+            (None, None) => return "not connected".to_owned(),
+        };
+
+        match args.len() {
+            // netlog: stop logging
+            0 => match msg_log.take() {
+                Some(_) => "Message log closed".to_owned(),
+                None => "usage: netlog [FILE]".to_owned(),
+            },
+
+            // netlog (filename): log all commands on this connection to a file
+            1 => match MessageLog::create(args[0]) {
+                Ok(log) => {
+                    *msg_log = Some(log);
+                    format!("Logging messages to {}", args[0])
+                }
+                Err(e) => format!("Couldn't open message log {}: {}", args[0], e),
+            },
+
+            _ => "usage: netlog [FILE]".to_owned(),
+        }
+    })
+}
+
+// implements the "record" command
+fn cmd_record(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        // TODO: accept a map once there's a local server to start it on
+        if args.len() == 2 {
+            return "Can't change maps without a local server. Connect, then record <demoname>."
+                .to_owned();
+        }
+
+        if args.len() != 1 {
+            return "usage: record <demoname>".to_owned();
+        }
+
+        if args[0].contains("..") {
+            return "Relative pathnames are not allowed.".to_owned();
+        }
+
+        if demo_recorder.borrow().is_some() {
+            return "Already recording a demo. Use \"stop\" to finish it.".to_owned();
+        }
+
+        if let Some(Connection {
+            kind: ConnectionKind::Demo(_),
+            ..
+        }) = *conn.borrow()
+        {
+            return "Can't record during demo playback.".to_owned();
+        }
+
+        let mut path = match vfs.game_dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => return "No game directory to record to.".to_owned(),
+        };
+        path.push(args[0]);
+        if path.extension().is_none() {
+            path.set_extension("dem");
+        }
+
+        let mut recorder = match DemoRecorder::create(&path, None) {
+            Ok(r) => r,
+            Err(e) => return format!("Couldn't create {}: {}", path.display(), e),
+        };
+
+        // if we're already in a game, the demo has to start with the state we've built up
+        if let Some(ref conn) = *conn.borrow() {
+            if let Err(e) = conn.write_demo_signon(&mut recorder) {
+                return format!("Couldn't record {}: {}", path.display(), e);
+            }
+        }
+
+        demo_recorder.replace(Some(recorder));
+        format!("Recording to {}.", path.display())
+    })
+}
+
+// implements the "stop" command
+fn cmd_stop(
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(
+        move |_| match stop_recording(&mut demo_recorder.borrow_mut()) {
+            Some(msg) => msg,
+            None => "Not recording a demo.".to_owned(),
+        },
+    )
+}
+
+/// Opens `<name>.dem` for playback.
+fn open_demo(vfs: &Vfs, name: &str) -> Result<DemoServer, ClientError> {
+    let mut demo_file = vfs.open(format!("{}.dem", name))?;
+    Ok(DemoServer::new(&mut demo_file)?)
+}
+
 fn cmd_playdemo(
     conn: Rc<RefCell<Option<Connection>>>,
     vfs: Rc<Vfs>,
     input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() != 1 {
             return "usage: playdemo [DEMOFILE]".to_owned();
         }
 
-        let mut demo_file = match vfs.open(format!("{}.dem", args[0])) {
-            Ok(f) => f,
+        let demo_server = match open_demo(&vfs, args[0]) {
+            Ok(d) => d,
             Err(e) => return format!("{}", e),
         };
 
-        let demo_server = match DemoServer::new(&mut demo_file) {
+        conn.replace(Some(Connection::new(ConnectionKind::Demo(demo_server))));
+
+        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
+
+fn cmd_timedemo(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    input: Rc<RefCell<Input>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: timedemo [DEMOFILE]".to_owned();
+        }
+
+        let demo_server = match open_demo(&vfs, args[0]) {
             Ok(d) => d,
             Err(e) => return format!("{}", e),
         };
 
-        conn.replace(Some(Connection {
-            state: ClientState::new(stream.clone()),
-            kind: ConnectionKind::Demo(demo_server),
-            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
-        }));
+        let mut new_conn = Connection::new(ConnectionKind::Demo(demo_server));
+        new_conn.timedemo = Some(Timedemo::new());
+        conn.replace(Some(new_conn));
 
         input.borrow_mut().set_focus(InputFocus::Game);
         String::new()
     })
 }
 
+// implements the "startdemos" command
 fn cmd_startdemos(
     conn: Rc<RefCell<Option<Connection>>>,
-    vfs: Rc<Vfs>,
-    input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
-    demo_queue: Rc<RefCell<VecDeque<String>>>,
+    demo_playlist: Rc<RefCell<DemoPlaylist>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() == 0 {
             return "usage: startdemos [DEMOS]".to_owned();
         }
 
-        for arg in args {
-            demo_queue.borrow_mut().push_back(arg.to_string());
+        let mut output = String::new();
+        if args.len() > demo::MAX_DEMOS {
+            output = format!("Max {} demos in demoloop\n", demo::MAX_DEMOS);
         }
 
-        let mut demo_file = match vfs.open(format!(
-            "{}.dem",
-            demo_queue.borrow_mut().pop_front().unwrap()
-        )) {
-            Ok(f) => f,
-            Err(e) => return format!("{}", e),
-        };
+        // like the original, the loop only starts right away if the client is idle. it is
+        // played on the next frame
+        let idle = conn.borrow().is_none();
+        let mut playlist = demo_playlist.borrow_mut();
+        playlist.set_demos(args, idle);
+        output.push_str(&format!("{} demo(s) in loop", playlist.demos().len()));
+        output
+    })
+}
 
-        let demo_server = match DemoServer::new(&mut demo_file) {
-            Ok(d) => d,
-            Err(e) => return format!("{}", e),
-        };
+// implements the "demos" command
+fn cmd_demos(
+    conn: Rc<RefCell<Option<Connection>>>,
+    connect_attempt: Rc<RefCell<Option<ConnectAttempt>>>,
+    demo_playlist: Rc<RefCell<DemoPlaylist>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder<BufWriter<File>>>>>,
+    input: Rc<RefCell<Input>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        if !demo_playlist.borrow_mut().resume() {
+            return "No demos in loop".to_owned();
+        }
 
-        conn.replace(Some(Connection {
-            state: ClientState::new(stream.clone()),
-            kind: ConnectionKind::Demo(demo_server),
-            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
-        }));
+        // drop the current connection. the next demo in the loop starts on the next frame
+        connect_attempt.replace(None);
+        if conn.replace(None).is_some() {
+            input.borrow_mut().set_focus(InputFocus::Console);
+            return stop_recording(&mut demo_recorder.borrow_mut()).unwrap_or_default();
+        }
 
-        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
 
+// implements the "stopdemo" command
+fn cmd_stopdemo(
+    conn: Rc<RefCell<Option<Connection>>>,
+    demo_playlist: Rc<RefCell<DemoPlaylist>>,
+    input: Rc<RefCell<Input>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let playing = matches!(
+            *conn.borrow(),
+            Some(Connection {
+                kind: ConnectionKind::Demo(_),
+                ..
+            })
+        );
+
+        if !playing {
+            return "Not playing a demo.".to_owned();
+        }
+
+        demo_playlist.borrow_mut().stop();
+        conn.replace(None);
+        input.borrow_mut().set_focus(InputFocus::Console);
         String::new()
     })
 }
 
-fn cmd_music(music_player: Rc<RefCell<MusicPlayer>>) -> Box<dyn Fn(&[&str]) -> String> {
+/// Applies `f` to the demo server if a demo is playing.
+fn with_demo_server<F>(conn: &RefCell<Option<Connection>>, f: F) -> String
+where
+    F: FnOnce(&mut DemoServer) -> String,
+{
+    match *conn.borrow_mut() {
+        Some(Connection {
+            kind: ConnectionKind::Demo(ref mut demo_srv),
+            ..
+        }) => f(demo_srv),
+        _ => "Not playing a demo.".to_owned(),
+    }
+}
+
+// implements the "demo_pause" command
+fn cmd_demo_pause(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        with_demo_server(&conn, |demo_srv| {
+            let paused = !demo_srv.paused();
+            demo_srv.set_paused(paused);
+            if paused {
+                "Demo paused".to_owned()
+            } else {
+                "Demo resumed".to_owned()
+            }
+        })
+    })
+}
+
+// implements the "demo_seek" command
+fn cmd_demo_seek(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        with_demo_server(&conn, |demo_srv| match args.len() {
+            // demo_seek: show the current position
+            0 => format!(
+                "Demo position: {:.1}s of {:.1}s",
+                engine::duration_to_f32(demo_srv.time()),
+                engine::duration_to_f32(demo_srv.duration())
+            ),
+
+            // demo_seek (seconds): jump to a position on the demo timeline
+            1 => match args[0].parse::<f32>() {
+                Ok(secs) => {
+                    demo_srv.request_seek(engine::duration_from_f32(secs));
+                    String::new()
+                }
+                Err(_) => "usage: demo_seek [seconds]".to_owned(),
+            },
+
+            _ => "usage: demo_seek [seconds]".to_owned(),
+        })
+    })
+}
+
+// implements the "demo_skip" command
+fn cmd_demo_skip(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() != 1 {
-            return "usage: music [TRACKNAME]".to_owned();
+            return "usage: demo_skip <seconds>".to_owned();
         }
 
-        let res = music_player.borrow_mut().play_named(args[0]);
+        let secs = match args[0].parse::<f32>() {
+            Ok(s) => s,
+            Err(_) => return "usage: demo_skip <seconds>".to_owned(),
+        };
+
+        with_demo_server(&conn, |demo_srv| {
+            // skips made before the last one is carried out add up
+            let from = demo_srv.seek_request().unwrap_or_else(|| demo_srv.time());
+            demo_srv.request_seek(from + engine::duration_from_f32(secs));
+            String::new()
+        })
+    })
+}
+
+// implements the "demo_faster" and "demo_slower" commands
+fn cmd_demo_timescale(
+    cvars: Rc<RefCell<CvarRegistry>>,
+    factor: f32,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let cvars = cvars.borrow();
+        let timescale = match cvars.get_value("demo_timescale") {
+            Ok(t) => t,
+            Err(e) => return format!("{}", e),
+        };
+
+        let new_timescale = (timescale * factor).max(1.0 / 16.0).min(16.0);
+        match cvars.set("demo_timescale", new_timescale.to_string().as_str()) {
+            Ok(()) => format!("Demo timescale: {}", new_timescale),
+            Err(e) => format!("{}", e),
+        }
+    })
+}
+
+// implements the "missing_assets" command
+fn cmd_missing_assets(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match *conn.borrow() {
+        Some(ref conn) => conn.state.missing_assets.to_string(),
+        None => "not connected".to_owned(),
+    })
+}
+
+fn cmd_music(music_player: Rc<RefCell<MusicPlayer>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let mode = match args {
+            [_] => PlayMode::Loop,
+            [_, "loop"] => PlayMode::Loop,
+            [_, "once"] => PlayMode::Once,
+            _ => return "usage: music [TRACKNAME] [loop|once]".to_owned(),
+        };
+
+        let res = music_player.borrow_mut().play_named(args[0], mode);
         match res {
             Ok(()) => String::new(),
             Err(e) => {
@@ -1497,3 +2711,153 @@ fn cmd_music_resume(music_player: Rc<RefCell<MusicPlayer>>) -> Box<dyn Fn(&[&str
         String::new()
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    use crate::common::net::connect::{ConnectListener, ResponseAccept};
+
+    fn connect_vars(attempts: usize) -> ConnectVars {
+        ConnectVars {
+            cl_connect_attempts: attempts,
+            cl_connect_timeout: Duration::seconds(1),
+        }
+    }
+
+    fn console() -> Console {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        Console::new(cmds, cvars)
+    }
+
+    // waits long enough for a datagram sent over loopback to arrive
+    fn settle() {
+        thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    // returns the number of requests waiting at the listener and the address they came from
+    fn recv_requests(listener: &mut ConnectListener) -> (usize, Option<SocketAddr>) {
+        settle();
+        let mut count = 0;
+        let mut from = None;
+        while let Some((_, remote)) = listener.recv_request(BlockingMode::NonBlocking).unwrap() {
+            count += 1;
+            from = Some(remote);
+        }
+
+        (count, from)
+    }
+
+    #[test]
+    fn test_connect_attempt_retry() {
+        let mut server = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let mut console = console();
+        let mut attempt = ConnectAttempt::new(server_addr.to_string()).unwrap();
+
+        // the first request goes out immediately
+        assert!(attempt
+            .poll(Duration::zero(), connect_vars(3), &mut console)
+            .unwrap()
+            .is_none());
+        assert_eq!(recv_requests(&mut server).0, 1);
+
+        // nothing is resent until cl_connect_timeout has passed
+        assert!(attempt
+            .poll(Duration::milliseconds(600), connect_vars(3), &mut console)
+            .unwrap()
+            .is_none());
+        assert_eq!(recv_requests(&mut server).0, 0);
+
+        assert!(attempt
+            .poll(Duration::milliseconds(600), connect_vars(3), &mut console)
+            .unwrap()
+            .is_none());
+        assert_eq!(recv_requests(&mut server).0, 1);
+        assert_eq!(attempt.requests_sent, 2);
+    }
+
+    #[test]
+    fn test_connect_attempt_timeout() {
+        let mut server = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let mut console = console();
+        let mut attempt = ConnectAttempt::new(server_addr.to_string()).unwrap();
+
+        for _ in 0..2 {
+            assert!(attempt
+                .poll(Duration::seconds(1), connect_vars(2), &mut console)
+                .unwrap()
+                .is_none());
+        }
+        assert_eq!(recv_requests(&mut server).0, 2);
+
+        // the last request times out and there are no more addresses to try
+        match attempt.poll(Duration::seconds(1), connect_vars(2), &mut console) {
+            Err(ClientError::NoResponse) => (),
+            x => panic!("expected NoResponse, got {:?}", x.map(|c| c.is_some())),
+        }
+        assert_eq!(recv_requests(&mut server).0, 0);
+    }
+
+    #[test]
+    fn test_connect_attempt_resolve_hostname() {
+        let mut attempt = ConnectAttempt::new("localhost:27500").unwrap();
+
+        // the lookup happens on another thread, so nothing is known yet
+        assert!(attempt.resolving.is_some());
+        assert!(attempt.addrs.is_empty());
+
+        let mut resolved = false;
+        for _ in 0..200 {
+            resolved = attempt.poll_resolve().unwrap();
+            if resolved {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        assert!(resolved);
+        assert!(!attempt.addrs.is_empty());
+        for addr in attempt.addrs.iter() {
+            assert!(addr.ip().is_loopback());
+            assert_eq!(addr.port(), 27500);
+        }
+    }
+
+    #[test]
+    fn test_connect_attempt_wrong_address() {
+        let mut server = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let impostor = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let mut console = console();
+        let mut attempt = ConnectAttempt::new(server_addr.to_string()).unwrap();
+
+        attempt
+            .poll(Duration::zero(), connect_vars(3), &mut console)
+            .unwrap();
+        let client_addr = recv_requests(&mut server).1.unwrap();
+
+        let accept = || Response::Accept(ResponseAccept { port: 26001 });
+
+        // an accept from another address is ignored
+        impostor.send_response(accept(), client_addr).unwrap();
+        settle();
+        assert!(attempt
+            .poll(Duration::zero(), connect_vars(3), &mut console)
+            .unwrap()
+            .is_none());
+
+        // but the server's own accept completes the connection
+        server.send_response(accept(), client_addr).unwrap();
+        settle();
+        assert!(attempt
+            .poll(Duration::zero(), connect_vars(3), &mut console)
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tracking of assets the server asked for that couldn't be loaded.
+//!
+//! Rather than disconnecting when a model or sound is missing, the client substitutes a
+//! placeholder (an empty model or a silent sound) and keeps a list for the console, so that mod
+//! authors can find everything that's missing in one pass.
+
+use std::fmt;
+
+use crate::{
+    client::sound::AudioSource,
+    common::{bsp::BspData, model::Model, vfs::Vfs},
+};
+
+/// The assets that couldn't be loaded during sign-on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MissingAssets {
+    pub models: Vec<String>,
+    pub sounds: Vec<String>,
+    pub textures: Vec<String>,
+}
+
+impl MissingAssets {
+    pub fn new() -> MissingAssets {
+        MissingAssets::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.sounds.is_empty() && self.textures.is_empty()
+    }
+
+    /// Loads an alias or sprite model, substituting a placeholder if it can't be loaded.
+    pub fn load_model(&mut self, vfs: &Vfs, name: &str) -> Model {
+        match Model::load(vfs, name) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Couldn't load model {}: {}", name, e);
+                self.models.push(name.to_owned());
+                Model::placeholder(name)
+            }
+        }
+    }
+
+    /// Loads a sound, substituting silence if it can't be loaded.
+    pub fn load_sound(&mut self, vfs: &Vfs, name: &str) -> AudioSource {
+        match AudioSource::load(vfs, name) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Couldn't load sound {}: {}", name, e);
+                self.sounds.push(name.to_owned());
+                AudioSource::silent()
+            }
+        }
+    }
+
+    /// Records the textures missing from the BSP file `bsp_name`.
+    ///
+    /// The file doesn't name missing textures, so they're listed by texture ID.
+    pub fn check_textures(&mut self, bsp_name: &str, bsp_data: &BspData) {
+        for (id, tex) in bsp_data.textures().iter().enumerate() {
+            if tex.is_missing() {
+                self.textures.push(format!("{} texture {}", bsp_name, id));
+            }
+        }
+    }
+
+    /// Returns a one-line summary of what's missing, for printing after sign-on.
+    pub fn summary(&self) -> String {
+        let count = |n: usize, what: &str| match n {
+            1 => format!("1 {}", what),
+            n => format!("{} {}s", n, what),
+        };
+
+        format!(
+            "Missing {}, {} and {}; type \"missing_assets\" for a list",
+            count(self.models.len(), "model"),
+            count(self.sounds.len(), "sound"),
+            count(self.textures.len(), "texture"),
+        )
+    }
+}
+
+impl fmt::Display for MissingAssets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No missing assets");
+        }
+
+        let mut first = true;
+        for (kind, names) in &[
+            ("Models", &self.models),
+            ("Sounds", &self.sounds),
+            ("Textures", &self.textures),
+        ] {
+            if names.is_empty() {
+                continue;
+            }
+
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+
+            write!(f, "{}:", kind)?;
+            for name in names.iter() {
+                write!(f, "\n  {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        client::sound::{AudioBackend, WavBackend},
+        common::util::TempDir,
+    };
+
+    #[test]
+    fn test_missing_assets_load_sound() {
+        let dir = TempDir::new("precache");
+        WavBackend::create(dir.file("sound/misc/found.wav"))
+            .unwrap()
+            .write(&[0.5; 64])
+            .unwrap();
+        let vfs = dir.vfs();
+
+        let mut missing = MissingAssets::new();
+        assert!(!missing.load_sound(&vfs, "misc/found.wav").is_empty());
+        assert!(missing.is_empty());
+
+        // missing sounds are replaced with silence
+        assert!(missing.load_sound(&vfs, "misc/lost.wav").is_empty());
+        assert_eq!(missing.sounds, vec!["misc/lost.wav"]);
+    }
+
+    #[test]
+    fn test_missing_assets_report() {
+        let mut missing = MissingAssets::new();
+        assert_eq!(missing.to_string(), "No missing assets");
+
+        missing.models.push("progs/foo.mdl".to_owned());
+        missing.sounds.push("foo/bar.wav".to_owned());
+        missing.sounds.push("foo/baz.wav".to_owned());
+        assert_eq!(
+            missing.summary(),
+            "Missing 1 model, 2 sounds and 0 textures; type \"missing_assets\" for a list"
+        );
+        assert_eq!(
+            missing.to_string(),
+            "Models:\n  progs/foo.mdl\nSounds:\n  foo/bar.wav\n  foo/baz.wav"
+        );
+    }
+}
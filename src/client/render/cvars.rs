@@ -18,9 +18,23 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::common::console::CvarRegistry;
+use crate::common::console::{CvarDef, CvarKind, CvarRegistry};
 
 pub fn register_cvars(cvars: &CvarRegistry) {
-    cvars.register("r_lightmap", "0").unwrap();
-    cvars.register("r_msaa_samples", "4").unwrap();
+    cvars
+        .define(
+            CvarDef::new("r_lightmap", "0", CvarKind::Bool)
+                .help("Draw only lightmaps, without textures"),
+        )
+        .unwrap();
+    cvars
+        .define(
+            CvarDef::new(
+                "r_msaa_samples",
+                "4",
+                CvarKind::Enum(vec!["2".to_owned(), "4".to_owned()]),
+            )
+            .help("Number of samples per pixel for multisample anti-aliasing"),
+        )
+        .unwrap();
 }
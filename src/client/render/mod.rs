@@ -691,6 +691,7 @@ impl ClientRenderer {
         gfx_state: &GraphicsState,
         encoder: &mut wgpu::CommandEncoder,
         conn: Option<&Connection>,
+        connect_status: Option<&str>,
         width: u32,
         height: u32,
         fov: Deg<f32>,
@@ -705,10 +706,11 @@ impl ClientRenderer {
             state: ref cl_state,
             ref conn_state,
             ref kind,
+            ..
         }) = conn
         {
             match conn_state {
-                ConnectionState::Connected(ref world) => {
+                ConnectionState::Connected(Some(ref world)) => {
                     // if client is fully connected, draw world
                     let camera = match kind {
                         ConnectionKind::Demo(_) => {
@@ -784,16 +786,22 @@ impl ClientRenderer {
                     }
                 }
 
-                // if client is still signing on, draw the loading screen
-                ConnectionState::SignOn(_) => {
-                    // TODO: loading screen
-                }
+                // if client is still signing on, there's no world to draw yet. the UI renderer
+                // draws the loading screen instead.
+                ConnectionState::Connected(None) | ConnectionState::SignOn(_) => (),
             }
         }
 
+        let overlay = || match focus {
+            InputFocus::Game => None,
+            InputFocus::Console => Some(UiOverlay::Console(console)),
+            InputFocus::Menu => Some(UiOverlay::Menu(menu)),
+        };
+
         let ui_state = match conn {
             Some(Connection {
                 state: ref cl_state,
+                conn_state: ConnectionState::Connected(_),
                 ..
             }) => UiState::InGame {
                 hud: match cl_state.intermission() {
@@ -814,18 +822,27 @@ impl ClientRenderer {
                     },
                 },
 
-                overlay: match focus {
-                    InputFocus::Game => None,
-                    InputFocus::Console => Some(UiOverlay::Console(console)),
-                    InputFocus::Menu => Some(UiOverlay::Menu(menu)),
-                },
+                overlay: overlay(),
             },
 
-            None => UiState::Title {
-                overlay: match focus {
-                    InputFocus::Console => UiOverlay::Console(console),
-                    InputFocus::Menu => UiOverlay::Menu(menu),
-                    InputFocus::Game => unreachable!(),
+            // still signing on
+            Some(_) => UiState::Loading {
+                status: None,
+                overlay: overlay(),
+            },
+
+            None => match connect_status {
+                Some(status) => UiState::Loading {
+                    status: Some(status),
+                    overlay: overlay(),
+                },
+
+                None => UiState::Title {
+                    overlay: match focus {
+                        InputFocus::Console => UiOverlay::Console(console),
+                        InputFocus::Menu => UiOverlay::Menu(menu),
+                        InputFocus::Game => unreachable!(),
+                    },
                 },
             },
         };
@@ -13,16 +13,17 @@ use crate::{
         render::{
             ui::{
                 console::ConsoleRenderer,
-                glyph::{GlyphRenderer, GlyphRendererCommand},
+                glyph::{GlyphRenderer, GlyphRendererCommand, GLYPH_HEIGHT},
                 hud::{HudRenderer, HudState},
+                layout::{Anchor, Layout, ScreenPosition, Size},
                 menu::MenuRenderer,
-                quad::{QuadRenderer, QuadRendererCommand, QuadUniforms},
+                quad::{QuadRenderer, QuadRendererCommand, QuadTexture, QuadUniforms},
             },
             uniform::{self, DynamicUniformBufferBlock},
             Extent2d, GraphicsState,
         },
     },
-    common::{console::Console, util::any_slice_as_bytes},
+    common::{console::Console, util::any_slice_as_bytes, wad::QPic},
 };
 
 use cgmath::{Matrix4, Vector2};
@@ -82,6 +83,11 @@ pub enum UiState<'a> {
     Title {
         overlay: UiOverlay<'a>,
     },
+    Loading {
+        /// A description of the loading progress to draw under the plaque, if any.
+        status: Option<&'a str>,
+        overlay: Option<UiOverlay<'a>>,
+    },
     InGame {
         hud: HudState<'a>,
         overlay: Option<UiOverlay<'a>>,
@@ -89,6 +95,8 @@ pub enum UiState<'a> {
 }
 
 pub struct UiRenderer {
+    // not every game directory has a loading plaque, in which case only the status is drawn
+    loading_plaque: Option<QuadTexture>,
     console_renderer: ConsoleRenderer,
     menu_renderer: MenuRenderer,
     hud_renderer: HudRenderer,
@@ -98,7 +106,21 @@ pub struct UiRenderer {
 
 impl UiRenderer {
     pub fn new(state: &GraphicsState, menu: &Menu) -> UiRenderer {
+        let loading_plaque = state
+            .vfs()
+            .open("gfx/loading.lmp")
+            .map_err(|e| e.to_string())
+            .and_then(|f| QPic::load(f).map_err(|e| e.to_string()));
+        let loading_plaque = match loading_plaque {
+            Ok(qpic) => Some(QuadTexture::from_qpic(state, &qpic)),
+            Err(e) => {
+                warn!("Couldn't load gfx/loading.lmp: {}", e);
+                None
+            }
+        };
+
         UiRenderer {
+            loading_plaque,
             console_renderer: ConsoleRenderer::new(state),
             menu_renderer: MenuRenderer::new(state, menu),
             hud_renderer: HudRenderer::new(state),
@@ -119,9 +141,46 @@ impl UiRenderer {
     ) {
         let (hud_state, overlay) = match ui_state {
             UiState::Title { overlay } => (None, Some(overlay)),
+            UiState::Loading { overlay, .. } => (None, overlay.as_ref()),
             UiState::InGame { hud, overlay } => (Some(hud), overlay.as_ref()),
         };
 
+        if let UiState::Loading { status, .. } = ui_state {
+            // TODO: get from cvar
+            let scale = 2.0;
+
+            let mut plaque_height = 0;
+            if let Some(ref plaque) = self.loading_plaque {
+                plaque_height = plaque.height() as i32;
+                quad_commands.push(QuadRendererCommand {
+                    texture: plaque,
+                    layout: Layout {
+                        position: ScreenPosition::Relative {
+                            anchor: Anchor::CENTER,
+                            x_ofs: 0,
+                            y_ofs: 0,
+                        },
+                        anchor: Anchor::CENTER,
+                        size: Size::Scale { factor: scale },
+                    },
+                });
+            }
+
+            if let Some(status) = status {
+                glyph_commands.push(GlyphRendererCommand::Text {
+                    text: status.to_string(),
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::CENTER,
+                        x_ofs: 0,
+                        // just below the plaque
+                        y_ofs: -(plaque_height / 2 + GLYPH_HEIGHT as i32),
+                    },
+                    anchor: Anchor::TOP_CENTER,
+                    scale,
+                });
+            }
+        }
+
         if let Some(hstate) = hud_state {
             self.hud_renderer
                 .generate_commands(hstate, time, quad_commands, glyph_commands);
@@ -135,9 +194,9 @@ impl UiRenderer {
                 }
                 UiOverlay::Console(console) => {
                     // TODO: take in-game console proportion as cvar
-                    let proportion = match hud_state {
-                        Some(_) => 0.33,
-                        None => 1.0,
+                    let proportion = match ui_state {
+                        UiState::Title { .. } => 1.0,
+                        _ => 0.33,
                     };
 
                     self.console_renderer.generate_commands(
@@ -0,0 +1,207 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    client::{
+        precache::MissingAssets,
+        sound::{AudioSource, StereoGain, Voice},
+    },
+    common::{bsp::NUM_AMBIENTS, engine::duration_to_f32, vfs::Vfs},
+};
+
+use chrono::Duration;
+
+/// The sound played for each ambient level, if there is one.
+///
+/// The original engine never loaded slime or lava ambients.
+pub const AMBIENT_SOUND_NAMES: [Option<&str>; NUM_AMBIENTS] = [
+    Some("ambience/water1.wav"),
+    Some("ambience/wind2.wav"),
+    None,
+    None,
+];
+
+// levels quieter than this are silenced
+const MIN_AMBIENT_VOLUME: f32 = 8.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientVars {
+    /// Scales the ambient levels of the listener's leaf.
+    pub ambient_level: f32,
+
+    /// How quickly, in levels per second, the ambient volume moves toward that of the leaf.
+    pub ambient_fade: f32,
+}
+
+struct AmbientChannel {
+    // if None, there is no sound for this level
+    voice: Option<Voice>,
+
+    // current volume, from 0 to 255
+    volume: f32,
+}
+
+/// Plays the looping ambient sounds of the leaf the listener is in.
+///
+/// Ambient sounds aren't spatialized. Instead, each sound fades toward the level of the listener's
+/// current leaf, so moving between leaves doesn't produce an abrupt change in volume.
+pub struct AmbientMixer {
+    channels: [AmbientChannel; NUM_AMBIENTS],
+}
+
+impl AmbientMixer {
+    /// Starts the ambient sounds, initially silent.
+    pub fn new(sources: [Option<AudioSource>; NUM_AMBIENTS]) -> AmbientMixer {
+        let [water, sky, slime, lava] = sources;
+        let channel = |src: Option<AudioSource>| AmbientChannel {
+            voice: src.map(|src| Voice::new(src, true)),
+            volume: 0.0,
+        };
+
+        AmbientMixer {
+            channels: [channel(water), channel(sky), channel(slime), channel(lava)],
+        }
+    }
+
+    /// Loads the sounds in `AMBIENT_SOUND_NAMES`, substituting silence for any that are missing.
+    pub fn load_sources(
+        vfs: &Vfs,
+        missing: &mut MissingAssets,
+    ) -> [Option<AudioSource>; NUM_AMBIENTS] {
+        let mut sources: [Option<AudioSource>; NUM_AMBIENTS] = Default::default();
+        for (src, name) in sources.iter_mut().zip(AMBIENT_SOUND_NAMES.iter()) {
+            if let Some(name) = name {
+                *src = Some(missing.load_sound(vfs, name));
+            }
+        }
+
+        sources
+    }
+
+    /// Fades each ambient sound toward the levels of the listener's leaf.
+    ///
+    /// If `levels` is `None`, there is no world to take levels from and the sounds are silenced
+    /// immediately.
+    pub fn update(
+        &mut self,
+        levels: Option<[u8; NUM_AMBIENTS]>,
+        vars: AmbientVars,
+        frame_time: Duration,
+    ) {
+        let levels = match levels {
+            Some(l) if vars.ambient_level > 0.0 => l,
+            _ => {
+                for chan in self.channels.iter_mut() {
+                    chan.volume = 0.0;
+                }
+
+                return;
+            }
+        };
+
+        let max_change = duration_to_f32(frame_time) * vars.ambient_fade;
+        for (chan, level) in self.channels.iter_mut().zip(levels.iter()) {
+            let mut target = vars.ambient_level * *level as f32;
+            if target < MIN_AMBIENT_VOLUME {
+                target = 0.0;
+            }
+
+            chan.volume = fade(chan.volume, target, max_change);
+        }
+    }
+
+    /// Returns the current volume of each ambient sound, from 0 to 255.
+    pub fn volumes(&self) -> [f32; NUM_AMBIENTS] {
+        let mut volumes = [0.0; NUM_AMBIENTS];
+        for (vol, chan) in volumes.iter_mut().zip(self.channels.iter()) {
+            *vol = chan.volume;
+        }
+
+        volumes
+    }
+
+    /// Adds the next samples of each ambient sound to the interleaved stereo buffer `out`.
+    pub fn mix(&mut self, out: &mut [f32]) {
+        for chan in self.channels.iter_mut() {
+            let gain = chan.volume / 255.0;
+            if let Some(ref mut voice) = chan.voice {
+                voice.mix(
+                    out,
+                    StereoGain {
+                        left: gain,
+                        right: gain,
+                    },
+                );
+            }
+        }
+    }
+}
+
+// moves `current` toward `target` by at most `max_change`
+fn fade(current: f32, target: f32, max_change: f32) -> f32 {
+    if current < target {
+        (current + max_change).min(target)
+    } else {
+        (current - max_change).max(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VARS: AmbientVars = AmbientVars {
+        ambient_level: 0.5,
+        ambient_fade: 100.0,
+    };
+
+    #[test]
+    fn test_ambient_mixer_fade() {
+        let mut mixer = AmbientMixer::new(Default::default());
+
+        // water at full level fades in to half volume over more than one second
+        let levels = Some([255, 40, 10, 0]);
+        mixer.update(levels, VARS, Duration::milliseconds(500));
+        assert_eq!(mixer.volumes(), [50.0, 20.0, 0.0, 0.0]);
+        mixer.update(levels, VARS, Duration::milliseconds(500));
+        assert_eq!(mixer.volumes(), [100.0, 20.0, 0.0, 0.0]);
+        mixer.update(levels, VARS, Duration::seconds(1));
+        assert_eq!(mixer.volumes(), [127.5, 20.0, 0.0, 0.0]);
+
+        // leaving the water fades it back out
+        mixer.update(Some([0; NUM_AMBIENTS]), VARS, Duration::milliseconds(250));
+        assert_eq!(mixer.volumes(), [102.5, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_ambient_mixer_silence() {
+        let mut mixer = AmbientMixer::new(Default::default());
+        mixer.update(Some([255; NUM_AMBIENTS]), VARS, Duration::seconds(1));
+        assert_eq!(mixer.volumes(), [100.0; NUM_AMBIENTS]);
+
+        // turning ambient sound off is immediate
+        let off = AmbientVars {
+            ambient_level: 0.0,
+            ..VARS
+        };
+        mixer.update(Some([255; NUM_AMBIENTS]), off, Duration::milliseconds(10));
+        assert_eq!(mixer.volumes(), [0.0; NUM_AMBIENTS]);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::f32::consts::PI;
+
+use crate::{client::sound::MIX_RATE, common::bsp::BspLeafContents};
+
+// how long the effect takes to engage or disengage fully, in seconds
+const FADE_TIME: f32 = 0.1;
+
+// the delay of the reverb's echo, in seconds
+const REVERB_DELAY: f32 = 0.05;
+
+// how much of each echo is fed back into the delay line
+const REVERB_FEEDBACK: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DspVars {
+    /// How strongly sound is muffled when the listener is in liquid, from 0 (not at all) to 1.
+    pub snd_waterfx: f32,
+
+    /// How much reverb is added when the listener is in liquid, from 0 (none) to 1.
+    pub snd_reverb: f32,
+}
+
+/// Returns the cutoff frequency of the low-pass filter for a listener in a leaf with `contents`,
+/// or `None` if sound there is unfiltered.
+pub fn lowpass_cutoff(contents: BspLeafContents) -> Option<f32> {
+    match contents {
+        BspLeafContents::Water => Some(1200.0),
+        BspLeafContents::Slime => Some(800.0),
+        BspLeafContents::Lava => Some(500.0),
+        _ => None,
+    }
+}
+
+/// Filters mixed sound to match the listener's surroundings.
+///
+/// In water, slime or lava, sound passes through a low-pass filter and a short echo. The effect
+/// fades in and out rather than switching abruptly, so crossing the surface doesn't click.
+pub struct Dsp {
+    // the filtered output for each ear
+    lowpass: [f32; 2],
+
+    // interleaved stereo delay line for the reverb
+    delay: Vec<f32>,
+    delay_pos: usize,
+
+    // the current strength of the effect, from 0 to 1
+    level: f32,
+
+    // kept from the last liquid leaf so the effect can fade out after leaving it
+    cutoff: f32,
+}
+
+impl Dsp {
+    pub fn new() -> Dsp {
+        let delay_frames = (REVERB_DELAY * MIX_RATE as f32) as usize;
+
+        Dsp {
+            lowpass: [0.0; 2],
+            delay: vec![0.0; delay_frames * 2],
+            delay_pos: 0,
+            level: 0.0,
+            cutoff: 0.0,
+        }
+    }
+
+    /// Returns the current strength of the effect, from 0 to 1.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Filters the interleaved stereo buffer `out` for a listener in a leaf with `contents`.
+    ///
+    /// If `contents` is `None`, there is no world and the effect fades out.
+    pub fn process(&mut self, contents: Option<BspLeafContents>, vars: DspVars, out: &mut [f32]) {
+        let target = match contents.and_then(lowpass_cutoff) {
+            Some(cutoff) => {
+                self.cutoff = cutoff;
+                vars.snd_waterfx.max(0.0).min(1.0)
+            }
+            None => 0.0,
+        };
+
+        if target == 0.0 && self.level == 0.0 {
+            // forget old sound so it doesn't leak into the next time the effect engages
+            self.lowpass = [0.0; 2];
+            for s in self.delay.iter_mut() {
+                *s = 0.0;
+            }
+
+            return;
+        }
+
+        // one-pole low-pass coefficient for the cutoff frequency
+        let alpha = 1.0 - (-2.0 * PI * self.cutoff / MIX_RATE as f32).exp();
+        let step = 1.0 / (FADE_TIME * MIX_RATE as f32);
+
+        for frame in out.chunks_exact_mut(2) {
+            self.level = if self.level < target {
+                (self.level + step).min(target)
+            } else {
+                (self.level - step).max(target)
+            };
+
+            for (ear, s) in frame.iter_mut().enumerate() {
+                let dry = *s;
+                self.lowpass[ear] += alpha * (dry - self.lowpass[ear]);
+
+                let echo = self.delay[self.delay_pos + ear];
+                self.delay[self.delay_pos + ear] = self.lowpass[ear] + echo * REVERB_FEEDBACK;
+
+                let wet = self.lowpass[ear] + echo * vars.snd_reverb;
+                *s = dry + (wet - dry) * self.level;
+            }
+
+            self.delay_pos = (self.delay_pos + 2) % self.delay.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VARS: DspVars = DspVars {
+        snd_waterfx: 1.0,
+        snd_reverb: 0.0,
+    };
+
+    // alternating full-scale samples, the highest frequency that can be represented
+    fn nyquist(frames: usize) -> Vec<f32> {
+        (0..frames * 2)
+            .map(|i| if (i / 2) % 2 == 0 { 1.0 } else { -1.0 })
+            .collect()
+    }
+
+    // runs the filter long enough for the effect to fade in fully
+    fn engaged(contents: BspLeafContents, vars: DspVars) -> Dsp {
+        let mut dsp = Dsp::new();
+        let mut silence = vec![0.0; MIX_RATE as usize];
+        dsp.process(Some(contents), vars, &mut silence);
+        assert_eq!(dsp.level(), vars.snd_waterfx);
+        dsp
+    }
+
+    #[test]
+    fn test_dsp_dry() {
+        let mut dsp = Dsp::new();
+        let mut out = nyquist(64);
+        dsp.process(Some(BspLeafContents::Empty), VARS, &mut out);
+        dsp.process(None, VARS, &mut out);
+        assert_eq!(out, nyquist(64));
+
+        // disabled in water
+        let off = DspVars {
+            snd_waterfx: 0.0,
+            ..VARS
+        };
+        dsp.process(Some(BspLeafContents::Water), off, &mut out);
+        assert_eq!(out, nyquist(64));
+    }
+
+    #[test]
+    fn test_dsp_lowpass() {
+        let mut dsp = engaged(BspLeafContents::Water, VARS);
+
+        // high frequencies are muffled once the filter settles
+        let mut out = nyquist(256);
+        dsp.process(Some(BspLeafContents::Water), VARS, &mut out);
+        assert!(out[64..].iter().all(|s| s.abs() < 0.25));
+
+        // low frequencies pass through
+        let mut out = vec![0.5; 512];
+        dsp.process(Some(BspLeafContents::Water), VARS, &mut out);
+        assert!((out[511] - 0.5).abs() < 1e-3);
+
+        // lava muffles more than water
+        let mut water = nyquist(256);
+        engaged(BspLeafContents::Water, VARS).process(
+            Some(BspLeafContents::Water),
+            VARS,
+            &mut water,
+        );
+        let mut lava = nyquist(256);
+        engaged(BspLeafContents::Lava, VARS).process(Some(BspLeafContents::Lava), VARS, &mut lava);
+        assert!(lava[511].abs() < water[511].abs());
+    }
+
+    #[test]
+    fn test_dsp_fade() {
+        let mut dsp = engaged(BspLeafContents::Slime, VARS);
+
+        // leaving the slime fades the effect out over FADE_TIME
+        let half = (FADE_TIME * MIX_RATE as f32 / 2.0) as usize;
+        let mut out = vec![0.0; half * 2];
+        dsp.process(Some(BspLeafContents::Empty), VARS, &mut out);
+        assert!((dsp.level() - 0.5).abs() < 1e-3);
+
+        let mut out = vec![0.0; half * 4];
+        dsp.process(Some(BspLeafContents::Empty), VARS, &mut out);
+        assert_eq!(dsp.level(), 0.0);
+    }
+
+    #[test]
+    fn test_dsp_reverb() {
+        let vars = DspVars {
+            snd_reverb: 1.0,
+            ..VARS
+        };
+        let mut dsp = engaged(BspLeafContents::Water, vars);
+
+        // an impulse echoes after the delay, then again more quietly
+        let delay = (REVERB_DELAY * MIX_RATE as f32) as usize * 2;
+        let mut out = vec![0.0; delay * 3];
+        out[0] = 1.0;
+        dsp.process(Some(BspLeafContents::Water), vars, &mut out);
+
+        assert!(out[delay - 2].abs() < 1e-3);
+        assert!(out[delay] > 0.1);
+        assert!(out[delay * 2] > 0.0 && out[delay * 2] < out[delay]);
+    }
+}
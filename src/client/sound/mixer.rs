@@ -0,0 +1,457 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{client::sound::SoundError, common::engine::duration_to_f32};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use chrono::Duration;
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+
+/// The sample rate of mixed sound.
+pub const MIX_RATE: u32 = 22050;
+
+// the number of samples per frame of mixed sound (left and right)
+const MIX_CHANNELS: usize = 2;
+
+// how far ahead of the audio device sound is mixed
+const DEVICE_MIXAHEAD_MS: i64 = 100;
+
+// the most samples the audio thread takes from the queue at once
+const DEVICE_CHUNK: usize = 512 * MIX_CHANNELS;
+
+/// Somewhere to send mixed sound.
+pub trait AudioBackend {
+    /// Returns how many frames to mix next, given that `elapsed` frames of game time have passed
+    /// since the last update.
+    ///
+    /// By default this is `elapsed`, so the output follows the game clock. A backend that plays in
+    /// real time asks for what it has played since the last update instead.
+    fn frames_wanted(&mut self, elapsed: usize) -> usize {
+        elapsed
+    }
+
+    /// Outputs interleaved stereo samples at `MIX_RATE`.
+    fn write(&mut self, samples: &[f32]) -> Result<(), SoundError>;
+}
+
+/// Discards all sound.
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn write(&mut self, _samples: &[f32]) -> Result<(), SoundError> {
+        Ok(())
+    }
+}
+
+/// Writes sound to a 16-bit PCM WAV file.
+///
+/// The header is kept up to date after every write, so the file is valid even if the writer is
+/// never closed cleanly.
+pub struct WavBackend<W>
+where
+    W: Write + Seek,
+{
+    writer: W,
+    data_len: u32,
+}
+
+impl WavBackend<BufWriter<File>> {
+    /// Creates a WAV file at `path`, replacing any existing file.
+    pub fn create<P>(path: P) -> Result<WavBackend<BufWriter<File>>, SoundError>
+    where
+        P: AsRef<Path>,
+    {
+        WavBackend::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W> WavBackend<W>
+where
+    W: Write + Seek,
+{
+    pub fn new(mut writer: W) -> Result<WavBackend<W>, SoundError> {
+        let block_align = (MIX_CHANNELS * 2) as u16;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(36)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        writer.write_u16::<LittleEndian>(1)?; // PCM
+        writer.write_u16::<LittleEndian>(MIX_CHANNELS as u16)?;
+        writer.write_u32::<LittleEndian>(MIX_RATE)?;
+        writer.write_u32::<LittleEndian>(MIX_RATE * block_align as u32)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(16)?;
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.flush()?;
+
+        Ok(WavBackend {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> AudioBackend for WavBackend<W>
+where
+    W: Write + Seek,
+{
+    fn write(&mut self, samples: &[f32]) -> Result<(), SoundError> {
+        for s in samples {
+            self.writer
+                .write_i16::<LittleEndian>((s.max(-1.0).min(1.0) * 32767.0) as i16)?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+
+        // patch the RIFF and data chunk lengths
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LittleEndian>(36 + self.data_len)?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_u32::<LittleEndian>(self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Mixed samples waiting to be played by the audio device.
+///
+/// The game pushes samples from its thread and the device pulls them from its own, so the mixer
+/// can refill exactly what was played, as the original engine did with its DMA buffer.
+#[derive(Clone, Default)]
+struct SampleQueue {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl SampleQueue {
+    fn new() -> SampleQueue {
+        SampleQueue::default()
+    }
+
+    /// Returns the number of frames needed to fill the queue to `mixahead` frames.
+    fn frames_wanted(&self, mixahead: usize) -> usize {
+        match self.samples.lock() {
+            Ok(samples) => mixahead.saturating_sub(samples.len() / MIX_CHANNELS),
+            Err(_) => 0,
+        }
+    }
+
+    fn push(&self, samples: &[f32]) -> Result<(), SoundError> {
+        self.samples
+            .lock()
+            .map_err(|_| SoundError::Device("audio thread panicked".to_owned()))?
+            .extend(samples.iter().copied());
+        Ok(())
+    }
+
+    /// Returns a source that plays the queued samples, or silence if the queue runs dry.
+    fn source(&self) -> QueueSource {
+        QueueSource {
+            queue: self.clone(),
+            chunk: VecDeque::with_capacity(DEVICE_CHUNK),
+        }
+    }
+}
+
+struct QueueSource {
+    queue: SampleQueue,
+
+    // samples taken from the queue but not yet played, so the lock isn't taken for every sample
+    chunk: VecDeque<f32>,
+}
+
+impl Iterator for QueueSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.chunk.is_empty() {
+            if let Ok(mut samples) = self.queue.samples.lock() {
+                // the queue only ever holds whole frames, so this keeps the channels in order
+                let len = samples.len().min(DEVICE_CHUNK);
+                self.chunk.extend(samples.drain(..len));
+            }
+        }
+
+        // never end the stream; an underrun is just silence
+        Some(self.chunk.pop_front().unwrap_or(0.0))
+    }
+}
+
+impl Source for QueueSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        MIX_CHANNELS as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        MIX_RATE
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// Plays sound on the default audio device.
+///
+/// The device pulls mixed sound from a queue, and each update mixes as much as it played since
+/// the last one, regardless of how long the game frame was.
+pub struct DeviceBackend {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    _sink: Sink,
+    queue: SampleQueue,
+}
+
+impl DeviceBackend {
+    pub fn new() -> Result<DeviceBackend, SoundError> {
+        let (stream, handle) =
+            OutputStream::try_default().map_err(|e| SoundError::Device(e.to_string()))?;
+        let sink = Sink::try_new(&handle).map_err(|e| SoundError::Device(e.to_string()))?;
+
+        let queue = SampleQueue::new();
+        sink.append(queue.source());
+
+        Ok(DeviceBackend {
+            _stream: stream,
+            handle,
+            _sink: sink,
+            queue,
+        })
+    }
+
+    /// Returns a handle to the device for sounds played outside the mixer, like music.
+    pub fn handle(&self) -> OutputStreamHandle {
+        self.handle.clone()
+    }
+}
+
+impl AudioBackend for DeviceBackend {
+    fn frames_wanted(&mut self, _elapsed: usize) -> usize {
+        self.queue
+            .frames_wanted(frames_for(Duration::milliseconds(DEVICE_MIXAHEAD_MS)))
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), SoundError> {
+        self.queue.push(samples)
+    }
+}
+
+fn frames_for(time: Duration) -> usize {
+    (duration_to_f32(time) * MIX_RATE as f32) as usize
+}
+
+/// Mixes sound into a PCM buffer and sends it to an output backend.
+pub struct Mixer {
+    backend: Box<dyn AudioBackend>,
+    buffer: Vec<f32>,
+    volume: f32,
+
+    // the fraction of a frame left over by the last update, so no time is lost to rounding
+    remainder: f64,
+
+    // total frames mixed
+    frames: u64,
+}
+
+impl Mixer {
+    pub fn new(backend: Box<dyn AudioBackend>) -> Mixer {
+        Mixer {
+            backend,
+            buffer: Vec::new(),
+            volume: 1.0,
+            remainder: 0.0,
+            frames: 0,
+        }
+    }
+
+    /// Replaces the output backend.
+    pub fn set_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.backend = backend;
+    }
+
+    /// Sets the volume applied to all mixed sound.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    /// Returns the total number of frames mixed.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Mixes sound for `time` of game time and sends it to the backend.
+    ///
+    /// The backend decides how much is actually mixed; see [`AudioBackend::frames_wanted`].
+    /// `paint` is passed a silent interleaved stereo buffer to add sounds to. The result is scaled
+    /// by the volume and clipped.
+    pub fn update<F>(&mut self, time: Duration, paint: F) -> Result<(), SoundError>
+    where
+        F: FnOnce(&mut [f32]),
+    {
+        let exact = time.num_microseconds().unwrap_or(0) as f64 * MIX_RATE as f64 / 1_000_000.0
+            + self.remainder;
+        let frames = exact.max(0.0).floor();
+        self.remainder = exact - frames;
+
+        let frames = self.backend.frames_wanted(frames as usize);
+        if frames == 0 {
+            return Ok(());
+        }
+
+        self.buffer.clear();
+        self.buffer.resize(frames * MIX_CHANNELS, 0.0);
+        paint(&mut self.buffer);
+
+        for s in self.buffer.iter_mut() {
+            *s = (*s * self.volume).max(-1.0).min(1.0);
+        }
+
+        self.frames += frames as u64;
+        self.backend.write(&self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    // keeps everything written to it
+    struct CaptureBackend(Rc<RefCell<Vec<f32>>>);
+
+    impl AudioBackend for CaptureBackend {
+        fn write(&mut self, samples: &[f32]) -> Result<(), SoundError> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mixer_update() {
+        let captured = Rc::new(RefCell::new(Vec::new()));
+        let mut mixer = Mixer::new(Box::new(CaptureBackend(captured.clone())));
+        mixer.set_volume(0.5);
+
+        // 10ms is 220.5 frames, so the extra half frame carries over to the next update
+        for _ in 0..2 {
+            mixer
+                .update(Duration::milliseconds(10), |out| {
+                    for s in out.iter_mut() {
+                        *s += 4.0;
+                    }
+                    out[0] = 1.0;
+                })
+                .unwrap();
+        }
+
+        assert_eq!(mixer.frames(), 441);
+        let captured = captured.borrow();
+        assert_eq!(captured.len(), 441 * 2);
+        assert_eq!(captured[0], 0.5);
+        // loud samples are clipped
+        assert_eq!(captured[1], 1.0);
+    }
+
+    // asks for a fixed number of frames, however much game time has passed
+    struct RealTimeBackend(Rc<RefCell<Vec<f32>>>);
+
+    impl AudioBackend for RealTimeBackend {
+        fn frames_wanted(&mut self, _elapsed: usize) -> usize {
+            100
+        }
+
+        fn write(&mut self, samples: &[f32]) -> Result<(), SoundError> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mixer_frames_wanted() {
+        let captured = Rc::new(RefCell::new(Vec::new()));
+        let mut mixer = Mixer::new(Box::new(RealTimeBackend(captured.clone())));
+        mixer.update(Duration::milliseconds(1), |_| ()).unwrap();
+        mixer.update(Duration::seconds(1), |_| ()).unwrap();
+        assert_eq!(mixer.frames(), 200);
+        assert_eq!(captured.borrow().len(), 400);
+    }
+
+    #[test]
+    fn test_sample_queue() {
+        let queue = SampleQueue::new();
+        let mut source = queue.source();
+        assert_eq!(queue.frames_wanted(4), 4);
+
+        queue.push(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]).unwrap();
+        assert_eq!(queue.frames_wanted(4), 1);
+
+        // the device takes what it plays from the queue, making room for more
+        assert_eq!(source.next(), Some(0.1));
+        assert_eq!(source.next(), Some(0.2));
+        assert_eq!(queue.frames_wanted(4), 4);
+        assert_eq!(
+            source.by_ref().take(6).collect::<Vec<_>>(),
+            vec![0.3, 0.4, 0.5, 0.6, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_wav_backend() {
+        let mut wav = WavBackend::new(Cursor::new(Vec::new())).unwrap();
+        wav.write(&[0.0, 1.0]).unwrap();
+        wav.write(&[-1.0, 2.0]).unwrap();
+
+        let data = wav.into_inner().into_inner();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &(36u32 + 8).to_le_bytes());
+        assert_eq!(&data[24..28], &MIX_RATE.to_le_bytes());
+        assert_eq!(&data[40..44], &8u32.to_le_bytes());
+
+        let samples: Vec<i16> = data[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, vec![0, 32767, -32767, 32767]);
+    }
+}
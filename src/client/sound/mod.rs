@@ -18,26 +18,31 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod ambient;
+mod dsp;
+mod mixer;
 mod music;
-pub use music::MusicPlayer;
+mod wav;
+pub use ambient::{AmbientMixer, AmbientVars, AMBIENT_SOUND_NAMES};
+pub use dsp::{Dsp, DspVars};
+pub use mixer::{AudioBackend, DeviceBackend, Mixer, NullBackend, WavBackend, MIX_RATE};
+pub use music::{MusicPlayer, MusicVars, PlayMode};
+pub use wav::{Wav, WavError};
 
 use std::{
     cell::{Cell, RefCell},
-    io::{self, BufReader, Cursor, Read},
+    io::{self, Read},
+    rc::Rc,
 };
 
 use crate::common::vfs::{Vfs, VfsError};
 
 use cgmath::{InnerSpace, Vector3};
-use rodio::{
-    source::{Buffered, SamplesConverter},
-    Decoder, OutputStreamHandle, Sink, Source,
-};
-use thiserror::Error;
 use chrono::Duration;
+use thiserror::Error;
 
 pub const DISTANCE_ATTENUATION_FACTOR: f32 = 0.001;
-const MAX_ENTITY_CHANNELS: usize = 128;
+pub const MAX_ENTITY_CHANNELS: usize = 128;
 
 #[derive(Error, Debug)]
 pub enum SoundError {
@@ -49,6 +54,10 @@ pub enum SoundError {
     Vfs(#[from] VfsError),
     #[error("WAV decoder error: {0}")]
     Decoder(#[from] rodio::decoder::DecoderError),
+    #[error("WAV error: {0}")]
+    Wav(#[from] WavError),
+    #[error("Couldn't open audio device: {0}")]
+    Device(String),
 }
 
 /// Data needed for sound spatialization.
@@ -94,22 +103,59 @@ impl Listener {
         self.right_ear.set(new_origin);
     }
 
-    pub fn attenuate(
+    /// Computes the gain for each ear from the position of an emitter.
+    ///
+    /// This matches the original `SND_Spatialize`: volume falls off linearly with distance, and a
+    /// sound directly to one side of the listener is played at double volume in the near ear and
+    /// silently in the far ear. Sounds at the listener's position are centered.
+    pub fn spatialize(
         &self,
         emitter_origin: Vector3<f32>,
         base_volume: f32,
         attenuation: f32,
-    ) -> f32 {
-        let decay = (emitter_origin - self.origin.get()).magnitude()
-            * attenuation
-            * DISTANCE_ATTENUATION_FACTOR;
-        let volume = ((1.0 - decay) * base_volume).max(0.0);
-        volume
+    ) -> StereoGain {
+        let offset = emitter_origin - self.origin.get();
+        let distance = offset.magnitude();
+        let decay = distance * attenuation * DISTANCE_ATTENUATION_FACTOR;
+
+        // cosine of the angle between the emitter and the listener's right
+        let right = self.right_ear.get() - self.left_ear.get();
+        let dot = if distance > 0.0 && right.magnitude2() > 0.0 {
+            offset.normalize().dot(right.normalize())
+        } else {
+            0.0
+        };
+
+        let scale = (1.0 - decay) * base_volume;
+        StereoGain {
+            left: (scale * (1.0 - dot)).max(0.0),
+            right: (scale * (1.0 + dot)).max(0.0),
+        }
     }
 }
 
+/// The volume of a sound in each ear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoGain {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl StereoGain {
+    pub const SILENT: StereoGain = StereoGain {
+        left: 0.0,
+        right: 0.0,
+    };
+}
+
+/// A sound decoded to mono samples at `MIX_RATE`.
 #[derive(Clone)]
-pub struct AudioSource(Buffered<SamplesConverter<Decoder<Cursor<Vec<u8>>>, f32>>);
+pub struct AudioSource {
+    samples: Rc<[f32]>,
+
+    // the sample to restart at when the sound reaches its end, if it loops
+    loop_start: Option<usize>,
+}
 
 impl AudioSource {
     pub fn load<S>(vfs: &Vfs, name: S) -> Result<AudioSource, SoundError>
@@ -122,65 +168,185 @@ impl AudioSource {
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
 
-        let src = Decoder::new(Cursor::new(data))?
-            .convert_samples()
-            .buffered();
+        Ok(AudioSource::from_wav(&wav::parse(&data)?))
+    }
+
+    /// Creates a sound from a parsed WAV file, keeping its loop point.
+    pub fn from_wav(wav: &Wav) -> AudioSource {
+        let src = AudioSource::from_samples(&wav.samples, wav.channels as usize, wav.sample_rate);
+
+        // the loop point is in frames of the original sample rate
+        let loop_start = wav
+            .loop_start
+            .map(|start| (start as u64 * MIX_RATE as u64 / wav.sample_rate as u64) as usize)
+            .filter(|&start| start < src.len());
+
+        AudioSource { loop_start, ..src }
+    }
+
+    /// Creates a sound from interleaved samples.
+    ///
+    /// The samples are mixed down to mono and resampled to `MIX_RATE`.
+    pub fn from_samples(samples: &[f32], channels: usize, sample_rate: u32) -> AudioSource {
+        let mono: Vec<f32> = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect();
+
+        AudioSource {
+            samples: resample(&mono, sample_rate, MIX_RATE).into(),
+            loop_start: None,
+        }
+    }
+
+    /// Returns a sound with no samples, which finishes as soon as it starts.
+    pub fn silent() -> AudioSource {
+        AudioSource {
+            samples: Vec::new().into(),
+            loop_start: None,
+        }
+    }
+
+    /// Returns the length of the sound in samples.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Returns the sample this sound restarts at when it loops, if it has a loop point.
+    pub fn loop_start(&self) -> Option<usize> {
+        self.loop_start
+    }
+}
+
+// resamples with linear interpolation
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let len = (samples.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+    let last = samples.len() - 1;
+
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let i0 = (pos as usize).min(last);
+            let i1 = (i0 + 1).min(last);
+            let frac = (pos - i0 as f64) as f32;
+            samples[i0] + (samples[i1] - samples[i0]) * frac
+        })
+        .collect()
+}
+
+// A sound being played.
+//
+// A sound with a loop point always loops, as in the original engine. If `looping` is set, a sound
+// without one loops from the beginning.
+struct Voice {
+    src: AudioSource,
+    pos: usize,
+    looping: bool,
+}
+
+impl Voice {
+    fn new(src: AudioSource, looping: bool) -> Voice {
+        Voice {
+            src,
+            pos: 0,
+            looping,
+        }
+    }
+
+    // where playback restarts at the end of the sound, or None if the sound ends there
+    fn restart_pos(&self) -> Option<usize> {
+        match self.src.loop_start {
+            Some(start) => Some(start),
+            None if self.looping => Some(0),
+            None => None,
+        }
+    }
+
+    // Adds the next samples of the sound to the interleaved stereo buffer `out`.
+    //
+    // Returns false once a sound that doesn't loop has finished.
+    fn mix(&mut self, out: &mut [f32], gain: StereoGain) -> bool {
+        let restart = self.restart_pos();
+        let samples = &self.src.samples;
+        if samples.is_empty() {
+            return false;
+        }
+
+        for frame in out.chunks_exact_mut(2) {
+            if self.pos >= samples.len() {
+                match restart {
+                    Some(start) => self.pos = start,
+                    None => return false,
+                }
+            }
+
+            frame[0] += samples[self.pos] * gain.left;
+            frame[1] += samples[self.pos] * gain.right;
+            self.pos += 1;
+        }
 
-        Ok(AudioSource(src))
+        restart.is_some() || self.pos < samples.len()
     }
 }
 
 pub struct StaticSound {
     origin: Vector3<f32>,
-    sink: RefCell<Sink>,
+    voice: RefCell<Voice>,
+    gain: Cell<StereoGain>,
     volume: f32,
     attenuation: f32,
 }
 
 impl StaticSound {
     pub fn new(
-        stream: &OutputStreamHandle,
         origin: Vector3<f32>,
         src: AudioSource,
         volume: f32,
         attenuation: f32,
         listener: &Listener,
     ) -> StaticSound {
-        // TODO: handle PlayError once PR accepted
-        let sink = Sink::try_new(&stream).unwrap();
-        let infinite = src.0.clone().repeat_infinite();
-        sink.append(infinite);
-        sink.set_volume(listener.attenuate(origin, volume, attenuation));
-
         StaticSound {
             origin,
-            sink: RefCell::new(sink),
+            voice: RefCell::new(Voice::new(src, true)),
+            gain: Cell::new(listener.spatialize(origin, volume, attenuation)),
             volume,
             attenuation,
         }
     }
 
     pub fn update(&self, listener: &Listener) {
-        let sink = self.sink.borrow_mut();
+        self.gain
+            .set(listener.spatialize(self.origin, self.volume, self.attenuation));
+    }
 
-        sink.set_volume(listener.attenuate(self.origin, self.volume, self.attenuation));
+    /// Adds the next samples of this sound to the interleaved stereo buffer `out`.
+    pub fn mix(&self, out: &mut [f32]) {
+        self.voice.borrow_mut().mix(out, self.gain.get());
     }
 }
 
 /// Represents a single audio channel, capable of playing one sound at a time.
 pub struct Channel {
-    stream: OutputStreamHandle,
-    sink: RefCell<Option<Sink>>,
+    voice: RefCell<Option<Voice>>,
+    gain: Cell<StereoGain>,
     master_vol: Cell<f32>,
     attenuation: Cell<f32>,
 }
 
 impl Channel {
-    /// Create a new `Channel` backed by the given `Device`.
-    pub fn new(stream: OutputStreamHandle) -> Channel {
+    pub fn new() -> Channel {
         Channel {
-            stream,
-            sink: RefCell::new(None),
+            voice: RefCell::new(None),
+            gain: Cell::new(StereoGain::SILENT),
             master_vol: Cell::new(0.0),
             attenuation: Cell::new(0.0),
         }
@@ -197,52 +363,48 @@ impl Channel {
     ) {
         self.master_vol.set(volume);
         self.attenuation.set(attenuation);
-
-        // stop the old sound
-        self.sink.replace(None);
-
-        // start the new sound
-        let new_sink = Sink::try_new(&self.stream).unwrap();
-        new_sink.append(src.0);
-        new_sink.set_volume(listener.attenuate(
-            ent_pos,
-            self.master_vol.get(),
-            self.attenuation.get(),
-        ));
-
-        self.sink.replace(Some(new_sink));
+        self.gain
+            .set(listener.spatialize(ent_pos, self.master_vol.get(), self.attenuation.get()));
+        self.voice.replace(Some(Voice::new(src, false)));
     }
 
     pub fn update(&self, ent_pos: Vector3<f32>, listener: &Listener) {
-        if let Some(ref sink) = *self.sink.borrow_mut() {
-            // attenuate using quake coordinates since distance is the same either way
-            sink.set_volume(listener.attenuate(
+        if self.in_use() {
+            self.gain.set(listener.spatialize(
                 ent_pos,
                 self.master_vol.get(),
                 self.attenuation.get(),
             ));
-        };
+        }
     }
 
     /// Stop the sound currently playing on this channel, if there is one.
     pub fn stop(&self) {
-        self.sink.replace(None);
+        self.voice.replace(None);
+    }
+
+    /// Returns the current volume of this channel in each ear.
+    pub fn gain(&self) -> StereoGain {
+        self.gain.get()
     }
 
     /// Returns whether or not this `Channel` is currently in use.
     pub fn in_use(&self) -> bool {
-        let replace_sink;
-        match *self.sink.borrow() {
-            Some(ref sink) => replace_sink = sink.empty(),
-            None => return false,
-        }
+        self.voice.borrow().is_some()
+    }
 
-        // if the sink isn't in use, free it
-        if replace_sink {
-            self.sink.replace(None);
-            false
-        } else {
-            true
+    /// Adds the next samples of the playing sound to the interleaved stereo buffer `out`.
+    ///
+    /// The channel is freed once the sound finishes.
+    pub fn mix(&self, out: &mut [f32]) {
+        let mut voice = self.voice.borrow_mut();
+        let finished = match *voice {
+            Some(ref mut v) => !v.mix(out, self.gain.get()),
+            None => false,
+        };
+
+        if finished {
+            *voice = None;
         }
     }
 }
@@ -265,14 +427,29 @@ impl EntityChannel {
     }
 }
 
+/// Plays sounds attached to entities, or to points in space for temporary entities.
+///
+/// When a new sound starts, its channel is chosen the way the original engine chose one:
+///
+/// - A sound on the same entity and entity channel as a playing sound replaces it. Entity channel
+///   0 never replaces anything, and entity channel -1 replaces any sound on the same entity.
+/// - Otherwise, a free channel is used if there is one.
+/// - Otherwise, the quietest sound is cut off, or the oldest if several are equally quiet. Sounds
+///   on the local player's entity are never cut off by sounds on other entities, so if every
+///   channel is playing one, the new sound is dropped.
 pub struct EntityMixer {
-    stream: OutputStreamHandle,
     // TODO: replace with an array once const type parameters are implemented
     channels: Box<[Option<EntityChannel>]>,
+
+    // the number of channels in `channels` that may be used
+    max_channels: usize,
+
+    // the local player's entity
+    view_entity: Option<usize>,
 }
 
 impl EntityMixer {
-    pub fn new(stream: OutputStreamHandle) -> EntityMixer {
+    pub fn new() -> EntityMixer {
         let mut channel_vec = Vec::new();
 
         for _ in 0..MAX_ENTITY_CHANNELS {
@@ -280,49 +457,88 @@ impl EntityMixer {
         }
 
         EntityMixer {
-            stream,
             channels: channel_vec.into_boxed_slice(),
+            max_channels: MAX_ENTITY_CHANNELS,
+            view_entity: None,
         }
     }
 
-    fn find_free_channel(&self, ent_id: Option<usize>, ent_channel: i8) -> usize {
-        let mut oldest = 0;
+    /// Limits the number of sounds that can play at once, stopping any sounds past the limit.
+    ///
+    /// The limit is clamped to `MAX_ENTITY_CHANNELS`.
+    pub fn set_max_channels(&mut self, max_channels: usize) {
+        self.max_channels = max_channels.min(MAX_ENTITY_CHANNELS);
+        for chan in self.channels[self.max_channels..].iter_mut() {
+            *chan = None;
+        }
+    }
 
-        for (i, channel) in self.channels.iter().enumerate() {
-            match *channel {
-                Some(ref chan) => {
-                    // if this channel is free, return it
-                    if !chan.channel.in_use() {
-                        return i;
-                    }
+    /// Sets the entity of the local player, whose sounds take priority over other entities'.
+    pub fn set_view_entity(&mut self, ent_id: usize) {
+        self.view_entity = Some(ent_id);
+    }
+
+    // returns the channel to play a new sound on, or None if the sound shouldn't be played
+    fn find_free_channel(&self, ent_id: Option<usize>, ent_channel: i8) -> Option<usize> {
+        let channels = &self.channels[..self.max_channels];
 
-                    // replace sounds on the same entity channel
-                    if ent_channel != 0
+        // always replace sounds on the same entity channel, even if another channel is free
+        if ent_channel != 0 {
+            let same = channels.iter().position(|c| match *c {
+                Some(ref chan) => {
+                    chan.channel.in_use()
                         && chan.ent_id == ent_id
                         && (chan.ent_channel == ent_channel || ent_channel == -1)
-                    {
-                        return i;
-                    }
+                }
+                None => false,
+            });
+
+            if same.is_some() {
+                return same;
+            }
+        }
+
+        let free = channels.iter().position(|c| match *c {
+            Some(ref chan) => !chan.channel.in_use(),
+            None => true,
+        });
+
+        if free.is_some() {
+            return free;
+        }
+
+        let is_player = |id: Option<usize>| id.is_some() && id == self.view_entity;
+        let loudness = |chan: &EntityChannel| {
+            let gain = chan.channel.gain();
+            gain.left.max(gain.right)
+        };
+
+        let mut victim: Option<usize> = None;
+        for (i, chan) in channels.iter().enumerate() {
+            // every channel in range is in use at this point
+            let chan = chan.as_ref().unwrap();
 
-                    // TODO: don't clobber player sounds with monster sounds
+            // don't clobber player sounds with monster sounds
+            if is_player(chan.ent_id) && !is_player(ent_id) {
+                continue;
+            }
 
-                    // keep track of which sound started the earliest
-                    match self.channels[oldest] {
-                        Some(ref o) => {
-                            if chan.start_time < o.start_time {
-                                oldest = i;
-                            }
-                        }
-                        None => oldest = i,
+            victim = match victim {
+                Some(v) => {
+                    let other = self.channels[v].as_ref().unwrap();
+                    let (vol, other_vol) = (loudness(chan), loudness(other));
+                    if vol < other_vol || (vol == other_vol && chan.start_time < other.start_time) {
+                        Some(i)
+                    } else {
+                        Some(v)
                     }
                 }
 
-                None => return i,
-            }
+                None => Some(i),
+            };
         }
 
-        // if there are no good channels, just replace the one that's been running the longest
-        oldest
+        victim
     }
 
     pub fn start_sound(
@@ -336,16 +552,20 @@ impl EntityMixer {
         origin: Vector3<f32>,
         listener: &Listener,
     ) {
-        let chan_id = self.find_free_channel(ent_id, ent_channel);
-        let new_channel = Channel::new(self.stream.clone());
+        let chan_id = match self.find_free_channel(ent_id, ent_channel) {
+            Some(c) => c,
+            None => {
+                debug!(
+                    "no channel for sound on entity {:?} channel {}",
+                    ent_id, ent_channel
+                );
+                return;
+            }
+        };
 
-        new_channel.play(
-            src.clone(),
-            origin,
-            listener,
-            volume,
-            attenuation,
-        );
+        let new_channel = Channel::new();
+
+        new_channel.play(src.clone(), origin, listener, volume, attenuation);
         self.channels[chan_id] = Some(EntityChannel {
             start_time: time,
             ent_id,
@@ -358,7 +578,272 @@ impl EntityMixer {
         self.channels.iter().filter_map(|e| e.as_ref())
     }
 
-    pub fn stream(&self) -> OutputStreamHandle {
-        self.stream.clone()
+    /// Adds the next samples of every channel to the interleaved stereo buffer `out`.
+    pub fn mix(&self, out: &mut [f32]) {
+        for chan in self.iter_entity_channels() {
+            chan.channel.mix(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a listener at the origin facing +x, so its left ear is toward +y
+    fn test_listener() -> Listener {
+        let listener = Listener::new();
+        listener.set_left_ear(Vector3::new(0.0, 4.0, 0.0));
+        listener.set_right_ear(Vector3::new(0.0, -4.0, 0.0));
+        listener
+    }
+
+    #[test]
+    fn test_spatialize_direction() {
+        let listener = test_listener();
+
+        let ahead = listener.spatialize(Vector3::new(100.0, 0.0, 0.0), 1.0, 0.0);
+        assert_eq!(
+            ahead,
+            StereoGain {
+                left: 1.0,
+                right: 1.0
+            }
+        );
+
+        let right = listener.spatialize(Vector3::new(0.0, -100.0, 0.0), 1.0, 0.0);
+        assert_eq!(
+            right,
+            StereoGain {
+                left: 0.0,
+                right: 2.0
+            }
+        );
+
+        let left = listener.spatialize(Vector3::new(0.0, 100.0, 0.0), 1.0, 0.0);
+        assert_eq!(
+            left,
+            StereoGain {
+                left: 2.0,
+                right: 0.0
+            }
+        );
+
+        // sounds at the listener are centered
+        let here = listener.spatialize(Vector3::new(0.0, 0.0, 0.0), 0.5, 1.0);
+        assert_eq!(
+            here,
+            StereoGain {
+                left: 0.5,
+                right: 0.5
+            }
+        );
+    }
+
+    #[test]
+    fn test_spatialize_distance() {
+        let listener = test_listener();
+
+        // attenuation 1 fades out over 1000 units
+        let near = listener.spatialize(Vector3::new(-250.0, 0.0, 0.0), 1.0, 1.0);
+        assert!((near.left - 0.75).abs() < 1e-6);
+        assert!((near.right - 0.75).abs() < 1e-6);
+
+        let far = listener.spatialize(Vector3::new(0.0, 2000.0, 0.0), 1.0, 1.0);
+        assert_eq!(
+            far,
+            StereoGain {
+                left: 0.0,
+                right: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_resample() {
+        let samples = [0.0, 1.0, 0.0, -1.0];
+        assert_eq!(
+            resample(&samples, 11025, 22050),
+            vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]
+        );
+        assert_eq!(resample(&samples, 44100, 22050), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_channel_mix() {
+        let listener = test_listener();
+        let chan = Channel::new();
+        let src = AudioSource::from_samples(&[1.0, 0.5, 0.25], 1, MIX_RATE);
+
+        // a sound directly to the right plays only in the right ear
+        chan.play(src, Vector3::new(0.0, -100.0, 0.0), &listener, 0.5, 0.0);
+        assert!(chan.in_use());
+
+        let mut out = vec![0.0; 4];
+        chan.mix(&mut out);
+        assert_eq!(out, vec![0.0, 1.0, 0.0, 0.5]);
+        assert!(chan.in_use());
+
+        // the channel is freed once the sound runs out
+        let mut out = vec![0.0; 4];
+        chan.mix(&mut out);
+        assert_eq!(out, vec![0.0, 0.25, 0.0, 0.0]);
+        assert!(!chan.in_use());
+    }
+
+    #[test]
+    fn test_static_sound_loops() {
+        let listener = test_listener();
+        let src = AudioSource::from_samples(&[1.0, -1.0], 1, MIX_RATE);
+        let sound = StaticSound::new(Vector3::new(100.0, 0.0, 0.0), src, 1.0, 0.0, &listener);
+
+        let mut out = vec![0.0; 10];
+        sound.mix(&mut out);
+        assert_eq!(
+            out,
+            vec![1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_channel_loop_start() {
+        let listener = test_listener();
+        let chan = Channel::new();
+
+        // the loop point is scaled along with the samples
+        let src = AudioSource::from_wav(&Wav {
+            channels: 1,
+            sample_rate: MIX_RATE / 2,
+            samples: vec![0.0, 0.5, 1.0, 0.0],
+            loop_start: Some(2),
+        });
+        assert_eq!(src.len(), 8);
+        assert_eq!(src.loop_start(), Some(4));
+
+        // entity sounds with a loop point keep playing from it
+        chan.play(src, Vector3::new(100.0, 0.0, 0.0), &listener, 1.0, 0.0);
+        let mut out = vec![0.0; 24];
+        chan.mix(&mut out);
+        let left: Vec<f32> = out.iter().step_by(2).cloned().collect();
+        assert_eq!(
+            left,
+            vec![0.0, 0.25, 0.5, 0.75, 1.0, 0.5, 0.0, 0.0, 1.0, 0.5, 0.0, 0.0]
+        );
+        assert!(chan.in_use());
+    }
+
+    // starts a long sound on `ent_id`, `distance` units in front of the listener
+    fn start_test_sound(
+        mixer: &mut EntityMixer,
+        listener: &Listener,
+        time_ms: i64,
+        ent_id: usize,
+        ent_channel: i8,
+        distance: f32,
+    ) {
+        mixer.start_sound(
+            AudioSource::from_samples(&[1.0; 64], 1, MIX_RATE),
+            Duration::milliseconds(time_ms),
+            Some(ent_id),
+            ent_channel,
+            1.0,
+            1.0,
+            Vector3::new(distance, 0.0, 0.0),
+            listener,
+        );
+    }
+
+    // returns the entity and entity channel of each playing sound
+    fn playing(mixer: &EntityMixer) -> Vec<(Option<usize>, i8)> {
+        mixer
+            .iter_entity_channels()
+            .filter(|c| c.channel().in_use())
+            .map(|c| (c.ent_id, c.ent_channel))
+            .collect()
+    }
+
+    #[test]
+    fn test_entity_mixer_override() {
+        let listener = test_listener();
+        let mut mixer = EntityMixer::new();
+
+        start_test_sound(&mut mixer, &listener, 0, 1, 1, 0.0);
+        start_test_sound(&mut mixer, &listener, 0, 1, 2, 0.0);
+        start_test_sound(&mut mixer, &listener, 0, 2, 1, 0.0);
+
+        // the same entity channel replaces the playing sound
+        start_test_sound(&mut mixer, &listener, 10, 1, 1, 0.0);
+        assert_eq!(
+            playing(&mixer),
+            vec![(Some(1), 1), (Some(1), 2), (Some(2), 1)]
+        );
+        assert_eq!(
+            mixer.channels[0].as_ref().unwrap().start_time,
+            Duration::milliseconds(10)
+        );
+
+        // channel 0 never replaces anything
+        start_test_sound(&mut mixer, &listener, 20, 2, 0, 0.0);
+        start_test_sound(&mut mixer, &listener, 20, 2, 0, 0.0);
+        assert_eq!(playing(&mixer).len(), 5);
+
+        // channel -1 replaces any sound on the entity
+        start_test_sound(&mut mixer, &listener, 30, 1, -1, 0.0);
+        assert_eq!(
+            playing(&mixer),
+            vec![
+                (Some(1), -1),
+                (Some(1), 2),
+                (Some(2), 1),
+                (Some(2), 0),
+                (Some(2), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entity_mixer_steal() {
+        let listener = test_listener();
+        let mut mixer = EntityMixer::new();
+        mixer.set_max_channels(3);
+
+        start_test_sound(&mut mixer, &listener, 0, 2, 1, 0.0);
+        start_test_sound(&mut mixer, &listener, 10, 3, 1, 500.0);
+        start_test_sound(&mut mixer, &listener, 20, 4, 1, 500.0);
+
+        // the quietest sound is replaced, the oldest of those if there's a tie
+        start_test_sound(&mut mixer, &listener, 30, 5, 1, 0.0);
+        assert_eq!(
+            playing(&mixer),
+            vec![(Some(2), 1), (Some(5), 1), (Some(4), 1)]
+        );
+
+        // lowering the limit stops sounds past it
+        mixer.set_max_channels(1);
+        assert_eq!(playing(&mixer), vec![(Some(2), 1)]);
+    }
+
+    #[test]
+    fn test_entity_mixer_player_priority() {
+        let listener = test_listener();
+        let mut mixer = EntityMixer::new();
+        mixer.set_max_channels(2);
+        mixer.set_view_entity(1);
+
+        // the player's sound is quieter, but monster sounds can't replace it
+        start_test_sound(&mut mixer, &listener, 0, 1, 1, 500.0);
+        start_test_sound(&mut mixer, &listener, 10, 2, 1, 0.0);
+        start_test_sound(&mut mixer, &listener, 20, 3, 1, 0.0);
+        assert_eq!(playing(&mixer), vec![(Some(1), 1), (Some(3), 1)]);
+
+        // the player's own sounds can
+        start_test_sound(&mut mixer, &listener, 30, 1, 2, 0.0);
+        assert_eq!(playing(&mixer), vec![(Some(1), 2), (Some(3), 1)]);
+        start_test_sound(&mut mixer, &listener, 40, 1, 3, 0.0);
+        assert_eq!(playing(&mixer), vec![(Some(1), 2), (Some(1), 3)]);
+
+        // with only player sounds playing, monster sounds are dropped
+        start_test_sound(&mut mixer, &listener, 50, 4, 1, 0.0);
+        assert_eq!(playing(&mixer), vec![(Some(1), 2), (Some(1), 3)]);
     }
 }
@@ -3,37 +3,99 @@ use std::{
     rc::Rc,
 };
 
-use crate::{client::sound::SoundError, common::vfs::Vfs};
+use crate::{
+    client::sound::SoundError,
+    common::{engine::duration_to_f32, vfs::Vfs},
+};
 
+use chrono::Duration;
 use rodio::{Decoder, OutputStreamHandle, Sink, Source};
 
+/// Directories searched for music tracks, in order.
+pub const MUSIC_DIRS: [&str; 2] = ["music", "sound/cdtracks"];
+
+/// Music file extensions, in order of preference.
+pub const MUSIC_EXTENSIONS: [&str; 4] = ["ogg", "flac", "mp3", "wav"];
+
+/// Whether a music track starts over when it ends.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlayMode {
+    Loop,
+    Once,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MusicVars {
+    /// The volume of music, from 0 to 1.
+    pub bgmvolume: f32,
+
+    /// How long a new track takes to fade in over the old one, in seconds.
+    pub bgm_crossfade: f32,
+}
+
+/// Returns the virtual paths where the track `name` may be found, in the order they're searched.
+///
+/// If `name` has no extension, each supported format is tried in each music directory. Otherwise,
+/// `name` is tried as given and then in each music directory.
+pub fn track_paths(name: &str) -> Vec<String> {
+    if name.contains('.') {
+        let mut paths = vec![name.to_owned()];
+        paths.extend(MUSIC_DIRS.iter().map(|dir| format!("{}/{}", dir, name)));
+        paths
+    } else {
+        MUSIC_DIRS
+            .iter()
+            .flat_map(|dir| {
+                MUSIC_EXTENSIONS
+                    .iter()
+                    .map(move |ext| format!("{}/{}.{}", dir, name, ext))
+            })
+            .collect()
+    }
+}
+
+struct Track {
+    name: String,
+
+    // if None, the player has no output stream
+    sink: Option<Sink>,
+
+    // how far the track has faded in, from 0 to 1
+    fade: f32,
+}
+
 /// Plays music tracks.
 pub struct MusicPlayer {
     vfs: Rc<Vfs>,
-    stream: OutputStreamHandle,
-    playing: Option<String>,
-    sink: Option<Sink>,
+    // if None, tracks are loaded but not played
+    stream: Option<OutputStreamHandle>,
+    playing: Option<Track>,
+
+    // old tracks fading out under the current one
+    fading: Vec<Track>,
+    paused: bool,
 }
 
 impl MusicPlayer {
-    pub fn new(vfs: Rc<Vfs>, stream: OutputStreamHandle) -> MusicPlayer {
+    pub fn new(vfs: Rc<Vfs>, stream: Option<OutputStreamHandle>) -> MusicPlayer {
         MusicPlayer {
             vfs,
             stream,
             playing: None,
-            sink: None,
+            fading: Vec::new(),
+            paused: false,
         }
     }
 
     /// Start playing the track with the given name.
     ///
-    /// Music tracks are expected to be in the "music/" directory of the virtual
-    /// filesystem, so they can be placed either in an actual directory
-    /// `"id1/music/"` or packaged in a PAK archive with a path beginning with
-    /// `"music/"`.
+    /// Tracks are searched for in the order given by `track_paths`, so they can be placed in an
+    /// actual directory like `"id1/music/"` or packaged in a PAK archive with a path beginning
+    /// with `"music/"` or `"sound/cdtracks/"`.
     ///
-    /// If the specified track is already playing, this has no effect.
-    pub fn play_named<S>(&mut self, name: S) -> Result<(), SoundError>
+    /// If another track is playing, the new track fades in over it. If the specified track is
+    /// already playing, this has no effect.
+    pub fn play_named<S>(&mut self, name: S, mode: PlayMode) -> Result<(), SoundError>
     where
         S: AsRef<str>,
     {
@@ -41,37 +103,49 @@ impl MusicPlayer {
 
         // don't replay the same track
         if let Some(ref playing) = self.playing {
-            if playing == name {
+            if playing.name == name {
                 return Ok(());
             }
         }
 
-        // TODO: there's probably a better way to do this extension check
-        let mut file = if !name.contains('.') {
-            // try all supported formats
-            self.vfs
-                .open(format!("music/{}.flac", name))
-                .or_else(|_| self.vfs.open(format!("music/{}.wav", name)))
-                .or_else(|_| self.vfs.open(format!("music/{}.mp3", name)))
-                .or_else(|_| self.vfs.open(format!("music/{}.ogg", name)))
-                .or(Err(SoundError::NoSuchTrack(name.to_owned())))?
-        } else {
-            self.vfs.open(name)?
-        };
+        let mut file = track_paths(name)
+            .iter()
+            .find_map(|path| self.vfs.open(path).ok())
+            .ok_or_else(|| SoundError::NoSuchTrack(name.to_owned()))?;
 
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
         let source = Decoder::new(Cursor::new(data))?
             .convert_samples::<f32>()
-            .buffered()
-            .repeat_infinite();
+            .buffered();
+
+        // only fade in if there's something to fade in over
+        let fade = if self.playing.is_some() { 0.0 } else { 1.0 };
 
-        // stop the old track before starting the new one so there's no overlap
-        self.sink = None;
-        // TODO handle PlayError
-        let new_sink = Sink::try_new(&self.stream).unwrap();
-        new_sink.append(source);
-        self.sink = Some(new_sink);
+        let sink = match self.stream {
+            Some(ref stream) => {
+                let sink = Sink::try_new(stream).map_err(|e| SoundError::Device(e.to_string()))?;
+                sink.set_volume(0.0);
+                match mode {
+                    PlayMode::Loop => sink.append(source.repeat_infinite()),
+                    PlayMode::Once => sink.append(source),
+                }
+
+                Some(sink)
+            }
+
+            None => None,
+        };
+
+        let new_track = Track {
+            name: name.to_owned(),
+            sink,
+            fade,
+        };
+
+        if let Some(old) = self.playing.replace(new_track) {
+            self.fading.push(old);
+        }
 
         Ok(())
     }
@@ -80,19 +154,64 @@ impl MusicPlayer {
     ///
     /// Note that the first actual music track is track 2; track 1 on the
     /// original Quake CD-ROM held the game data.
-    pub fn play_track(&mut self, track_id: usize) -> Result<(), SoundError> {
-        self.play_named(format!("track{:02}", track_id))
+    pub fn play_track(&mut self, track_id: usize, mode: PlayMode) -> Result<(), SoundError> {
+        self.play_named(format!("track{:02}", track_id), mode)
+    }
+
+    /// Returns the name of the current track, if there is one.
+    pub fn playing(&self) -> Option<&str> {
+        self.playing.as_ref().map(|t| t.name.as_str())
+    }
+
+    /// Advances crossfades by `frame_time` and applies the music volume.
+    ///
+    /// A track played once is forgotten when it ends.
+    pub fn update(&mut self, frame_time: Duration, vars: MusicVars) {
+        if !self.paused {
+            let step = match vars.bgm_crossfade {
+                t if t > 0.0 => duration_to_f32(frame_time) / t,
+                _ => 1.0,
+            };
+
+            if let Some(ref mut track) = self.playing {
+                track.fade = (track.fade + step).min(1.0);
+            }
+
+            for track in self.fading.iter_mut() {
+                track.fade -= step;
+            }
+
+            self.fading.retain(|t| t.fade > 0.0);
+        }
+
+        for track in self.playing.iter().chain(self.fading.iter()) {
+            if let Some(ref sink) = track.sink {
+                sink.set_volume(track.fade * vars.bgmvolume);
+            }
+        }
+
+        let finished = match self.playing {
+            Some(Track {
+                sink: Some(ref sink),
+                ..
+            }) => sink.empty(),
+            _ => false,
+        };
+
+        if finished {
+            self.playing = None;
+        }
     }
 
     /// Stop the current music track.
     ///
-    /// This ceases playback entirely. To pause the track, allowing it to be
-    /// resumed later, use `MusicPlayer::pause()`.
+    /// This ceases playback entirely, without fading out. To pause the track,
+    /// allowing it to be resumed later, use `MusicPlayer::pause()`.
     ///
     /// If no music track is currently playing, this has no effect.
     pub fn stop(&mut self) {
-        self.sink = None;
         self.playing = None;
+        self.fading.clear();
     }
 
     /// Pause the current music track.
@@ -100,8 +219,11 @@ impl MusicPlayer {
     /// If no music track is currently playing, or if the current track is
     /// already paused, this has no effect.
     pub fn pause(&mut self) {
-        if let Some(ref mut sink) = self.sink {
-            sink.pause();
+        self.paused = true;
+        for track in self.playing.iter().chain(self.fading.iter()) {
+            if let Some(ref sink) = track.sink {
+                sink.pause();
+            }
         }
     }
 
@@ -110,8 +232,73 @@ impl MusicPlayer {
     /// If no music track is currently playing, or if the current track is not
     /// paused, this has no effect.
     pub fn resume(&mut self) {
-        if let Some(ref mut sink) = self.sink {
-            sink.play();
+        self.paused = false;
+        for track in self.playing.iter().chain(self.fading.iter()) {
+            if let Some(ref sink) = track.sink {
+                sink.play();
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        client::sound::{AudioBackend, WavBackend},
+        common::util::TempDir,
+    };
+
+    const VARS: MusicVars = MusicVars {
+        bgmvolume: 1.0,
+        bgm_crossfade: 2.0,
+    };
+
+    #[test]
+    fn test_music_track_paths() {
+        let paths = track_paths("track02");
+        assert_eq!(paths.len(), MUSIC_DIRS.len() * MUSIC_EXTENSIONS.len());
+        assert_eq!(paths[0], "music/track02.ogg");
+        assert_eq!(paths[3], "music/track02.wav");
+        assert_eq!(paths[4], "sound/cdtracks/track02.ogg");
+
+        assert_eq!(
+            track_paths("loop.mp3"),
+            vec!["loop.mp3", "music/loop.mp3", "sound/cdtracks/loop.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_music_player_crossfade() {
+        let dir = TempDir::new("music");
+        for path in &["music/track02.wav", "sound/cdtracks/track03.wav"] {
+            let mut wav = WavBackend::create(dir.file(path)).unwrap();
+            wav.write(&[0.0; 64]).unwrap();
+        }
+
+        let mut player = MusicPlayer::new(Rc::new(dir.vfs()), None);
+
+        // the first track starts at full volume
+        player.play_track(2, PlayMode::Loop).unwrap();
+        assert_eq!(player.playing(), Some("track02"));
+        assert_eq!(player.playing.as_ref().unwrap().fade, 1.0);
+
+        // the next, found in another directory, fades in over it
+        player.play_track(3, PlayMode::Once).unwrap();
+        assert_eq!(player.playing(), Some("track03"));
+        player.update(Duration::seconds(1), VARS);
+        assert_eq!(player.playing.as_ref().unwrap().fade, 0.5);
+        assert_eq!(player.fading[0].fade, 0.5);
+        player.update(Duration::seconds(1), VARS);
+        assert_eq!(player.playing.as_ref().unwrap().fade, 1.0);
+        assert!(player.fading.is_empty());
+
+        // missing tracks leave the current one playing
+        assert!(matches!(
+            player.play_track(4, PlayMode::Loop),
+            Err(SoundError::NoSuchTrack(_))
+        ));
+        assert_eq!(player.playing(), Some("track03"));
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! WAV file parsing.
+//!
+//! Besides the samples, Quake sound effects carry a `cue ` chunk marking the sample where a looping
+//! sound restarts, and sometimes a `LIST` chunk giving the length of the loop. Generic decoders
+//! ignore both, so sounds like torches and machinery are parsed here instead.
+
+use std::io::{self, Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+
+#[derive(Error, Debug)]
+pub enum WavError {
+    #[error("Not a RIFF WAVE file")]
+    NotWave,
+    #[error("Missing {0} chunk")]
+    MissingChunk(&'static str),
+    #[error("Unsupported WAV format {format} with {bits} bits per sample")]
+    UnsupportedFormat { format: u16, bits: u16 },
+    #[error("Invalid WAV format chunk")]
+    InvalidFormat,
+}
+
+/// The contents of a WAV file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+    pub channels: u16,
+    pub sample_rate: u32,
+
+    /// Interleaved samples from -1 to 1.
+    pub samples: Vec<f32>,
+
+    /// The frame to restart at when the sound loops, if it does.
+    pub loop_start: Option<usize>,
+}
+
+impl Wav {
+    /// Returns the number of frames (samples per channel).
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+}
+
+// a chunk's ID and contents
+struct Chunk<'a> {
+    id: &'a [u8],
+    data: &'a [u8],
+}
+
+fn chunks(mut data: &[u8]) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+
+    while data.len() >= 8 {
+        let id = &data[0..4];
+        let len = (&data[4..8]).read_u32::<LittleEndian>().unwrap() as usize;
+
+        // tolerate a truncated final chunk, as the original engine did
+        let end = (8 + len).min(data.len());
+        chunks.push(Chunk {
+            id,
+            data: &data[8..end],
+        });
+
+        // chunks are padded to an even length
+        let next = (end + (len & 1)).min(data.len());
+        data = &data[next..];
+    }
+
+    chunks
+}
+
+/// Parses a WAV file.
+///
+/// Integer PCM of 8, 16, 24 or 32 bits and 32-bit float samples are supported.
+pub fn parse(data: &[u8]) -> Result<Wav, WavError> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(WavError::NotWave);
+    }
+
+    let chunks = chunks(&data[12..]);
+    let find = |id: &[u8]| chunks.iter().position(|c| c.id == id);
+
+    let fmt = &chunks[find(b"fmt ").ok_or(WavError::MissingChunk("fmt "))?];
+    let (format, channels, sample_rate, bits) =
+        read_format(fmt.data).map_err(|_| WavError::InvalidFormat)?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(WavError::InvalidFormat);
+    }
+
+    let pcm = &chunks[find(b"data").ok_or(WavError::MissingChunk("data"))?].data;
+    let mut samples = decode(pcm, format, bits)?;
+
+    let mut loop_start = None;
+    if let Some(cue_id) = find(b"cue ") {
+        // the sample offset of the first cue point
+        let start = chunks[cue_id]
+            .data
+            .get(24..)
+            .and_then(|mut d| d.read_u32::<LittleEndian>().ok());
+
+        if let Some(start) = start {
+            loop_start = Some(start as usize);
+
+            // a "mark" label in a following LIST chunk gives the length of the loop, after which
+            // the rest of the sound is never played
+            let list = chunks[cue_id + 1..].iter().find(|c| c.id == b"LIST");
+            if let Some(list) = list {
+                if list.data.get(20..24) == Some(&b"mark"[..]) {
+                    if let Some(len) = list
+                        .data
+                        .get(16..)
+                        .and_then(|mut d| d.read_u32::<LittleEndian>().ok())
+                    {
+                        let end = (start as usize + len as usize) * channels as usize;
+                        samples.truncate(end);
+                    }
+                }
+            }
+        }
+    }
+
+    let wav = Wav {
+        channels,
+        sample_rate,
+        samples,
+        loop_start,
+    };
+
+    // ignore loop points past the end of the sound
+    let frames = wav.frames();
+    Ok(Wav {
+        loop_start: wav.loop_start.filter(|&start| start < frames),
+        ..wav
+    })
+}
+
+// returns the format tag, channel count, sample rate and bits per sample
+fn read_format(data: &[u8]) -> io::Result<(u16, u16, u32, u16)> {
+    let mut reader = Cursor::new(data);
+    let format = reader.read_u16::<LittleEndian>()?;
+    let channels = reader.read_u16::<LittleEndian>()?;
+    let sample_rate = reader.read_u32::<LittleEndian>()?;
+    let _byte_rate = reader.read_u32::<LittleEndian>()?;
+    let _block_align = reader.read_u16::<LittleEndian>()?;
+    let bits = reader.read_u16::<LittleEndian>()?;
+    Ok((format, channels, sample_rate, bits))
+}
+
+fn decode(pcm: &[u8], format: u16, bits: u16) -> Result<Vec<f32>, WavError> {
+    let mut reader = Cursor::new(pcm);
+    let mut samples = Vec::new();
+
+    match (format, bits) {
+        // 8-bit samples are unsigned
+        (FORMAT_PCM, 8) => samples.extend(pcm.iter().map(|&s| (s as f32 - 128.0) / 128.0)),
+        (FORMAT_PCM, 16) => {
+            while let Ok(s) = reader.read_i16::<LittleEndian>() {
+                samples.push(s as f32 / 32768.0);
+            }
+        }
+        (FORMAT_PCM, 24) => {
+            while let Ok(s) = reader.read_i24::<LittleEndian>() {
+                samples.push(s as f32 / 8388608.0);
+            }
+        }
+        (FORMAT_PCM, 32) => {
+            while let Ok(s) = reader.read_i32::<LittleEndian>() {
+                samples.push(s as f32 / 2147483648.0);
+            }
+        }
+        (FORMAT_FLOAT, 32) => {
+            while let Ok(s) = reader.read_f32::<LittleEndian>() {
+                samples.push(s);
+            }
+        }
+        _ => return Err(WavError::UnsupportedFormat { format, bits }),
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use byteorder::WriteBytesExt;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        c.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    }
+
+    fn fmt(channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut f = Vec::new();
+        f.write_u16::<LittleEndian>(FORMAT_PCM).unwrap();
+        f.write_u16::<LittleEndian>(channels).unwrap();
+        f.write_u32::<LittleEndian>(rate).unwrap();
+        f.write_u32::<LittleEndian>(rate * block_align as u32)
+            .unwrap();
+        f.write_u16::<LittleEndian>(block_align).unwrap();
+        f.write_u16::<LittleEndian>(bits).unwrap();
+        chunk(b"fmt ", &f)
+    }
+
+    fn cue(start: u32) -> Vec<u8> {
+        let mut c = Vec::new();
+        c.write_u32::<LittleEndian>(1).unwrap(); // cue point count
+        c.write_u32::<LittleEndian>(1).unwrap(); // id
+        c.write_u32::<LittleEndian>(start).unwrap(); // position
+        c.extend_from_slice(b"data");
+        c.write_u32::<LittleEndian>(0).unwrap(); // chunk start
+        c.write_u32::<LittleEndian>(0).unwrap(); // block start
+        c.write_u32::<LittleEndian>(start).unwrap(); // sample offset
+        chunk(b"cue ", &c)
+    }
+
+    fn list_mark(len: u32) -> Vec<u8> {
+        let mut l = b"adtl".to_vec();
+        let mut ltxt = Vec::new();
+        ltxt.write_u32::<LittleEndian>(1).unwrap(); // cue id
+        ltxt.write_u32::<LittleEndian>(len).unwrap();
+        ltxt.extend_from_slice(b"mark");
+        ltxt.extend_from_slice(&[0; 8]);
+        l.extend(chunk(b"ltxt", &ltxt));
+        chunk(b"LIST", &l)
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for c in chunks {
+            body.extend_from_slice(c);
+        }
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn test_wav_parse_8bit() {
+        let data = riff(&[fmt(1, 11025, 8), chunk(b"data", &[128, 255, 0])]);
+        let wav = parse(&data).unwrap();
+        assert_eq!(wav.channels, 1);
+        assert_eq!(wav.sample_rate, 11025);
+        assert_eq!(wav.samples, vec![0.0, 127.0 / 128.0, -1.0]);
+        assert_eq!(wav.loop_start, None);
+    }
+
+    #[test]
+    fn test_wav_parse_16bit_stereo() {
+        let mut pcm = Vec::new();
+        for s in &[0i16, -32768, 16384, 0] {
+            pcm.write_i16::<LittleEndian>(*s).unwrap();
+        }
+
+        let wav = parse(&riff(&[fmt(2, 22050, 16), chunk(b"data", &pcm)])).unwrap();
+        assert_eq!(wav.frames(), 2);
+        assert_eq!(wav.samples, vec![0.0, -1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_wav_parse_cue() {
+        let pcm = [128u8; 10];
+        let wav = parse(&riff(&[fmt(1, 11025, 8), chunk(b"data", &pcm), cue(4)])).unwrap();
+        assert_eq!(wav.loop_start, Some(4));
+        assert_eq!(wav.frames(), 10);
+
+        // a LIST mark ends the sound after the loop
+        let wav = parse(&riff(&[
+            fmt(1, 11025, 8),
+            chunk(b"data", &pcm),
+            cue(4),
+            list_mark(3),
+        ]))
+        .unwrap();
+        assert_eq!(wav.loop_start, Some(4));
+        assert_eq!(wav.frames(), 7);
+
+        // loop points past the end are ignored
+        let wav = parse(&riff(&[fmt(1, 11025, 8), chunk(b"data", &pcm), cue(20)])).unwrap();
+        assert_eq!(wav.loop_start, None);
+    }
+
+    #[test]
+    fn test_wav_parse_errors() {
+        assert!(matches!(parse(b"not a wav file"), Err(WavError::NotWave)));
+        assert!(matches!(
+            parse(&riff(&[chunk(b"data", &[0])])),
+            Err(WavError::MissingChunk("fmt "))
+        ));
+        assert!(matches!(
+            parse(&riff(&[fmt(1, 11025, 12), chunk(b"data", &[0])])),
+            Err(WavError::UnsupportedFormat { bits: 12, .. })
+        ));
+    }
+}
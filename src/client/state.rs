@@ -8,8 +8,12 @@ use crate::{
             Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_LIGHTS, MAX_TEMP_ENTITIES,
         },
         input::game::{Action, GameInput},
+        precache::MissingAssets,
         render::Camera,
-        sound::{AudioSource, EntityMixer, Listener, StaticSound},
+        sound::{
+            AmbientMixer, AmbientVars, AudioSource, Dsp, DspVars, EntityMixer, Listener,
+            StaticSound,
+        },
         view::{IdleVars, KickVars, MouseVars, RollVars, View},
         ClientError, ColorShiftCode, IntermissionKind, MoveVars, MAX_STATS,
     },
@@ -33,7 +37,6 @@ use rand::{
     rngs::SmallRng,
     SeedableRng,
 };
-use rodio::OutputStreamHandle;
 
 const CACHED_SOUND_NAMES: &[&'static str] = &[
     "hknight/hit.wav",
@@ -115,12 +118,17 @@ pub struct ClientState {
     pub completion_time: Option<Duration>,
 
     pub mixer: EntityMixer,
+    pub ambient: AmbientMixer,
+    pub dsp: Dsp,
     pub listener: Listener,
+
+    // assets that couldn't be loaded during sign-on
+    pub missing_assets: MissingAssets,
 }
 
 impl ClientState {
     // TODO: add parameter for number of player slots and reserve them in entity list
-    pub fn new(stream: OutputStreamHandle) -> ClientState {
+    pub fn new() -> ClientState {
         ClientState {
             rng: SmallRng::from_entropy(),
             models: vec![Model::none()],
@@ -171,18 +179,30 @@ impl ClientState {
             intermission: None,
             start_time: Duration::zero(),
             completion_time: None,
-            mixer: EntityMixer::new(stream),
+            mixer: EntityMixer::new(),
+            ambient: AmbientMixer::new(Default::default()),
+            dsp: Dsp::new(),
             listener: Listener::new(),
+            missing_assets: MissingAssets::new(),
         }
     }
 
+    /// Replaces the random number generator with one seeded from `seed`, so that effects like
+    /// particles and light radii are reproducible.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
     pub fn from_server_info(
         vfs: &Vfs,
-        stream: OutputStreamHandle,
         max_clients: u8,
         model_precache: Vec<String>,
         sound_precache: Vec<String>,
     ) -> Result<ClientState, ClientError> {
+        // missing assets other than the world are replaced with placeholders and reported
+        // together once sign-on is done
+        let mut missing_assets = MissingAssets::new();
+
         // TODO: validate submodel names
         let mut models = Vec::with_capacity(model_precache.len());
         models.push(Model::none());
@@ -190,8 +210,33 @@ impl ClientState {
         for mod_name in model_precache {
             // BSPs can have more than one model
             if mod_name.ends_with(".bsp") {
-                let bsp_data = vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
+                let loaded = vfs
+                    .open(&mod_name)
+                    .map_err(ClientError::from)
+                    .and_then(|f| {
+                        bsp::load(f).map_err(|e| ClientError::BspFile(mod_name.clone(), e))
+                    });
+
+                let (mut brush_models, _) = match loaded {
+                    Ok(l) => l,
+
+                    // there's nothing to play without the world
+                    Err(e) if models.len() == 1 => return Err(e),
+
+                    Err(e) => {
+                        warn!("Couldn't load model {}: {}", mod_name, e);
+                        missing_assets.models.push(mod_name.clone());
+                        model_names.insert(mod_name.clone(), models.len());
+                        models.push(Model::placeholder(&mod_name));
+                        continue;
+                    }
+                };
+
+                // all of a file's models share its textures
+                if let Some(ModelKind::Brush(bmodel)) = brush_models.first().map(|m| m.kind()) {
+                    missing_assets.check_textures(&mod_name, &bmodel.bsp_data());
+                }
+
                 for bmodel in brush_models.drain(..) {
                     let id = models.len();
                     let name = bmodel.name().to_owned();
@@ -202,32 +247,36 @@ impl ClientState {
                 // model names starting with * are loaded from the world BSP
                 debug!("Loading model {}", mod_name);
                 let id = models.len();
-                models.push(Model::load(vfs, &mod_name)?);
+                models.push(missing_assets.load_model(vfs, &mod_name));
                 model_names.insert(mod_name, id);
             }
 
             // TODO: send keepalive message?
         }
 
-        let mut sounds = vec![AudioSource::load(&vfs, "misc/null.wav")?];
+        let mut sounds = vec![missing_assets.load_sound(vfs, "misc/null.wav")];
         for ref snd_name in sound_precache {
             debug!("Loading sound {}: {}", sounds.len(), snd_name);
-            sounds.push(AudioSource::load(vfs, snd_name)?);
+            sounds.push(missing_assets.load_sound(vfs, snd_name));
             // TODO: send keepalive message?
         }
 
         let mut cached_sounds = HashMap::new();
         for name in CACHED_SOUND_NAMES {
-            cached_sounds.insert(name.to_string(), AudioSource::load(vfs, name)?);
+            cached_sounds.insert(name.to_string(), missing_assets.load_sound(vfs, name));
         }
 
+        let ambient = AmbientMixer::new(AmbientMixer::load_sources(vfs, &mut missing_assets));
+
         Ok(ClientState {
             models,
             model_names,
             sounds,
             cached_sounds,
+            ambient,
+            missing_assets,
             max_players: max_clients as usize,
-            ..ClientState::new(stream)
+            ..ClientState::new()
         })
     }
 
@@ -1074,6 +1123,41 @@ impl ClientState {
         }
     }
 
+    // applies `f` to the leaf containing the listener, if there is a world
+    fn with_listener_leaf<F, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&bsp::BspLeaf) -> T,
+    {
+        match self.models.get(1).map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) => {
+                let bsp_data = bmodel.bsp_data();
+                let leaf_id = bsp_data.find_leaf(self.listener.origin());
+                Some(f(&bsp_data.leaves()[leaf_id]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Fades the ambient sounds toward the levels of the listener's leaf.
+    pub fn update_ambient_sounds(&mut self, vars: AmbientVars, frame_time: Duration) {
+        let levels = self.with_listener_leaf(|leaf| leaf.ambient_levels);
+        self.ambient.update(levels, vars, frame_time);
+    }
+
+    /// Adds the next samples of every playing sound to the interleaved stereo buffer `out`.
+    ///
+    /// The result is filtered according to the contents of the listener's leaf.
+    pub fn paint_sound(&mut self, out: &mut [f32], dsp_vars: DspVars) {
+        self.mixer.mix(out);
+        for ss in self.static_sounds.iter() {
+            ss.mix(out);
+        }
+        self.ambient.mix(out);
+
+        let contents = self.with_listener_leaf(|leaf| leaf.contents);
+        self.dsp.process(contents, dsp_vars, out);
+    }
+
     fn view_leaf_contents(&self) -> Result<bsp::BspLeafContents, ClientError> {
         match self.models[1].kind() {
             ModelKind::Brush(ref bmodel) => {
@@ -1193,6 +1277,7 @@ impl ClientState {
             Err(ClientError::InvalidViewEntity(entity_id))?;
         }
         self.view.set_entity_id(entity_id);
+        self.mixer.set_view_entity(entity_id);
         Ok(())
     }
 
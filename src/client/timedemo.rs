@@ -0,0 +1,252 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Benchmarking of the client by playing back demos as fast as possible.
+//!
+//! During a timedemo, each client frame plays exactly one demo message regardless of how much
+//! time has passed, and the CPU time spent on each stage of the frame is recorded.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// CPU time spent on each stage of a single client frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimes {
+    /// Time spent parsing server messages and updating entities, lights and the view.
+    pub state: Duration,
+
+    /// Time spent on particle physics.
+    pub particles: Duration,
+
+    /// Time spent generating the client's move command.
+    pub commands: Duration,
+}
+
+impl FrameTimes {
+    pub fn total(&self) -> Duration {
+        self.state + self.particles + self.commands
+    }
+}
+
+/// Frame times recorded over the course of a timedemo.
+#[derive(Debug)]
+pub struct Timedemo {
+    start: Instant,
+    frames: Vec<FrameTimes>,
+}
+
+impl Timedemo {
+    pub fn new() -> Timedemo {
+        Timedemo {
+            start: Instant::now(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Records the simulation stages of a new frame.
+    pub fn push_frame(&mut self, state: Duration, particles: Duration) {
+        self.frames.push(FrameTimes {
+            state,
+            particles,
+            commands: Duration::default(),
+        });
+    }
+
+    /// Records the time taken to generate the move command for the most recent frame.
+    pub fn record_commands(&mut self, commands: Duration) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.commands += commands;
+        }
+    }
+
+    pub fn frames(&self) -> &[FrameTimes] {
+        &self.frames
+    }
+
+    /// Ends the timedemo and computes its statistics.
+    pub fn finish(self) -> TimedemoSummary {
+        TimedemoSummary::new(&self.frames, self.start.elapsed())
+    }
+}
+
+/// Statistics for one stage of the client frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageStats {
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl StageStats {
+    fn new<I>(samples: I) -> StageStats
+    where
+        I: IntoIterator<Item = Duration>,
+    {
+        let mut samples: Vec<Duration> = samples.into_iter().collect();
+        if samples.is_empty() {
+            return StageStats::default();
+        }
+
+        samples.sort();
+        let sum: Duration = samples.iter().sum();
+
+        // nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p * samples.len() as f64).ceil() as usize;
+            samples[rank.max(1) - 1]
+        };
+
+        StageStats {
+            mean: sum / samples.len() as u32,
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: *samples.last().unwrap(),
+        }
+    }
+}
+
+/// The results of a timedemo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedemoSummary {
+    /// The number of frames played.
+    pub frames: usize,
+
+    /// The wall time taken to play the demo.
+    pub elapsed: Duration,
+
+    pub state: StageStats,
+    pub particles: StageStats,
+    pub commands: StageStats,
+    pub total: StageStats,
+}
+
+impl TimedemoSummary {
+    pub fn new(frames: &[FrameTimes], elapsed: Duration) -> TimedemoSummary {
+        TimedemoSummary {
+            frames: frames.len(),
+            elapsed,
+            state: StageStats::new(frames.iter().map(|f| f.state)),
+            particles: StageStats::new(frames.iter().map(|f| f.particles)),
+            commands: StageStats::new(frames.iter().map(|f| f.commands)),
+            total: StageStats::new(frames.iter().map(FrameTimes::total)),
+        }
+    }
+
+    /// Returns the average frame rate over the whole demo.
+    pub fn fps(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            s if s > 0.0 => self.frames as f64 / s,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for TimedemoSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames {:.1} seconds {:.1} fps",
+            self.frames,
+            self.elapsed.as_secs_f64(),
+            self.fps()
+        )?;
+
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:<10} {:>8} {:>8} {:>8} {:>8} {:>8} (ms)",
+            "", "mean", "p50", "p95", "p99", "max"
+        )?;
+        for (name, stats) in &[
+            ("state", self.state),
+            ("particles", self.particles),
+            ("commands", self.commands),
+            ("total", self.total),
+        ] {
+            write!(
+                f,
+                "\n{:<10} {:>8.3} {:>8.3} {:>8.3} {:>8.3} {:>8.3}",
+                name,
+                ms(stats.mean),
+                ms(stats.p50),
+                ms(stats.p95),
+                ms(stats.p99),
+                ms(stats.max)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timedemo_summary_percentiles() {
+        let frames: Vec<FrameTimes> = (1..=100)
+            .rev()
+            .map(|ms| FrameTimes {
+                state: Duration::from_millis(ms),
+                particles: Duration::from_millis(1),
+                commands: Duration::default(),
+            })
+            .collect();
+
+        let summary = TimedemoSummary::new(&frames, Duration::from_secs(2));
+        assert_eq!(summary.frames, 100);
+        assert_eq!(summary.fps(), 50.0);
+
+        assert_eq!(summary.state.mean, Duration::from_micros(50500));
+        assert_eq!(summary.state.p50, Duration::from_millis(50));
+        assert_eq!(summary.state.p95, Duration::from_millis(95));
+        assert_eq!(summary.state.p99, Duration::from_millis(99));
+        assert_eq!(summary.state.max, Duration::from_millis(100));
+        assert_eq!(summary.particles.p99, Duration::from_millis(1));
+        assert_eq!(summary.total.max, Duration::from_millis(101));
+    }
+
+    #[test]
+    fn test_timedemo_record_commands() {
+        let mut timedemo = Timedemo::new();
+
+        // nothing to attach to yet
+        timedemo.record_commands(Duration::from_millis(5));
+        assert!(timedemo.frames().is_empty());
+
+        timedemo.push_frame(Duration::from_millis(2), Duration::from_millis(1));
+        timedemo.record_commands(Duration::from_millis(3));
+        assert_eq!(timedemo.frames()[0].total(), Duration::from_millis(6));
+
+        let summary = timedemo.finish();
+        assert_eq!(summary.frames, 1);
+        assert_eq!(summary.commands.max, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn test_timedemo_summary_empty() {
+        let summary = TimedemoSummary::new(&[], Duration::default());
+        assert_eq!(summary.fps(), 0.0);
+        assert_eq!(summary.total, StageStats::default());
+    }
+}
@@ -27,7 +27,7 @@ use crate::common::{
         BspCollisionHull, BspCollisionNode, BspCollisionNodeChild, BspData, BspEdge,
         BspEdgeDirection, BspEdgeIndex, BspFace, BspFaceSide, BspLeaf, BspLeafContents, BspModel,
         BspRenderNode, BspRenderNodeChild, BspTexInfo, BspTexture, MAX_HULLS, MAX_LIGHTSTYLES,
-        MIPLEVELS,
+        MIPLEVELS, NUM_AMBIENTS,
     },
     math::{Axis, Hyperplane},
     model::Model,
@@ -63,7 +63,6 @@ const MAX_VISLIST: usize = 0x100000;
 
 const TEX_NAME_MAX: usize = 16;
 
-const NUM_AMBIENTS: usize = 4;
 const MAX_TEXTURE_FRAMES: usize = 10;
 const TEXTURE_FRAME_LEN_MS: i64 = 200;
 
@@ -253,6 +252,31 @@ struct BspFileTexture {
     mipmaps: [Vec<u8>; MIPLEVELS],
 }
 
+// the checkerboard drawn in place of textures missing from the BSP file, as in the original.
+//
+// the name is left empty, since the file doesn't give one.
+fn missing_texture() -> BspFileTexture {
+    const SIZE: usize = 16;
+
+    let mut mipmaps: [Vec<u8>; MIPLEVELS] = Default::default();
+    for (level, mipmap) in mipmaps.iter_mut().enumerate() {
+        let size = SIZE >> level;
+        for y in 0..size {
+            for x in 0..size {
+                let dark = (y < size / 2) ^ (x < size / 2);
+                mipmap.push(if dark { 0 } else { 0xff });
+            }
+        }
+    }
+
+    BspFileTexture {
+        name: String::new(),
+        width: SIZE as u32,
+        height: SIZE as u32,
+        mipmaps,
+    }
+}
+
 // load a textures from the BSP file.
 //
 // converts the texture's name to all lowercase, including its frame specifier
@@ -509,14 +533,7 @@ where
                 file_textures.push(texture);
             }
 
-            None => {
-                file_textures.push(BspFileTexture {
-                    name: String::new(),
-                    width: 0,
-                    height: 0,
-                    mipmaps: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
-                });
-            }
+            None => file_textures.push(missing_texture()),
         }
     }
     table.check_end_position(&mut reader, BspFileSectionId::Textures)?;
@@ -849,7 +866,7 @@ where
     // max: [32767, 32767, 32767],
     // facelist_id: 0,
     // facelist_count: 0,
-    // sounds: [0u8; NUM_AMBIENTS],
+    // ambient_levels: [0u8; NUM_AMBIENTS],
     // });
 
     for _ in 0..leaf_count {
@@ -873,8 +890,8 @@ where
 
         let facelist_id = reader.read_u16::<LittleEndian>()? as usize;
         let facelist_count = reader.read_u16::<LittleEndian>()? as usize;
-        let mut sounds = [0u8; NUM_AMBIENTS];
-        reader.read(&mut sounds)?;
+        let mut ambient_levels = [0u8; NUM_AMBIENTS];
+        reader.read_exact(&mut ambient_levels)?;
         leaves.push(BspLeaf {
             contents,
             vis_offset,
@@ -882,7 +899,7 @@ where
             max,
             facelist_id,
             facelist_count,
-            sounds,
+            ambient_levels,
         });
     }
     table.check_end_position(&mut reader, BspFileSectionId::Leaves)?;
@@ -1117,3 +1134,80 @@ where
     reader.read_i16_into::<LittleEndian>(&mut ar)?;
     Ok(ar)
 }
+
+/// Returns a BSP file holding one plane that splits the world into a solid leaf below `z = 0` and
+/// an empty one above it, with no faces or textures.
+#[cfg(test)]
+pub fn test_bsp() -> Vec<u8> {
+    use byteorder::WriteBytesExt;
+
+    let mut sections: Vec<Vec<u8>> = vec![Vec::new(); SECTION_COUNT];
+
+    // entities
+    sections[BspFileSectionId::Entities as usize]
+        .extend_from_slice(b"{\n\"classname\" \"worldspawn\"\n}\n\0");
+
+    // planes: z = 0
+    for x in &[0.0, 0.0, 1.0, 0.0] {
+        sections[BspFileSectionId::Planes as usize]
+            .write_f32::<LittleEndian>(*x)
+            .unwrap();
+    }
+    sections[BspFileSectionId::Planes as usize]
+        .write_i32::<LittleEndian>(2)
+        .unwrap();
+
+    // textures: none
+    sections[BspFileSectionId::Textures as usize]
+        .write_i32::<LittleEndian>(0)
+        .unwrap();
+
+    // render nodes: leaf 1 in front of the plane, leaf 0 behind it
+    let node = &mut sections[BspFileSectionId::RenderNodes as usize];
+    node.write_i32::<LittleEndian>(0).unwrap();
+    node.write_i16::<LittleEndian>(!1).unwrap();
+    node.write_i16::<LittleEndian>(!0).unwrap();
+    for x in &[-4096, -4096, -4096, 4096, 4096, 4096] {
+        node.write_i16::<LittleEndian>(*x).unwrap();
+    }
+    node.write_u16::<LittleEndian>(0).unwrap();
+    node.write_u16::<LittleEndian>(0).unwrap();
+
+    // leaves: contents are negated on disk
+    for contents in &[-2, -1] {
+        let leaf = &mut sections[BspFileSectionId::Leaves as usize];
+        leaf.write_i32::<LittleEndian>(*contents).unwrap();
+        leaf.write_i32::<LittleEndian>(-1).unwrap();
+        for x in &[-4096, -4096, -4096, 4096, 4096, 4096] {
+            leaf.write_i16::<LittleEndian>(*x).unwrap();
+        }
+        leaf.write_u16::<LittleEndian>(0).unwrap();
+        leaf.write_u16::<LittleEndian>(0).unwrap();
+        leaf.extend_from_slice(&[0; 4]);
+    }
+
+    // models: just the world
+    let model = &mut sections[BspFileSectionId::Models as usize];
+    for x in &[
+        -4096.0, -4096.0, -4096.0, 4096.0, 4096.0, 4096.0, 0.0, 0.0, 0.0,
+    ] {
+        model.write_f32::<LittleEndian>(*x).unwrap();
+    }
+    for x in &[0, 0, 0, 0, 2, 0, 0] {
+        model.write_i32::<LittleEndian>(*x).unwrap();
+    }
+
+    let mut bsp = Vec::new();
+    bsp.write_i32::<LittleEndian>(VERSION).unwrap();
+    let mut offset = 4 + 8 * sections.len();
+    for section in sections.iter() {
+        bsp.write_i32::<LittleEndian>(offset as i32).unwrap();
+        bsp.write_i32::<LittleEndian>(section.len() as i32).unwrap();
+        offset += section.len();
+    }
+    for section in sections.iter() {
+        bsp.extend_from_slice(section);
+    }
+
+    bsp
+}
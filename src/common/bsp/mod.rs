@@ -128,6 +128,8 @@ use crate::server::world::{Trace, TraceEnd, TraceStart};
 use cgmath::Vector3;
 use chrono::Duration;
 
+#[cfg(test)]
+pub use self::load::test_bsp;
 pub use self::load::{load, BspFileError};
 
 // this is 4 in the original source, but the 4th hull is never used.
@@ -135,7 +137,8 @@ const MAX_HULLS: usize = 3;
 
 pub const MAX_LIGHTMAPS: usize = 64;
 pub const MAX_LIGHTSTYLES: usize = 4;
-pub const MAX_SOUNDS: usize = 4;
+/// The number of ambient sounds a leaf can play: water, sky, slime and lava, in that order.
+pub const NUM_AMBIENTS: usize = 4;
 pub const MIPLEVELS: usize = 4;
 const DIST_EPSILON: f32 = 0.03125;
 
@@ -241,6 +244,11 @@ impl BspTexture {
     pub fn kind(&self) -> &BspTextureKind {
         &self.kind
     }
+
+    /// Returns true if the texture was missing from the BSP file and a placeholder was used.
+    pub fn is_missing(&self) -> bool {
+        self.name.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -657,7 +665,9 @@ pub struct BspLeaf {
     pub max: [i16; 3],
     pub facelist_id: usize,
     pub facelist_count: usize,
-    pub sounds: [u8; MAX_SOUNDS],
+
+    /// The volume of each ambient sound in this leaf, from 0 to 255.
+    pub ambient_levels: [u8; NUM_AMBIENTS],
 }
 
 #[derive(Debug)]
@@ -1014,7 +1024,26 @@ impl BspData {}
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::common::model::ModelKind;
     use cgmath::Zero;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_load_test_bsp() {
+        let (models, entities) = load(Cursor::new(test_bsp())).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].name(), "*0");
+        assert!(entities.contains("worldspawn"));
+
+        let bsp_data = match models[0].kind() {
+            ModelKind::Brush(bmodel) => bmodel.bsp_data(),
+            _ => panic!("world is not a brush model"),
+        };
+        let leaf_id = bsp_data.find_leaf(Vector3::new(0.0, 0.0, 16.0));
+        assert_eq!(bsp_data.leaves[leaf_id].contents, BspLeafContents::Empty);
+        let leaf_id = bsp_data.find_leaf(Vector3::new(0.0, 0.0, -16.0));
+        assert_eq!(bsp_data.leaves[leaf_id].contents, BspLeafContents::Solid);
+    }
 
     #[test]
     fn test_hull_for_bounds() {
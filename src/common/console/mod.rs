@@ -19,14 +19,15 @@
 // SOFTWARE.
 
 use std::{
-    cell::{Ref, RefCell},
+    cell::{Cell, Ref, RefCell},
     collections::{HashMap, VecDeque},
-    fmt::Write,
+    fmt::{self, Write},
+    io::{self, Read},
     iter::FromIterator,
     rc::Rc,
 };
 
-use crate::common::parse;
+use crate::common::{parse, vfs::Vfs};
 
 use chrono::{Duration, Utc};
 use thiserror::Error;
@@ -37,6 +38,12 @@ pub enum ConsoleError {
     CmdError(String),
     #[error("Could not parse cvar as a number: {name} = \"{value}\"")]
     CvarParseFailed { name: String, value: String },
+    #[error("Invalid value for {name}: \"{value}\" ({reason})")]
+    InvalidCvarValue {
+        name: String,
+        value: String,
+        reason: String,
+    },
     #[error("A command named \"{0}\" already exists")]
     DuplicateCommand(String),
     #[error("A cvar named \"{0}\" already exists")]
@@ -49,6 +56,17 @@ pub enum ConsoleError {
 
 type Cmd = Box<dyn Fn(&[&str]) -> String>;
 
+/// The maximum number of commands run by a single call to `Console::execute`.
+///
+/// This keeps an alias that runs itself from hanging the engine.
+const MAX_COMMANDS_PER_EXECUTE: usize = 8192;
+
+/// Suggests values for a command argument.
+///
+/// The completer is passed the arguments before the one being completed and returns every value
+/// that argument could take; the console narrows them down to those matching what's been typed.
+pub type ArgCompleter = Box<dyn Fn(&[&str]) -> Vec<String>>;
+
 fn insert_name<S>(names: &mut Vec<String>, name: S) -> Result<usize, usize>
 where
     S: AsRef<str>,
@@ -166,6 +184,187 @@ impl CmdRegistry {
     }
 }
 
+/// Called with the name and new value of a cvar whenever its value changes.
+pub type CvarListener = Box<dyn Fn(&str, &str)>;
+
+/// Identifies a listener added to a `CvarRegistry` so it can later be removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CvarListenerId(usize);
+
+struct CvarListenerEntry {
+    id: CvarListenerId,
+
+    // the cvar this listener watches, or None to watch every notify cvar
+    cvar: Option<String>,
+
+    listener: CvarListener,
+}
+
+/// The type of value a cvar holds and the values it may take.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CvarKind {
+    /// Any text.
+    String,
+
+    /// Either `0` or `1`.
+    Bool,
+
+    /// An integer, optionally limited to an inclusive range.
+    Int { min: Option<i32>, max: Option<i32> },
+
+    /// A number, optionally limited to an inclusive range.
+    Float { min: Option<f32>, max: Option<f32> },
+
+    /// One of a fixed set of values.
+    Enum(Vec<String>),
+}
+
+impl CvarKind {
+    /// Checks that `value` is valid for this kind of cvar.
+    ///
+    /// Returns the reason if it isn't.
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        match *self {
+            CvarKind::String => Ok(()),
+
+            CvarKind::Bool => match value {
+                "0" | "1" => Ok(()),
+                _ => Err("expected 0 or 1".to_owned()),
+            },
+
+            CvarKind::Int { min, max } => {
+                let v = value
+                    .parse::<i32>()
+                    .map_err(|_| "expected an integer".to_owned())?;
+                check_range(v, min, max)
+            }
+
+            CvarKind::Float { min, max } => {
+                let v = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| "expected a number".to_owned())?;
+                check_range(v, min, max)
+            }
+
+            CvarKind::Enum(ref values) => {
+                if values.iter().any(|v| v == value) {
+                    Ok(())
+                } else {
+                    Err(format!("expected one of {}", values.join(", ")))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for CvarKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CvarKind::String => write!(f, "string"),
+            CvarKind::Bool => write!(f, "bool"),
+            CvarKind::Int { min, max } => {
+                write!(f, "int")?;
+                fmt_range(f, min, max)
+            }
+            CvarKind::Float { min, max } => {
+                write!(f, "float")?;
+                fmt_range(f, min, max)
+            }
+            CvarKind::Enum(ref values) => write!(f, "one of {}", values.join(", ")),
+        }
+    }
+}
+
+fn check_range<T>(v: T, min: Option<T>, max: Option<T>) -> Result<(), String>
+where
+    T: PartialOrd + fmt::Display,
+{
+    let below = min.as_ref().map_or(false, |min| v < *min);
+    let above = max.as_ref().map_or(false, |max| v > *max);
+    if below || above {
+        let mut reason = "expected".to_owned();
+        match (min, max) {
+            (Some(min), Some(max)) => write!(reason, " {} to {}", min, max),
+            (Some(min), None) => write!(reason, " at least {}", min),
+            (None, Some(max)) => write!(reason, " at most {}", max),
+            (None, None) => unreachable!(),
+        }
+        .unwrap();
+        return Err(reason);
+    }
+
+    Ok(())
+}
+
+fn fmt_range<T>(f: &mut fmt::Formatter, min: Option<T>, max: Option<T>) -> fmt::Result
+where
+    T: fmt::Display,
+{
+    match (min, max) {
+        (Some(min), Some(max)) => write!(f, ", {} to {}", min, max),
+        (Some(min), None) => write!(f, ", at least {}", min),
+        (None, Some(max)) => write!(f, ", at most {}", max),
+        (None, None) => Ok(()),
+    }
+}
+
+/// The definition of a typed cvar.
+///
+/// ```ignore
+/// cvars.define(
+///     CvarDef::new("r_msaa_samples", "4", CvarKind::Enum(vec!["2".into(), "4".into()]))
+///         .help("Number of samples per pixel for anti-aliasing"),
+/// )?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct CvarDef {
+    name: String,
+    default: String,
+    kind: CvarKind,
+    archive: bool,
+    notify: bool,
+    help: Option<String>,
+}
+
+impl CvarDef {
+    pub fn new<S>(name: S, default: S, kind: CvarKind) -> CvarDef
+    where
+        S: AsRef<str>,
+    {
+        CvarDef {
+            name: name.as_ref().to_owned(),
+            default: default.as_ref().to_owned(),
+            kind,
+            archive: false,
+            notify: false,
+            help: None,
+        }
+    }
+
+    /// Marks the cvar to be saved to `config.cfg`.
+    pub fn archive(mut self) -> CvarDef {
+        self.archive = true;
+        self
+    }
+
+    /// Marks the cvar as a notify cvar.
+    pub fn notify(mut self) -> CvarDef {
+        self.notify = true;
+        self
+    }
+
+    /// Sets the text displayed by the `help` command.
+    pub fn help<S>(mut self, help: S) -> CvarDef
+    where
+        S: AsRef<str>,
+    {
+        self.help = Some(help.as_ref().to_owned());
+        self
+    }
+}
+
 /// A configuration variable.
 ///
 /// Cvars are the primary method of configuring the game.
@@ -174,6 +373,12 @@ struct Cvar {
     // Value of this variable
     val: String,
 
+    // The values this variable may take
+    kind: CvarKind,
+
+    // Description shown by the help command
+    help: Option<String>,
+
     // If true, this variable should be archived in vars.rc
     archive: bool,
 
@@ -186,10 +391,11 @@ struct Cvar {
     default: String,
 }
 
-#[derive(Debug)]
 pub struct CvarRegistry {
     cvars: RefCell<HashMap<String, Cvar>>,
     names: Rc<RefCell<Vec<String>>>,
+    listeners: RefCell<Vec<CvarListenerEntry>>,
+    next_listener_id: Cell<usize>,
 }
 
 impl CvarRegistry {
@@ -198,6 +404,8 @@ impl CvarRegistry {
         CvarRegistry {
             cvars: RefCell::new(HashMap::new()),
             names,
+            listeners: RefCell::new(Vec::new()),
+            next_listener_id: Cell::new(0),
         }
     }
 
@@ -211,11 +419,40 @@ impl CvarRegistry {
     where
         S: AsRef<str>,
     {
-        let name = name.as_ref();
-        let default = default.as_ref();
+        let mut def = CvarDef::new(name, default, CvarKind::String);
+        def.archive = archive;
+        def.notify = notify;
+        self.define(def)
+    }
 
+    /// Registers a typed cvar.
+    ///
+    /// Returns an error if the default value isn't valid for the cvar's type, or if the cvar
+    /// already exists. Registering an existing cvar as notify instead makes it a notify cvar.
+    pub fn define(&self, def: CvarDef) -> Result<(), ConsoleError> {
+        let CvarDef {
+            name,
+            default,
+            kind,
+            archive,
+            notify,
+            help,
+        } = def;
+
+        if let Err(reason) = kind.validate(&default) {
+            return Err(ConsoleError::InvalidCvarValue {
+                name,
+                value: default,
+                reason,
+            });
+        }
+
+        let name = name.as_str();
         let mut cvars = self.cvars.borrow_mut();
-        match cvars.get(name) {
+        match cvars.get_mut(name) {
+            // a cvar shared by the client and server is registered by both, but the server
+            // needs to hear about changes to it
+            Some(cvar) if notify => cvar.notify = true,
             Some(_) => Err(ConsoleError::DuplicateCvar(name.into()))?,
             None => {
                 if insert_name(&mut self.names.borrow_mut(), name).is_err() {
@@ -225,10 +462,12 @@ impl CvarRegistry {
                 cvars.insert(
                     name.to_owned(),
                     Cvar {
-                        val: default.to_owned(),
+                        val: default.clone(),
+                        kind,
+                        help,
                         archive,
                         notify,
-                        default: default.to_owned(),
+                        default,
                     },
                 );
             }
@@ -261,6 +500,8 @@ impl CvarRegistry {
     /// When this `Cvar` is set:
     /// - If the host is a server, broadcast that the variable has been changed to all clients.
     /// - If the host is a client, update the clientinfo string.
+    ///
+    /// If a `Cvar` with this name already exists, it keeps its value and becomes a notify `Cvar`.
     pub fn register_notify<S>(&self, name: S, default: S) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
@@ -276,7 +517,7 @@ impl CvarRegistry {
     /// Additionally, when this `Cvar` is set:
     /// - If the host is a server, broadcast that the variable has been changed to all clients.
     /// - If the host is a client, update the clientinfo string.
-    pub fn register_archive_notify<S>(&mut self, name: S, default: S) -> Result<(), ConsoleError>
+    pub fn register_archive_notify<S>(&self, name: S, default: S) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
@@ -324,30 +565,193 @@ impl CvarRegistry {
         Ok(val)
     }
 
+    /// Sets the value of a cvar.
+    ///
+    /// Returns an error if the value isn't valid for the cvar's type. If the value changed, this
+    /// calls the listeners watching the cvar, and if it is a notify cvar, the listeners added with
+    /// `add_notify_listener`.
     pub fn set<S>(&self, name: S, value: S) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
-        trace!("cvar assignment: {} {}", name.as_ref(), value.as_ref());
-        let mut cvars = self.cvars.borrow_mut();
-        let mut cvar = cvars
-            .get_mut(name.as_ref())
-            .ok_or(ConsoleError::NoSuchCvar(name.as_ref().to_owned()))?;
-        cvar.val = value.as_ref().to_owned();
-        if cvar.notify {
-            // TODO: update userinfo/serverinfo
-            unimplemented!();
+        let name = name.as_ref();
+        let value = value.as_ref();
+        trace!("cvar assignment: {} {}", name, value);
+
+        let notify = {
+            let mut cvars = self.cvars.borrow_mut();
+            let cvar = cvars
+                .get_mut(name)
+                .ok_or(ConsoleError::NoSuchCvar(name.to_owned()))?;
+
+            if let Err(reason) = cvar.kind.validate(value) {
+                return Err(ConsoleError::InvalidCvarValue {
+                    name: name.to_owned(),
+                    value: value.to_owned(),
+                    reason,
+                });
+            }
+
+            if cvar.val == value {
+                return Ok(());
+            }
+
+            cvar.val = value.to_owned();
+            cvar.notify
+        };
+
+        // the cvar table is no longer borrowed, so listeners are free to read or set cvars
+        for entry in self.listeners.borrow().iter() {
+            let watching = match entry.cvar {
+                Some(ref cvar) => cvar == name,
+                None => notify,
+            };
+
+            if watching {
+                (entry.listener)(name, value);
+            }
         }
 
         Ok(())
     }
 
+    fn add_listener_impl(&self, cvar: Option<String>, listener: CvarListener) -> CvarListenerId {
+        let id = CvarListenerId(self.next_listener_id.get());
+        self.next_listener_id.set(id.0 + 1);
+        self.listeners
+            .borrow_mut()
+            .push(CvarListenerEntry { id, cvar, listener });
+        id
+    }
+
+    /// Adds a listener which is called whenever the named cvar changes.
+    ///
+    /// Listeners may not add or remove other listeners.
+    pub fn add_listener<S>(
+        &self,
+        name: S,
+        listener: CvarListener,
+    ) -> Result<CvarListenerId, ConsoleError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        if !self.contains(name) {
+            return Err(ConsoleError::NoSuchCvar(name.to_owned()));
+        }
+
+        Ok(self.add_listener_impl(Some(name.to_owned()), listener))
+    }
+
+    /// Adds a listener which is called whenever any notify cvar changes.
+    ///
+    /// Listeners may not add or remove other listeners.
+    pub fn add_notify_listener(&self, listener: CvarListener) -> CvarListenerId {
+        self.add_listener_impl(None, listener)
+    }
+
+    /// Removes a listener.
+    ///
+    /// Returns `false` if the listener was already removed.
+    pub fn remove_listener(&self, id: CvarListenerId) -> bool {
+        let mut listeners = self.listeners.borrow_mut();
+        match listeners.iter().position(|entry| entry.id == id) {
+            Some(i) => {
+                listeners.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the serverinfo string.
+    ///
+    /// This is a `\name\value` pair for each notify cvar, in order of name.
+    pub fn serverinfo(&self) -> String {
+        let cvars = self.cvars.borrow();
+        let mut names: Vec<&String> = cvars
+            .iter()
+            .filter(|(_, cvar)| cvar.notify)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        let mut info = String::new();
+        for name in names {
+            write!(info, "\\{}\\{}", name, cvars[name].val).unwrap();
+        }
+
+        info
+    }
+
     pub fn contains<S>(&self, name: S) -> bool
     where
         S: AsRef<str>,
     {
         self.cvars.borrow().contains_key(name.as_ref())
     }
+
+    /// Describes a cvar: its value, type, flags and help text.
+    pub fn describe<S>(&self, name: S) -> Result<String, ConsoleError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let cvars = self.cvars.borrow();
+        let cvar = cvars
+            .get(name)
+            .ok_or(ConsoleError::NoSuchCvar(name.to_owned()))?;
+
+        let mut desc = format!(
+            "\"{}\" is \"{}\" (default \"{}\")\ntype: {}",
+            name, cvar.val, cvar.default, cvar.kind
+        );
+
+        let flags: Vec<&str> = [(cvar.archive, "archive"), (cvar.notify, "notify")]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect();
+        if !flags.is_empty() {
+            write!(desc, "\nflags: {}", flags.join(", ")).unwrap();
+        }
+
+        if let Some(ref help) = cvar.help {
+            write!(desc, "\n{}", help).unwrap();
+        }
+
+        Ok(desc)
+    }
+
+    /// Writes the archived cvars to `writer` as commands that restore their current values.
+    pub fn write_archived<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let cvars = self.cvars.borrow();
+        let mut names: Vec<&String> = cvars
+            .iter()
+            .filter(|(_, cvar)| cvar.archive)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            writeln!(writer, "{} \"{}\"", name, cvars[name].val)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CvarRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CvarRegistry")
+            .field("cvars", &self.cvars)
+            .field("names", &self.names)
+            .field("listeners", &self.listeners.borrow().len())
+            .finish()
+    }
 }
 
 /// The line of text currently being edited in the console.
@@ -534,14 +938,69 @@ impl ConsoleOutput {
     }
 }
 
+// A tab completion with more than one match. Pressing tab again cycles through the matches.
+struct Completion {
+    // the input line before the word being completed
+    base: String,
+    matches: Vec<String>,
+
+    // the match currently in the input line
+    current: Option<usize>,
+}
+
+/// Returns the candidates that begin with `word`, ignoring case.
+fn complete_word(candidates: Vec<String>, word: &str) -> Vec<String> {
+    let word = word.to_lowercase();
+    let mut matches: Vec<String> = candidates
+        .into_iter()
+        .filter(|c| c.to_lowercase().starts_with(&word))
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// Returns the longest prefix shared by all of `matches`, ignoring case.
+fn common_prefix(matches: &[String]) -> String {
+    let first = match matches.first() {
+        Some(f) => f,
+        None => return String::new(),
+    };
+
+    let mut len = first.chars().count();
+    for m in &matches[1..] {
+        len = first
+            .chars()
+            .zip(m.chars())
+            .take(len)
+            .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+            .count();
+    }
+
+    first.chars().take(len).collect()
+}
+
+/// Splits `line` into everything before its last word and the last word itself.
+fn split_last_word(line: &str) -> (&str, &str) {
+    match line.char_indices().rev().find(|(_, c)| c.is_whitespace()) {
+        // the separator may be more than one byte long
+        Some((i, c)) => line.split_at(i + c.len_utf8()),
+        None => ("", line),
+    }
+}
+
 pub struct Console {
     cmds: Rc<RefCell<CmdRegistry>>,
     cvars: Rc<RefCell<CvarRegistry>>,
     aliases: Rc<RefCell<HashMap<String, String>>>,
+    wait: Rc<Cell<bool>>,
+    completers: RefCell<HashMap<String, ArgCompleter>>,
 
     input: ConsoleInput,
     hist: History,
-    buffer: RefCell<String>,
+    completion: Option<Completion>,
+    // parsed commands waiting to be executed
+    buffer: RefCell<VecDeque<Vec<String>>>,
 
     out_buffer: RefCell<Vec<char>>,
     output: RefCell<ConsoleOutput>,
@@ -551,17 +1010,7 @@ impl Console {
     pub fn new(cmds: Rc<RefCell<CmdRegistry>>, cvars: Rc<RefCell<CvarRegistry>>) -> Console {
         let output = RefCell::new(ConsoleOutput::new());
         cmds.borrow_mut()
-            .insert(
-                "echo",
-                Box::new(move |args| {
-                    let msg = match args.len() {
-                        0 => "",
-                        _ => args[0],
-                    };
-
-                    msg.to_owned()
-                }),
-            )
+            .insert("echo", Box::new(|args| args.join(" ")))
             .unwrap();
 
         let aliases: Rc<RefCell<HashMap<String, String>>> = Rc::new(RefCell::new(HashMap::new()));
@@ -569,28 +1018,129 @@ impl Console {
         cmds.borrow_mut()
             .insert(
                 "alias",
-                Box::new(move |args| {
-                    match args.len() {
-                        0 => {
-                            for (name, script) in cmd_aliases.borrow().iter() {
-                                println!("    {}: {}", name, script);
-                            }
-                            println!("{} alias command(s)", cmd_aliases.borrow().len());
-                        }
+                Box::new(move |args| match args.len() {
+                    // alias: list all aliases
+                    0 => {
+                        let aliases = cmd_aliases.borrow();
+                        let mut names: Vec<&String> = aliases.keys().collect();
+                        names.sort();
 
-                        2 => {
-                            let name = args[0].to_string();
-                            let script = args[1].to_string();
-                            let _ = cmd_aliases.borrow_mut().insert(name, script);
+                        let mut output = String::new();
+                        for name in names {
+                            writeln!(&mut output, "{} : {}", name, aliases[name]).unwrap();
                         }
+                        write!(&mut output, "{} alias command(s)", aliases.len()).unwrap();
+                        output
+                    }
 
-                        _ => (),
+                    // alias (name): show what the alias runs
+                    1 => match cmd_aliases.borrow().get(args[0]) {
+                        Some(script) => format!("\"{}\" is \"{}\"", args[0], script),
+                        None => format!("\"{}\" is not an alias", args[0]),
+                    },
+
+                    // alias (name) (commands): like the original, the rest of the line is the
+                    // alias text
+                    _ => {
+                        let script = args[1..].join(" ");
+                        cmd_aliases.borrow_mut().insert(args[0].to_owned(), script);
+                        String::new()
                     }
+                }),
+            )
+            .unwrap();
+
+        let wait = Rc::new(Cell::new(false));
+        let cmd_wait = wait.clone();
+        cmds.borrow_mut()
+            .insert(
+                "wait",
+                Box::new(move |_| {
+                    cmd_wait.set(true);
                     String::new()
                 }),
             )
             .unwrap();
 
+        // the name table is shared by commands and cvars, so anything that isn't a cvar is a
+        // command. the command registry itself is borrowed while this runs
+        let cmdlist_names = cmds.borrow().names();
+        let cmdlist_cvars = cvars.clone();
+        cmds.borrow_mut()
+            .insert(
+                "cmdlist",
+                Box::new(move |args| {
+                    let prefix = args.get(0).copied().unwrap_or("");
+                    let cvars = cmdlist_cvars.borrow();
+                    let names = cmdlist_names.borrow();
+
+                    let mut output = String::new();
+                    let mut count = 0;
+                    for name in names
+                        .iter()
+                        .filter(|n| n.starts_with(prefix) && !cvars.contains(n.as_str()))
+                    {
+                        writeln!(&mut output, "  {}", name).unwrap();
+                        count += 1;
+                    }
+                    write!(&mut output, "{} command(s)", count).unwrap();
+                    output
+                }),
+            )
+            .unwrap();
+
+        let cvarlist_cvars = cvars.clone();
+        cmds.borrow_mut()
+            .insert(
+                "cvarlist",
+                Box::new(move |args| {
+                    let prefix = args.get(0).copied().unwrap_or("");
+                    let registry = cvarlist_cvars.borrow();
+                    let cvars = registry.cvars.borrow();
+                    let mut names: Vec<&String> =
+                        cvars.keys().filter(|n| n.starts_with(prefix)).collect();
+                    names.sort();
+
+                    // flag archived cvars with '*' and notify cvars with 's', and follow typed
+                    // cvars with their type so valid values can be looked up at a glance
+                    let mut output = String::new();
+                    for name in names.iter() {
+                        let cvar = &cvars[*name];
+                        write!(
+                            &mut output,
+                            "{}{} {} \"{}\"",
+                            if cvar.archive { '*' } else { ' ' },
+                            if cvar.notify { 's' } else { ' ' },
+                            name,
+                            cvar.val
+                        )
+                        .unwrap();
+                        match cvar.kind {
+                            CvarKind::String => writeln!(&mut output),
+                            ref kind => writeln!(&mut output, " ({})", kind),
+                        }
+                        .unwrap();
+                    }
+                    write!(&mut output, "{} cvar(s)", names.len()).unwrap();
+                    output
+                }),
+            )
+            .unwrap();
+
+        let help_cvars = cvars.clone();
+        cmds.borrow_mut()
+            .insert(
+                "help",
+                Box::new(move |args| match args.len() {
+                    1 => match help_cvars.borrow().describe(args[0]) {
+                        Ok(desc) => desc,
+                        Err(e) => format!("{}", e),
+                    },
+                    _ => "usage: help <cvar>".to_owned(),
+                }),
+            )
+            .unwrap();
+
         let find_names = cmds.borrow().names();
         cmds.borrow_mut()
             .insert(
@@ -625,13 +1175,23 @@ impl Console {
             )
             .unwrap();
 
+        let mut completers: HashMap<String, ArgCompleter> = HashMap::new();
+        let help_cvars = cvars.clone();
+        completers.insert(
+            "help".to_owned(),
+            Box::new(move |_| help_cvars.borrow().cvars.borrow().keys().cloned().collect()),
+        );
+
         Console {
             cmds,
             cvars,
             aliases: aliases.clone(),
+            wait,
+            completers: RefCell::new(completers),
             input: ConsoleInput::new(),
             hist: History::new(),
-            buffer: RefCell::new(String::new()),
+            completion: None,
+            buffer: RefCell::new(VecDeque::new()),
             out_buffer: RefCell::new(Vec::new()),
             output,
         }
@@ -690,15 +1250,17 @@ impl Console {
     }
 
     pub fn send_char(&mut self, c: char) {
+        if c != '\t' {
+            self.completion = None;
+        }
+
         match c {
             // ignore grave and escape keys
             '`' | '\x1b' => (),
 
             '\r' => {
                 // cap with a newline and push to the execution buffer
-                let mut entered = self.get_string();
-                entered.push('\n');
-                self.buffer.borrow_mut().push_str(&entered);
+                self.stuff_text(self.get_string());
 
                 // add the current input to the history
                 self.hist.add_line(self.input.get_text());
@@ -715,7 +1277,7 @@ impl Console {
             '\x08' => self.input.backspace(),
             '\x7f' => self.input.delete(),
 
-            '\t' => warn!("Tab completion not implemented"), // TODO: tab completion
+            '\t' => self.complete(),
 
             // TODO: we should probably restrict what characters are allowed
             c => self.input.insert(c),
@@ -727,77 +1289,186 @@ impl Console {
     }
 
     pub fn cursor_right(&mut self) {
+        self.completion = None;
         self.input.cursor_right()
     }
 
     pub fn cursor_left(&mut self) {
+        self.completion = None;
         self.input.cursor_left()
     }
 
     pub fn history_up(&mut self) {
+        self.completion = None;
         if let Some(line) = self.hist.line_up() {
             self.input.set_text(&line);
         }
     }
 
     pub fn history_down(&mut self) {
+        self.completion = None;
         if let Some(line) = self.hist.line_down() {
             self.input.set_text(&line);
         }
     }
 
-    /// Interprets the contents of the execution buffer.
+    /// Registers a completer for the arguments of the command `cmd`.
+    ///
+    /// This replaces any completer previously registered for `cmd`.
+    pub fn register_completer<S>(&self, cmd: S, completer: ArgCompleter)
+    where
+        S: AsRef<str>,
+    {
+        self.completers
+            .borrow_mut()
+            .insert(cmd.as_ref().to_owned(), completer);
+    }
+
+    /// Returns the possible completions of the last word in `line`.
+    ///
+    /// The first word is completed from the names of commands, cvars and aliases. Later words
+    /// are completed by the completer registered for the command, if there is one.
+    pub fn completions(&self, line: &str) -> Vec<String> {
+        let (base, word) = split_last_word(line);
+        let words: Vec<&str> = base.split_whitespace().collect();
+
+        let candidates = match words.split_first() {
+            None => {
+                let mut names = self.cmds.borrow().names().borrow().clone();
+                names.extend(self.aliases.borrow().keys().cloned());
+                names
+            }
+
+            Some((cmd, args)) => match self.completers.borrow().get(*cmd) {
+                Some(completer) => completer(args),
+                None => Vec::new(),
+            },
+        };
+
+        complete_word(candidates, word)
+    }
+
+    // Completes the last word of the input line.
+    //
+    // A single match is filled in. With several matches, they're listed and the input is extended
+    // to their common prefix; pressing tab again cycles through them.
+    fn complete(&mut self) {
+        if let Some(ref mut completion) = self.completion {
+            let next = match completion.current {
+                Some(i) => (i + 1) % completion.matches.len(),
+                None => 0,
+            };
+            completion.current = Some(next);
+
+            let text = format!("{}{}", completion.base, completion.matches[next]);
+            self.input.set_text(&text.chars().collect());
+            return;
+        }
+
+        let line = self.get_string();
+        let matches = self.completions(&line);
+        let base = split_last_word(&line).0.to_owned();
+
+        match matches.len() {
+            0 => (),
+
+            1 => {
+                let text = format!("{}{} ", base, matches[0]);
+                self.input.set_text(&text.chars().collect());
+            }
+
+            _ => {
+                self.println(format!("]{}", line));
+                for m in matches.iter() {
+                    self.println(format!("  {}", m));
+                }
+
+                let text = format!("{}{}", base, common_prefix(&matches));
+                self.input.set_text(&text.chars().collect());
+                self.completion = Some(Completion {
+                    base,
+                    matches,
+                    current: None,
+                });
+            }
+        }
+    }
+
+    /// Runs the commands in the command buffer.
+    ///
+    /// A `wait` command stops execution until the next call, which is normally made once per
+    /// frame; the rest of the buffer is kept until then.
     pub fn execute(&self) {
-        let text = self.buffer.replace(String::new());
+        let mut count = 0;
+
+        loop {
+            let args = match self.buffer.borrow_mut().pop_front() {
+                Some(a) => a,
+                None => break,
+            };
+
+            count += 1;
+            if count > MAX_COMMANDS_PER_EXECUTE {
+                // most likely an alias that runs itself
+                self.buffer.borrow_mut().clear();
+                self.println("Command buffer overflow, clearing buffer");
+                break;
+            }
 
-        let (_remaining, commands) = parse::commands(text.as_str()).unwrap();
+            debug!("{:?}", args);
+            self.execute_command(&args);
 
-        for command in commands.iter() {
-            debug!("{:?}", command);
+            if self.wait.replace(false) {
+                break;
+            }
         }
+    }
 
-        for args in commands {
-            if let Some(arg_0) = args.get(0) {
-                let maybe_alias = self.aliases.borrow().get(*arg_0).map(|a| a.to_owned());
-                match maybe_alias {
-                    Some(a) => {
-                        self.stuff_text(a);
-                        self.execute();
+    fn execute_command(&self, args: &[String]) {
+        let arg_0 = match args.get(0) {
+            Some(a) => a.as_str(),
+            None => return,
+        };
+        let tail_args: Vec<&str> = args.iter().map(|s| s.as_str()).skip(1).collect();
+
+        // commands take precedence over aliases, which take precedence over cvars
+        if self.cmds.borrow().contains(arg_0) {
+            match self.cmds.borrow_mut().exec(arg_0, &tail_args) {
+                Ok(o) => {
+                    if !o.is_empty() {
+                        self.println(o)
                     }
+                }
+                Err(e) => self.println(format!("{}", e)),
+            }
 
-                    None => {
-                        let tail_args: Vec<&str> =
-                            args.iter().map(|s| s.as_ref()).skip(1).collect();
-
-                        if self.cmds.borrow().contains(arg_0) {
-                            match self.cmds.borrow_mut().exec(arg_0, &tail_args) {
-                                Ok(o) => {
-                                    if !o.is_empty() {
-                                        self.println(o)
-                                    }
-                                }
-                                Err(e) => self.println(format!("{}", e)),
-                            }
-                        } else if self.cvars.borrow().contains(arg_0) {
-                            // TODO error handling on cvar set
-                            match args.get(1) {
-                                Some(arg_1) => self.cvars.borrow_mut().set(arg_0, arg_1).unwrap(),
-                                None => {
-                                    let msg = format!(
-                                        "\"{}\" is \"{}\"",
-                                        arg_0,
-                                        self.cvars.borrow().get(arg_0).unwrap()
-                                    );
-                                    self.println(msg);
-                                }
-                            }
-                        } else {
-                            // TODO: try sending to server first
-                            self.println(format!("Unrecognized command \"{}\"", arg_0));
-                        }
+            return;
+        }
+
+        let maybe_alias = self.aliases.borrow().get(arg_0).cloned();
+        if let Some(alias) = maybe_alias {
+            // the alias runs before anything else in the buffer
+            self.insert_text(alias);
+        } else if self.cvars.borrow().contains(arg_0) {
+            match tail_args.get(0) {
+                Some(arg_1) => {
+                    let result = self.cvars.borrow().set(arg_0, arg_1);
+                    if let Err(e) = result {
+                        self.println(format!("{}", e));
                     }
                 }
+                None => {
+                    let msg = format!(
+                        "\"{}\" is \"{}\"",
+                        arg_0,
+                        self.cvars.borrow().get(arg_0).unwrap()
+                    );
+                    self.println(msg);
+                }
             }
+        } else {
+            // TODO: try sending to server first
+            self.println(format!("Unrecognized command \"{}\"", arg_0));
         }
     }
 
@@ -805,18 +1476,509 @@ impl Console {
         String::from_iter(self.input.text.clone().into_iter())
     }
 
+    /// Adds commands to the end of the command buffer.
     pub fn stuff_text<S>(&self, text: S)
     where
         S: AsRef<str>,
     {
         debug!("stuff_text:\n{:?}", text.as_ref());
-        self.buffer.borrow_mut().push_str(text.as_ref());
+        let commands = self.parse_text(text.as_ref());
+        self.buffer.borrow_mut().extend(commands);
+    }
 
+    /// Adds commands to the front of the command buffer, so they run before anything already in
+    /// it.
+    pub fn insert_text<S>(&self, text: S)
+    where
+        S: AsRef<str>,
+    {
+        let commands = self.parse_text(text.as_ref());
+        let mut buffer = self.buffer.borrow_mut();
+        for command in commands.into_iter().rev() {
+            buffer.push_front(command);
+        }
+    }
+
+    fn parse_text(&self, text: &str) -> Vec<Vec<String>> {
         // in case the last line doesn't end with a newline
-        self.buffer.borrow_mut().push_str("\n");
+        let text = format!("{}\n", text);
+
+        match parse::commands(&text) {
+            Ok((remaining, commands)) => {
+                if !remaining.is_empty() {
+                    self.println(format!("Couldn't parse command: {}", remaining.trim_end()));
+                }
+
+                commands
+                    .into_iter()
+                    .map(|args| args.into_iter().map(str::to_owned).collect())
+                    .collect()
+            }
+
+            Err(e) => {
+                self.println(format!("Couldn't parse command: {}", e));
+                Vec::new()
+            }
+        }
     }
 
     pub fn output(&self) -> Ref<ConsoleOutput> {
         self.output.borrow()
     }
 }
+
+/// Implements the `exec` command, which runs a script file from `vfs`.
+///
+/// The script is inserted at the front of the command buffer, so it finishes before any commands
+/// that followed the `exec`.
+pub fn cmd_exec(vfs: Rc<Vfs>, console: Rc<RefCell<Console>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        match args.len() {
+            // exec (filename): execute a script file
+            1 => {
+                let mut script_file = match vfs.open(args[0]) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        return format!("Couldn't exec {}: {:?}", args[0], e);
+                    }
+                };
+
+                let mut script = String::new();
+                if let Err(e) = script_file.read_to_string(&mut script) {
+                    return format!("Couldn't exec {}: {}", args[0], e);
+                }
+
+                console.borrow().insert_text(script);
+                String::new()
+            }
+
+            _ => format!("exec (filename): execute a script file"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::common::util::TempDir;
+
+    fn test_console() -> Console {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        cvars.borrow().register("fov", "90").unwrap();
+        cvars.borrow().register("fov_adapt", "1").unwrap();
+        for name in &["playdemo", "play", "timedemo"] {
+            cmds.borrow_mut()
+                .insert(name, Box::new(|_| String::new()))
+                .unwrap();
+        }
+
+        Console::new(cmds, cvars)
+    }
+
+    fn type_text(console: &mut Console, text: &str) {
+        for c in text.chars() {
+            console.send_char(c);
+        }
+    }
+
+    #[test]
+    fn test_console_complete_single() {
+        let mut console = test_console();
+        type_text(&mut console, "time\t");
+        assert_eq!(console.get_string(), "timedemo ");
+    }
+
+    #[test]
+    fn test_console_complete_cycle() {
+        let mut console = test_console();
+
+        // the common prefix is filled in and the candidates are listed
+        type_text(&mut console, "pl\t");
+        assert_eq!(console.get_string(), "play");
+        let lines: Vec<String> = console
+            .output()
+            .lines()
+            .map(|l| l.iter().collect())
+            .collect();
+        assert_eq!(lines, vec!["  playdemo", "  play", "]pl"]);
+
+        type_text(&mut console, "\t");
+        assert_eq!(console.get_string(), "play");
+        type_text(&mut console, "\t");
+        assert_eq!(console.get_string(), "playdemo");
+        type_text(&mut console, "\t");
+        assert_eq!(console.get_string(), "play");
+
+        // typing ends the cycle
+        type_text(&mut console, "d\t");
+        assert_eq!(console.get_string(), "playdemo ");
+    }
+
+    #[test]
+    fn test_console_complete_arguments() {
+        let console = test_console();
+        console.register_completer(
+            "playdemo",
+            Box::new(|args| match args.len() {
+                0 => vec!["demo1".to_owned(), "demo2".to_owned(), "e1m1".to_owned()],
+                _ => Vec::new(),
+            }),
+        );
+
+        assert_eq!(console.completions("playdemo d"), vec!["demo1", "demo2"]);
+        assert_eq!(console.completions("playdemo E"), vec!["e1m1"]);
+        assert!(console.completions("playdemo demo1 d").is_empty());
+        assert!(console.completions("timedemo d").is_empty());
+        assert_eq!(console.completions("FOV"), vec!["fov", "fov_adapt"]);
+    }
+
+    #[test]
+    fn test_split_last_word_multibyte() {
+        assert_eq!(split_last_word("playdemo d"), ("playdemo ", "d"));
+        assert_eq!(split_last_word("playdemo"), ("", "playdemo"));
+
+        // a no-break space is two bytes long
+        assert_eq!(split_last_word("playdemo\u{a0}d"), ("playdemo\u{a0}", "d"));
+        assert_eq!(split_last_word("fov\u{3000}"), ("fov\u{3000}", ""));
+    }
+
+    fn output_lines(console: &Console) -> Vec<String> {
+        let mut lines: Vec<String> = console
+            .output()
+            .lines()
+            .map(|l| l.iter().collect())
+            .collect();
+        lines.reverse();
+        lines
+    }
+
+    #[test]
+    fn test_console_wait() {
+        let console = test_console();
+        console.stuff_text("echo one; wait; echo two\necho three");
+
+        console.execute();
+        assert_eq!(output_lines(&console), vec!["one"]);
+        console.execute();
+        assert_eq!(output_lines(&console), vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_console_exec_order() {
+        let dir = TempDir::new("exec");
+        dir.write("foo.cfg", "echo foo; exec bar.cfg\necho baz");
+        dir.write("bar.cfg", "echo bar");
+
+        let console = Rc::new(RefCell::new(test_console()));
+        let cmds = console.borrow().cmds.clone();
+        cmds.borrow_mut()
+            .insert("exec", cmd_exec(Rc::new(dir.vfs()), console.clone()))
+            .unwrap();
+
+        // scripts run in full, nested ones included, before the rest of the buffer
+        console.borrow().stuff_text("exec foo.cfg; echo after");
+        console.borrow().execute();
+        assert_eq!(
+            output_lines(&console.borrow()),
+            vec!["foo", "bar", "baz", "after"]
+        );
+    }
+
+    #[test]
+    fn test_console_alias() {
+        let console = test_console();
+        console.stuff_text("alias greet \"echo hello; wait; echo world\"");
+        console.stuff_text("greet; echo after");
+
+        // the alias runs before the rest of the buffer, including across a wait
+        console.execute();
+        assert_eq!(output_lines(&console), vec!["hello"]);
+        console.execute();
+        assert_eq!(output_lines(&console), vec!["hello", "world", "after"]);
+
+        console.stuff_text("alias greet");
+        console.execute();
+        assert_eq!(
+            output_lines(&console).last().unwrap(),
+            "\"greet\" is \"echo hello; wait; echo world\""
+        );
+    }
+
+    #[test]
+    fn test_console_alias_recursive() {
+        let console = test_console();
+        console.stuff_text("alias loop loop; loop; echo unreachable");
+        console.execute();
+
+        assert_eq!(
+            output_lines(&console),
+            vec!["Command buffer overflow, clearing buffer"]
+        );
+    }
+
+    #[test]
+    fn test_console_echo() {
+        let console = test_console();
+        console.stuff_text("echo several words \"and a quote\"");
+        console.execute();
+        assert_eq!(output_lines(&console), vec!["several words and a quote"]);
+    }
+
+    #[test]
+    fn test_console_cmdlist_cvarlist() {
+        let console = test_console();
+        console
+            .cvars
+            .borrow()
+            .register_archive("name", "player")
+            .unwrap();
+
+        console.stuff_text("cmdlist play; cvarlist fov");
+        console.execute();
+        assert_eq!(
+            output_lines(&console),
+            vec![
+                "  play",
+                "  playdemo",
+                "2 command(s)",
+                "   fov \"90\"",
+                "   fov_adapt \"1\"",
+                "2 cvar(s)",
+            ]
+        );
+
+        console.stuff_text("cvarlist name");
+        console.execute();
+        assert_eq!(output_lines(&console)[6], "*  name \"player\"");
+
+        // typed cvars show their type and range
+        console
+            .cvars
+            .borrow()
+            .define(CvarDef::new(
+                "gamma",
+                "1",
+                CvarKind::Float {
+                    min: Some(0.5),
+                    max: Some(2.0),
+                },
+            ))
+            .unwrap();
+        console.stuff_text("cvarlist gamma");
+        console.execute();
+        assert_eq!(
+            output_lines(&console)[8],
+            "   gamma \"1\" (float, 0.5 to 2)"
+        );
+    }
+
+    #[test]
+    fn test_cvar_registry_write_archived() {
+        let console = test_console();
+        console
+            .cvars
+            .borrow()
+            .register_archive("name", "player")
+            .unwrap();
+        console
+            .cvars
+            .borrow()
+            .register_archive("sensitivity", "3")
+            .unwrap();
+        console.stuff_text("name \"a b\"; sensitivity 5.5; fov 110");
+        console.execute();
+
+        let mut config = Vec::new();
+        console.cvars.borrow().write_archived(&mut config).unwrap();
+        let config = String::from_utf8(config).unwrap();
+        assert_eq!(config, "name \"a b\"\nsensitivity \"5.5\"\n");
+
+        // executing the config restores the values
+        let restored = test_console();
+        restored
+            .cvars
+            .borrow()
+            .register_archive("name", "player")
+            .unwrap();
+        restored
+            .cvars
+            .borrow()
+            .register_archive("sensitivity", "3")
+            .unwrap();
+        restored.stuff_text(config);
+        restored.execute();
+        assert_eq!(restored.cvars.borrow().get("name").unwrap(), "a b");
+        assert_eq!(
+            restored.cvars.borrow().get_value("sensitivity").unwrap(),
+            5.5
+        );
+    }
+
+    #[test]
+    fn test_cvar_registry_listeners() {
+        let cvars = CvarRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        cvars.register("fov", "90").unwrap();
+        cvars.register_notify("sv_gravity", "800").unwrap();
+        cvars.register_notify("teamplay", "0").unwrap();
+
+        let fov_changes = Rc::new(RefCell::new(Vec::new()));
+        let fov_log = fov_changes.clone();
+        let fov_id = cvars
+            .add_listener(
+                "fov",
+                Box::new(move |_, val| fov_log.borrow_mut().push(val.to_owned())),
+            )
+            .unwrap();
+
+        let notify_changes = Rc::new(RefCell::new(Vec::new()));
+        let notify_log = notify_changes.clone();
+        cvars.add_notify_listener(Box::new(move |name, val| {
+            notify_log.borrow_mut().push(format!("{} {}", name, val))
+        }));
+
+        cvars.set("fov", "110").unwrap();
+        cvars.set("sv_gravity", "100").unwrap();
+        // setting the same value again is not a change
+        cvars.set("sv_gravity", "100").unwrap();
+        cvars.set("teamplay", "1").unwrap();
+        assert_eq!(*fov_changes.borrow(), vec!["110"]);
+        assert_eq!(
+            *notify_changes.borrow(),
+            vec!["sv_gravity 100", "teamplay 1"]
+        );
+
+        assert!(cvars.remove_listener(fov_id));
+        assert!(!cvars.remove_listener(fov_id));
+        cvars.set("fov", "90").unwrap();
+        assert_eq!(fov_changes.borrow().len(), 1);
+
+        assert!(cvars
+            .add_listener("nonexistent", Box::new(|_, _| ()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_cvar_registry_serverinfo() {
+        let cvars = CvarRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        cvars.register("fov", "90").unwrap();
+        cvars.register_notify("teamplay", "0").unwrap();
+        cvars.register_notify("sv_gravity", "800").unwrap();
+        cvars.set("teamplay", "1").unwrap();
+        assert_eq!(cvars.serverinfo(), "\\sv_gravity\\800\\teamplay\\1");
+    }
+
+    #[test]
+    fn test_cvar_registry_duplicate_notify() {
+        let cvars = CvarRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        cvars.register("sv_gravity", "800").unwrap();
+        cvars.set("sv_gravity", "100").unwrap();
+        assert!(matches!(
+            cvars.register("sv_gravity", "800"),
+            Err(ConsoleError::DuplicateCvar(_))
+        ));
+        assert_eq!(cvars.serverinfo(), "");
+
+        // registering as notify upgrades the existing cvar, keeping its value
+        cvars.register_notify("sv_gravity", "800").unwrap();
+        assert_eq!(cvars.get("sv_gravity").unwrap(), "100");
+        assert_eq!(cvars.serverinfo(), "\\sv_gravity\\100");
+    }
+
+    #[test]
+    fn test_cvar_registry_define_validates() {
+        let cvars = CvarRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        cvars
+            .define(CvarDef::new("r_lightmap", "0", CvarKind::Bool))
+            .unwrap();
+        cvars
+            .define(CvarDef::new(
+                "r_msaa_samples",
+                "4",
+                CvarKind::Enum(vec!["2".to_owned(), "4".to_owned()]),
+            ))
+            .unwrap();
+        cvars
+            .define(CvarDef::new(
+                "gamma",
+                "1",
+                CvarKind::Float {
+                    min: Some(0.5),
+                    max: Some(2.0),
+                },
+            ))
+            .unwrap();
+        cvars
+            .define(CvarDef::new(
+                "snd_maxchannels",
+                "64",
+                CvarKind::Int {
+                    min: Some(8),
+                    max: None,
+                },
+            ))
+            .unwrap();
+
+        cvars.set("r_lightmap", "1").unwrap();
+        cvars.set("r_msaa_samples", "2").unwrap();
+        cvars.set("gamma", "1.5").unwrap();
+        cvars.set("snd_maxchannels", "128").unwrap();
+
+        for &(name, value) in &[
+            ("r_lightmap", "yes"),
+            ("r_msaa_samples", "3"),
+            ("gamma", "2.5"),
+            ("gamma", "NaN"),
+            ("snd_maxchannels", "4"),
+            ("snd_maxchannels", "12.5"),
+        ] {
+            match cvars.set(name, value) {
+                Err(ConsoleError::InvalidCvarValue { .. }) => (),
+                other => panic!("{} {}: {:?}", name, value, other),
+            }
+        }
+
+        // invalid values leave the cvar unchanged
+        assert_eq!(cvars.get("r_msaa_samples").unwrap(), "2");
+        assert_eq!(cvars.get_value("gamma").unwrap(), 1.5);
+
+        // so do invalid defaults
+        assert!(cvars
+            .define(CvarDef::new("bad", "2", CvarKind::Bool))
+            .is_err());
+        assert!(!cvars.contains("bad"));
+    }
+
+    #[test]
+    fn test_console_help() {
+        let console = test_console();
+        console
+            .cvars
+            .borrow()
+            .define(
+                CvarDef::new(
+                    "r_msaa_samples",
+                    "4",
+                    CvarKind::Enum(vec!["2".to_owned(), "4".to_owned()]),
+                )
+                .archive()
+                .help("Multisample anti-aliasing samples"),
+            )
+            .unwrap();
+
+        console.stuff_text("r_msaa_samples 3; help r_msaa_samples");
+        console.execute();
+        assert_eq!(
+            output_lines(&console),
+            vec![
+                "Invalid value for r_msaa_samples: \"3\" (expected one of 2, 4)",
+                "\"r_msaa_samples\" is \"4\" (default \"4\")",
+                "type: one of 2, 4",
+                "flags: archive",
+                "Multisample anti-aliasing samples",
+            ]
+        );
+    }
+}
@@ -90,9 +90,7 @@ where
             Event::MainEventsCleared => self.frame(),
             Event::Suspended | Event::Resumed => unimplemented!(),
             Event::LoopDestroyed => {
-                // TODO:
-                // - host_writeconfig
-                // - others...
+                // TODO: cleanup beyond what Program::shutdown does
             }
 
             e => self.program.handle_event(e, _target, control_flow),
@@ -81,6 +81,17 @@ impl Model {
         }
     }
 
+    /// Construct a model with no data, used in place of a model that couldn't be loaded.
+    pub fn placeholder<S>(name: S) -> Model
+    where
+        S: AsRef<str>,
+    {
+        Model {
+            name: name.as_ref().to_owned(),
+            ..Model::none()
+        }
+    }
+
     pub fn kind(&self) -> &ModelKind {
         &self.kind
     }
@@ -19,14 +19,20 @@
 // SOFTWARE.
 
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     io::{BufReader, Cursor, ErrorKind},
     mem::size_of,
-    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Instant,
 };
 
-use crate::common::{
-    net::{NetError, QSocket, MAX_MESSAGE},
-    util,
+use crate::common::net::{
+    filter::{BanList, RateLimiter},
+    read_cstring, BlockingMode, NetError, QSocket, MAX_MESSAGE,
 };
 
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
@@ -37,6 +43,127 @@ pub const CONNECT_PROTOCOL_VERSION: u8 = 3;
 const CONNECT_CONTROL: i32 = 1 << 31;
 const CONNECT_LENGTH_MASK: i32 = 0x0000FFFF;
 
+/// The port servers listen on for connection requests if none is specified.
+pub const DEFAULT_PORT: u16 = 26000;
+
+/// Splits a server address into its host and port components.
+///
+/// Accepts `host`, `host:port`, `[ipv6]`, `[ipv6]:port` and bare IPv6 literals such as `::1`.
+/// If no port is given, `DEFAULT_PORT` is used.
+fn split_host_port(addr: &str) -> Result<(&str, u16), NetError> {
+    let parse_port = |port: &str| {
+        port.parse::<u16>().map_err(|_| {
+            NetError::InvalidData(format!("port \"{}\" in server address \"{}\"", port, addr))
+        })
+    };
+
+    if addr.is_empty() {
+        return Err(NetError::InvalidData("empty server address".to_owned()));
+    }
+
+    if let Some(rest) = addr.strip_prefix('[') {
+        // bracketed IPv6 address, e.g. [::1]:26000
+        let end = rest.find(']').ok_or_else(|| {
+            NetError::InvalidData(format!("unterminated '[' in server address \"{}\"", addr))
+        })?;
+        let host = &rest[..end];
+
+        if host.parse::<Ipv6Addr>().is_err() {
+            return Err(NetError::InvalidData(format!(
+                "\"{}\" in server address \"{}\" is not an IPv6 address",
+                host, addr
+            )));
+        }
+
+        let port = match &rest[end + 1..] {
+            "" => DEFAULT_PORT,
+            tail => match tail.strip_prefix(':') {
+                Some(port) => parse_port(port)?,
+                None => {
+                    return Err(NetError::InvalidData(format!(
+                        "unexpected \"{}\" after ']' in server address \"{}\"",
+                        tail, addr
+                    )))
+                }
+            },
+        };
+
+        return Ok((host, port));
+    }
+
+    // more than one colon means an unbracketed IPv6 address, which can't carry a port
+    if addr.matches(':').count() > 1 {
+        return match addr.parse::<Ipv6Addr>() {
+            Ok(_) => Ok((addr, DEFAULT_PORT)),
+            Err(_) => Err(NetError::InvalidData(format!(
+                "server address \"{}\" (IPv6 addresses with a port must be written as [addr]:port)",
+                addr
+            ))),
+        };
+    }
+
+    match addr.rfind(':') {
+        Some(i) => Ok((&addr[..i], parse_port(&addr[i + 1..])?)),
+        None => Ok((addr, DEFAULT_PORT)),
+    }
+}
+
+/// Resolves a server address to one or more socket addresses.
+///
+/// The address may be a hostname or an IPv4 or IPv6 address, optionally followed by a port (see
+/// `split_host_port`). Hostnames are resolved using the system resolver, which may block.
+pub fn resolve_server_addr(addr: &str) -> Result<Vec<SocketAddr>, NetError> {
+    let (host, port) = split_host_port(addr.trim())?;
+
+    if host.is_empty() {
+        return Err(NetError::InvalidData(format!(
+            "missing host in server address \"{}\"",
+            addr
+        )));
+    }
+
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| NetError::Resolve(format!("{}: {}", host, e)))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(NetError::Resolve(format!("{}: no addresses found", host)));
+    }
+
+    Ok(addrs)
+}
+
+/// The result of [`resolve_server_addr_async`].
+pub enum ResolveServerAddr {
+    /// The address was resolved without consulting the system resolver.
+    Done(Vec<SocketAddr>),
+
+    /// A hostname is being resolved on another thread, which will send the result.
+    Pending(Receiver<Result<Vec<SocketAddr>, NetError>>),
+}
+
+/// Resolves a server address like [`resolve_server_addr`], but without blocking on the system
+/// resolver.
+///
+/// IP addresses are resolved immediately. Hostnames are looked up on a separate thread. Malformed
+/// addresses are reported immediately either way.
+pub fn resolve_server_addr_async(addr: &str) -> Result<ResolveServerAddr, NetError> {
+    let (host, _) = split_host_port(addr.trim())?;
+    if host.is_empty() || host.parse::<IpAddr>().is_ok() {
+        return resolve_server_addr(addr).map(ResolveServerAddr::Done);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let addr = addr.to_owned();
+    thread::spawn(move || {
+        // if the receiver is gone, nobody is waiting on the result anymore
+        let _ = tx.send(resolve_server_addr(&addr));
+    });
+
+    Ok(ResolveServerAddr::Pending(rx))
+}
+
 pub trait ConnectPacket {
     /// Returns the numeric value of this packet's code.
     fn code(&self) -> u8;
@@ -525,9 +652,29 @@ impl ConnectPacket for Response {
     }
 }
 
+/// The default maximum number of connections that may be pending at once.
+pub const DEFAULT_MAX_PENDING: usize = 8;
+
+/// How long an accepted connection may take to be established before its slot is freed.
+pub fn default_pending_timeout() -> Duration {
+    Duration::seconds(10)
+}
+
 /// A socket that listens for new connections or queries.
+///
+/// Before a packet is parsed, its sender is checked against a [`RateLimiter`] and a [`BanList`].
+/// Packets from throttled addresses are dropped silently; connection requests from banned
+/// addresses are rejected. Connection requests are also rejected while too many connections are
+/// pending, that is, accepted but not yet reported as established with
+/// [`connection_established`](ConnectListener::connection_established). Packets that can't be
+/// parsed are dropped.
 pub struct ConnectListener {
     socket: UdpSocket,
+    rate_limiter: RateLimiter,
+    bans: Rc<RefCell<BanList>>,
+    pending: HashMap<SocketAddr, Instant>,
+    max_pending: usize,
+    pending_timeout: Duration,
 }
 
 impl ConnectListener {
@@ -538,16 +685,147 @@ impl ConnectListener {
     {
         let socket = UdpSocket::bind(addr)?;
 
-        Ok(ConnectListener { socket })
+        Ok(ConnectListener {
+            socket,
+            rate_limiter: RateLimiter::default(),
+            bans: Rc::new(RefCell::new(BanList::new())),
+            pending: HashMap::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            pending_timeout: default_pending_timeout(),
+        })
+    }
+
+    /// Returns the local address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Replaces the per-address rate limiter.
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    /// Returns the ban list, which may be shared with the `ban` console commands.
+    pub fn bans(&self) -> Rc<RefCell<BanList>> {
+        self.bans.clone()
+    }
+
+    /// Replaces the ban list.
+    pub fn set_bans(&mut self, bans: Rc<RefCell<BanList>>) {
+        self.bans = bans;
+    }
+
+    /// Sets the maximum number of pending connections and how long each may remain pending.
+    pub fn set_max_pending(&mut self, max_pending: usize, timeout: Duration) {
+        self.max_pending = max_pending;
+        self.pending_timeout = timeout;
+    }
+
+    /// Returns the number of connections that have been accepted but not yet established.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Marks the pending connection from `remote` as established, freeing its slot.
+    pub fn connection_established(&mut self, remote: SocketAddr) {
+        self.pending.remove(&remote);
     }
 
     /// Receives a request and returns it along with its remote address.
-    pub fn recv_request(&self) -> Result<(Request, SocketAddr), NetError> {
+    ///
+    /// Packets that are filtered out are skipped, and the listener keeps waiting for the next one.
+    /// If `block` is `BlockingMode::NonBlocking` and no packet is waiting, or if it is
+    /// `BlockingMode::Timeout` and no packet arrives in time, the function returns `None`.
+    pub fn recv_request(
+        &mut self,
+        block: BlockingMode,
+    ) -> Result<Option<(Request, SocketAddr)>, NetError> {
+        match block {
+            BlockingMode::Blocking => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::NonBlocking => {
+                self.socket.set_nonblocking(true)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::Timeout(d) => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
+            }
+        }
+
         // Original engine receives connection requests in `net_message`,
         // allocated at https://github.com/id-Software/Quake/blob/master/WinQuake/net_main.c#L851
         let mut recv_buf = [0u8; MAX_MESSAGE];
-        let (len, remote) = self.socket.recv_from(&mut recv_buf)?;
-        let mut reader = BufReader::new(&recv_buf[..len]);
+
+        loop {
+            let (len, remote) = match self.socket.recv_from(&mut recv_buf) {
+                Err(e) => match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(None),
+                    _ => return Err(NetError::from(e)),
+                },
+                Ok(ret) => ret,
+            };
+
+            let now = Instant::now();
+            if !self.rate_limiter.check(remote.ip(), now) {
+                debug!("Dropping packet from {} (rate limited)", remote);
+                continue;
+            }
+
+            if self.bans.borrow().is_banned(remote.ip()) {
+                debug!("Dropping packet from {} (banned)", remote);
+
+                // let banned clients know why they can't connect
+                if let Ok(Request::Connect(_)) = ConnectListener::parse_request(&recv_buf[..len]) {
+                    self.send_response(
+                        Response::Reject(ResponseReject {
+                            message: String::from("You have been banned.\n"),
+                        }),
+                        remote,
+                    )?;
+                }
+                continue;
+            }
+
+            let request = match ConnectListener::parse_request(&recv_buf[..len]) {
+                Ok(r) => r,
+                Err(e) => {
+                    debug!("Dropping packet from {}: {}", remote, e);
+                    continue;
+                }
+            };
+
+            if let Request::Connect(_) = request {
+                let timeout = self.pending_timeout.to_std().unwrap_or_default();
+                self.pending
+                    .retain(|_, since| now.saturating_duration_since(*since) < timeout);
+
+                // a client may resend its request if our response was lost
+                if !self.pending.contains_key(&remote) && self.pending.len() >= self.max_pending {
+                    debug!("Rejecting connection from {} (too many pending)", remote);
+                    self.send_response(
+                        Response::Reject(ResponseReject {
+                            message: String::from("Server is busy, try again later.\n"),
+                        }),
+                        remote,
+                    )?;
+                    continue;
+                }
+
+                self.pending.insert(remote, now);
+            }
+
+            return Ok(Some((request, remote)));
+        }
+    }
+
+    fn parse_request(packet: &[u8]) -> Result<Request, NetError> {
+        let len = packet.len();
+        let mut reader = BufReader::new(packet);
 
         let control = reader.read_i32::<NetworkEndian>()?;
 
@@ -587,7 +865,7 @@ impl ConnectListener {
 
         let request = match request_code {
             RequestCode::Connect => {
-                let game_name = util::read_cstring(&mut reader).unwrap();
+                let game_name = read_cstring(&mut reader)?;
                 let proto_ver = reader.read_u8()?;
                 Request::Connect(RequestConnect {
                     game_name,
@@ -596,7 +874,7 @@ impl ConnectListener {
             }
 
             RequestCode::ServerInfo => {
-                let game_name = util::read_cstring(&mut reader).unwrap();
+                let game_name = read_cstring(&mut reader)?;
                 Request::ServerInfo(RequestServerInfo { game_name })
             }
 
@@ -606,12 +884,12 @@ impl ConnectListener {
             }
 
             RequestCode::RuleInfo => {
-                let prev_cvar = util::read_cstring(&mut reader).unwrap();
+                let prev_cvar = read_cstring(&mut reader)?;
                 Request::RuleInfo(RequestRuleInfo { prev_cvar })
             }
         };
 
-        Ok((request, remote))
+        Ok(request)
     }
 
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
@@ -634,6 +912,17 @@ impl ConnectSocket {
         Ok(ConnectSocket { socket })
     }
 
+    /// Binds a `ConnectSocket` to an ephemeral port on the unspecified address of the same family
+    /// as `remote`, so that it can reach both IPv4 and IPv6 servers.
+    pub fn bind_for(remote: SocketAddr) -> Result<ConnectSocket, NetError> {
+        let local_ip = match remote.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        ConnectSocket::bind(SocketAddr::new(local_ip, 0))
+    }
+
     pub fn into_qsocket(self, remote: SocketAddr) -> QSocket {
         QSocket::new(self.socket, remote)
     }
@@ -646,17 +935,31 @@ impl ConnectSocket {
 
     /// Receive a `Response` from the server.
     ///
-    /// If `timeout` is not `None`, the operation times out after the specified duration and the
-    /// function returns `None`.
+    /// If `block` is `BlockingMode::NonBlocking` and no response is waiting, or if it is
+    /// `BlockingMode::Timeout` and the timeout expires, the function returns `None`.
     pub fn recv_response(
         &mut self,
-        timeout: Option<Duration>,
+        block: BlockingMode,
     ) -> Result<Option<(Response, SocketAddr)>, NetError> {
         let mut recv_buf = [0u8; MAX_MESSAGE];
 
-        // if a timeout was specified, apply it for this recv
-        self.socket
-            .set_read_timeout(timeout.map(|d| d.to_std().unwrap()))?;
+        match block {
+            BlockingMode::Blocking => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::NonBlocking => {
+                self.socket.set_nonblocking(true)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::Timeout(d) => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
+            }
+        }
+
         let (len, remote) = match self.socket.recv_from(&mut recv_buf) {
             Err(e) => match e.kind() {
                 ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(None),
@@ -664,7 +967,6 @@ impl ConnectSocket {
             },
             Ok(ret) => ret,
         };
-        self.socket.set_read_timeout(None)?;
 
         let mut reader = BufReader::new(&recv_buf[..len]);
 
@@ -710,14 +1012,14 @@ impl ConnectSocket {
             }
 
             ResponseCode::Reject => {
-                let message = util::read_cstring(&mut reader).unwrap();
+                let message = read_cstring(&mut reader)?;
                 Response::Reject(ResponseReject { message })
             }
 
             ResponseCode::ServerInfo => {
-                let address = util::read_cstring(&mut reader).unwrap();
-                let hostname = util::read_cstring(&mut reader).unwrap();
-                let levelname = util::read_cstring(&mut reader).unwrap();
+                let address = read_cstring(&mut reader)?;
+                let hostname = read_cstring(&mut reader)?;
+                let levelname = read_cstring(&mut reader)?;
                 let client_count = reader.read_u8()?;
                 let client_max = reader.read_u8()?;
                 let protocol_version = reader.read_u8()?;
@@ -732,8 +1034,32 @@ impl ConnectSocket {
                 })
             }
 
-            ResponseCode::PlayerInfo => unimplemented!(),
-            ResponseCode::RuleInfo => unimplemented!(),
+            ResponseCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                let player_name = read_cstring(&mut reader)?;
+                let colors = reader.read_i32::<LittleEndian>()?;
+                let frags = reader.read_i32::<LittleEndian>()?;
+                let connect_duration = reader.read_i32::<LittleEndian>()?;
+                let address = read_cstring(&mut reader)?;
+
+                Response::PlayerInfo(ResponsePlayerInfo {
+                    player_id,
+                    player_name,
+                    colors,
+                    frags,
+                    connect_duration,
+                    address,
+                })
+            }
+
+            ResponseCode::RuleInfo => {
+                let cvar_name = read_cstring(&mut reader)?;
+                let cvar_val = read_cstring(&mut reader)?;
+                Response::RuleInfo(ResponseRuleInfo {
+                    cvar_name,
+                    cvar_val,
+                })
+            }
         };
 
         Ok(Some((response, remote)))
@@ -744,6 +1070,8 @@ impl ConnectSocket {
 mod test {
     use super::*;
 
+    use crate::common::net::filter::BanEntry;
+
     // test_request_*_packet_len
     //
     // These tests ensure that ConnectPacket::packet_len() returns an accurate value by comparing it
@@ -840,4 +1168,330 @@ mod test {
     fn test_connect_listener_bind() {
         let _listener = ConnectListener::bind("127.0.0.1:26000").unwrap();
     }
+
+    #[test]
+    fn test_resolve_server_addr_ipv4() {
+        let v4 = |a, b, c, d, port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), port);
+
+        assert_eq!(
+            resolve_server_addr("127.0.0.1").unwrap(),
+            vec![v4(127, 0, 0, 1, DEFAULT_PORT)]
+        );
+        assert_eq!(
+            resolve_server_addr("127.0.0.1:27500").unwrap(),
+            vec![v4(127, 0, 0, 1, 27500)]
+        );
+    }
+
+    #[test]
+    fn test_resolve_server_addr_ipv6() {
+        let v6 = |port| SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port);
+
+        assert_eq!(resolve_server_addr("::1").unwrap(), vec![v6(DEFAULT_PORT)]);
+        assert_eq!(
+            resolve_server_addr("[::1]").unwrap(),
+            vec![v6(DEFAULT_PORT)]
+        );
+        assert_eq!(resolve_server_addr("[::1]:27500").unwrap(), vec![v6(27500)]);
+    }
+
+    #[test]
+    fn test_resolve_server_addr_hostname() {
+        let addrs = resolve_server_addr("localhost:27500").unwrap();
+        assert!(!addrs.is_empty());
+        for addr in addrs {
+            assert!(addr.ip().is_loopback());
+            assert_eq!(addr.port(), 27500);
+        }
+    }
+
+    #[test]
+    fn test_resolve_server_addr_async() {
+        match resolve_server_addr_async("127.0.0.1:27500").unwrap() {
+            ResolveServerAddr::Done(addrs) => {
+                assert_eq!(addrs, vec!["127.0.0.1:27500".parse().unwrap()])
+            }
+            ResolveServerAddr::Pending(_) => panic!("IP address resolved on another thread"),
+        }
+
+        let rx = match resolve_server_addr_async("localhost:27500").unwrap() {
+            ResolveServerAddr::Pending(rx) => rx,
+            ResolveServerAddr::Done(_) => panic!("hostname resolved on the calling thread"),
+        };
+        let addrs = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.ip().is_loopback()));
+
+        assert!(matches!(
+            resolve_server_addr_async("127.0.0.1:port"),
+            Err(NetError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_server_addr_invalid() {
+        for addr in &[
+            "",
+            ":26000",
+            "127.0.0.1:",
+            "127.0.0.1:65536",
+            "127.0.0.1:port",
+            "[::1",
+            "[::1]26000",
+            "[127.0.0.1]:26000",
+            "::1:26000x",
+        ] {
+            match resolve_server_addr(addr) {
+                Err(NetError::InvalidData(_)) => (),
+                x => panic!("expected InvalidData for \"{}\", got {:?}", addr, x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_server_addr_unknown_host() {
+        // the .invalid TLD is guaranteed never to resolve (RFC 2606)
+        match resolve_server_addr("richter.invalid") {
+            Err(NetError::Resolve(_)) => (),
+            x => panic!("expected Resolve error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_connect_socket_loopback() {
+        for local in &["127.0.0.1:0", "[::1]:0"] {
+            let mut listener = match ConnectListener::bind(local) {
+                Ok(l) => l,
+                // IPv6 may be disabled on the host
+                Err(_) if local.starts_with('[') => continue,
+                Err(e) => panic!("{}", e),
+            };
+            let listen_addr = listener.local_addr().unwrap();
+
+            let server_addr = resolve_server_addr(&listen_addr.to_string()).unwrap()[0];
+            assert_eq!(server_addr, listen_addr);
+
+            let mut con_sock = ConnectSocket::bind_for(server_addr).unwrap();
+            con_sock
+                .send_request(
+                    Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION),
+                    server_addr,
+                )
+                .unwrap();
+
+            let (request, remote) = listener
+                .recv_request(BlockingMode::Timeout(Duration::seconds(1)))
+                .unwrap()
+                .unwrap();
+            assert_eq!(remote.port(), con_sock.socket.local_addr().unwrap().port());
+            match request {
+                Request::Connect(RequestConnect {
+                    game_name,
+                    proto_ver,
+                }) => {
+                    assert_eq!(game_name, "QUAKE");
+                    assert_eq!(proto_ver, CONNECT_PROTOCOL_VERSION);
+                }
+                r => panic!("unexpected request {:?}", r),
+            }
+
+            // nothing has been sent back yet
+            assert!(con_sock
+                .recv_response(BlockingMode::NonBlocking)
+                .unwrap()
+                .is_none());
+
+            listener
+                .send_response(Response::Accept(ResponseAccept { port: 27500 }), remote)
+                .unwrap();
+            match con_sock
+                .recv_response(BlockingMode::Timeout(Duration::seconds(1)))
+                .unwrap()
+            {
+                Some((Response::Accept(ResponseAccept { port }), from)) => {
+                    assert_eq!(port, 27500);
+                    assert_eq!(from, server_addr);
+                }
+                r => panic!("unexpected response {:?}", r),
+            }
+        }
+    }
+
+    // sends raw request packets to a listener from a plain UDP socket
+    struct TestClient {
+        socket: UdpSocket,
+        server: SocketAddr,
+    }
+
+    impl TestClient {
+        fn new(server: SocketAddr) -> TestClient {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(std::time::Duration::from_millis(200)))
+                .unwrap();
+            TestClient { socket, server }
+        }
+
+        fn send(&self, request: Request) {
+            self.socket
+                .send_to(&request.to_bytes().unwrap(), self.server)
+                .unwrap();
+        }
+
+        fn send_raw(&self, packet: &[u8]) {
+            self.socket.send_to(packet, self.server).unwrap();
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.socket.local_addr().unwrap()
+        }
+
+        // returns the message of a Reject response, if one arrives
+        fn recv_reject(&self) -> Option<String> {
+            let mut buf = [0u8; MAX_MESSAGE];
+            let len = self.socket.recv(&mut buf).ok()?;
+            assert_eq!(buf[4], ResponseCode::Reject as u8);
+            let mut reader = &buf[5..len];
+            Some(read_cstring(&mut reader).unwrap())
+        }
+    }
+
+    fn test_listener() -> ConnectListener {
+        ConnectListener::bind("127.0.0.1:0").unwrap()
+    }
+
+    fn recv(listener: &mut ConnectListener) -> Option<(Request, SocketAddr)> {
+        listener
+            .recv_request(BlockingMode::Timeout(Duration::milliseconds(200)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_connect_listener_rate_limit() {
+        let mut listener = test_listener();
+        listener.set_rate_limiter(RateLimiter::new(3, Duration::milliseconds(250)));
+        let client = TestClient::new(listener.local_addr().unwrap());
+
+        for _ in 0..5 {
+            client.send(Request::server_info("QUAKE"));
+        }
+
+        for _ in 0..3 {
+            let (request, remote) = recv(&mut listener).unwrap();
+            assert_eq!(remote, client.addr());
+            match request {
+                Request::ServerInfo(_) => (),
+                r => panic!("unexpected request {:?}", r),
+            }
+        }
+
+        // the last two were dropped
+        assert!(recv(&mut listener).is_none());
+
+        // once the client backs off, it can be heard again
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        client.send(Request::server_info("QUAKE"));
+        assert_eq!(recv(&mut listener).unwrap().1, client.addr());
+    }
+
+    #[test]
+    fn test_connect_listener_ban() {
+        let mut listener = test_listener();
+        let client = TestClient::new(listener.local_addr().unwrap());
+        let entry: BanEntry = "127.0.0.1".parse().unwrap();
+        listener.bans().borrow_mut().ban(entry);
+
+        // connection requests are rejected
+        client.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert!(recv(&mut listener).is_none());
+        assert_eq!(
+            client.recv_reject().as_deref(),
+            Some("You have been banned.\n")
+        );
+
+        // queries are dropped without a response
+        client.send(Request::server_info("QUAKE"));
+        assert!(recv(&mut listener).is_none());
+        assert!(client.recv_reject().is_none());
+
+        listener.bans().borrow_mut().unban(entry);
+        client.send(Request::server_info("QUAKE"));
+        assert_eq!(recv(&mut listener).unwrap().1, client.addr());
+    }
+
+    #[test]
+    fn test_connect_listener_malformed() {
+        let mut listener = test_listener();
+        let client = TestClient::new(listener.local_addr().unwrap());
+
+        // garbage is dropped without ending the listener's loop
+        client.send_raw(&[0xFF; 3]);
+        client.send_raw(&[0x80, 0x00, 0x00, 0x06, 0x7F, 0x00]);
+        client.send(Request::server_info("QUAKE"));
+        match recv(&mut listener) {
+            Some((Request::ServerInfo(_), remote)) => assert_eq!(remote, client.addr()),
+            r => panic!("unexpected request {:?}", r),
+        }
+
+        // including from banned addresses
+        listener
+            .bans()
+            .borrow_mut()
+            .ban("127.0.0.1".parse().unwrap());
+        client.send_raw(&[0xFF; 3]);
+        assert!(recv(&mut listener).is_none());
+        assert!(client.recv_reject().is_none());
+    }
+
+    #[test]
+    fn test_connect_listener_max_pending() {
+        let mut listener = test_listener();
+        listener.set_max_pending(1, Duration::minutes(1));
+        let first = TestClient::new(listener.local_addr().unwrap());
+        let second = TestClient::new(listener.local_addr().unwrap());
+
+        first.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert_eq!(recv(&mut listener).unwrap().1, first.addr());
+        assert_eq!(listener.pending_count(), 1);
+
+        // no room for a second connection
+        second.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert!(recv(&mut listener).is_none());
+        assert_eq!(
+            second.recv_reject().as_deref(),
+            Some("Server is busy, try again later.\n")
+        );
+
+        // but the first client may retry its request
+        first.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert_eq!(recv(&mut listener).unwrap().1, first.addr());
+
+        // queries don't count against the limit
+        second.send(Request::server_info("QUAKE"));
+        assert_eq!(recv(&mut listener).unwrap().1, second.addr());
+
+        listener.connection_established(first.addr());
+        assert_eq!(listener.pending_count(), 0);
+        second.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert_eq!(recv(&mut listener).unwrap().1, second.addr());
+    }
+
+    #[test]
+    fn test_connect_listener_pending_timeout() {
+        let mut listener = test_listener();
+        listener.set_max_pending(1, Duration::milliseconds(50));
+        let first = TestClient::new(listener.local_addr().unwrap());
+        let second = TestClient::new(listener.local_addr().unwrap());
+
+        first.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert_eq!(recv(&mut listener).unwrap().1, first.addr());
+
+        // the first connection was never established, so its slot expires
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        second.send(Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION));
+        assert_eq!(recv(&mut listener).unwrap().1, second.addr());
+    }
 }
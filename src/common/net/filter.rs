@@ -0,0 +1,596 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Protection against abusive connectionless traffic.
+//!
+//! Connection requests and server queries arrive before any client has been authenticated, so a
+//! public server must be careful how much work it does for them. This module provides a per-address
+//! rate limiter and a persistent ban list, both consulted by
+//! [`ConnectListener`](super::connect::ConnectListener) before a packet is parsed.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, fs,
+    io::ErrorKind,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+    time::Instant,
+};
+
+use crate::common::{console::CmdRegistry, net::NetError, vfs::Vfs};
+
+use chrono::Duration;
+
+/// Number of packets an address may send in a burst before it is throttled.
+pub const DEFAULT_RATE_BURST: u32 = 10;
+
+/// Time taken for a throttled address to earn back a single packet.
+pub fn default_rate_interval() -> Duration {
+    Duration::milliseconds(100)
+}
+
+// past this many tracked addresses, idle ones are forgotten to bound memory use
+const MAX_TRACKED_ADDRS: usize = 4096;
+
+struct Bucket {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+/// A token-bucket rate limiter keyed by remote address.
+///
+/// Each address starts with `burst` tokens and spends one per packet. Tokens are replenished at a
+/// rate of one per `interval`, up to `burst`. Packets from an address with no tokens left should be
+/// dropped.
+pub struct RateLimiter {
+    burst: u32,
+    interval: std::time::Duration,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, interval: Duration) -> RateLimiter {
+        RateLimiter {
+            burst: burst.max(1),
+            interval: interval
+                .to_std()
+                .unwrap_or(std::time::Duration::from_millis(1))
+                .max(std::time::Duration::from_nanos(1)),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Records a packet from `addr` received at time `now`.
+    ///
+    /// Returns `true` if the packet should be processed, or `false` if the address has exceeded its
+    /// rate limit.
+    pub fn check(&mut self, addr: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= MAX_TRACKED_ADDRS && !self.buckets.contains_key(&addr) {
+            self.prune(now);
+        }
+
+        let burst = self.burst;
+        let interval = self.interval;
+        let bucket = self.buckets.entry(addr).or_insert(Bucket {
+            tokens: burst,
+            last_refill: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        let refill = (elapsed.as_nanos() / interval.as_nanos()) as u64;
+        if refill > 0 {
+            let tokens = bucket.tokens as u64 + refill;
+            if tokens >= burst as u64 {
+                bucket.tokens = burst;
+                bucket.last_refill = now;
+            } else {
+                bucket.tokens = tokens as u32;
+                bucket.last_refill += interval * refill as u32;
+            }
+        }
+
+        if bucket.tokens == 0 {
+            return false;
+        }
+
+        bucket.tokens -= 1;
+        true
+    }
+
+    /// Forgets addresses whose buckets have refilled completely.
+    fn prune(&mut self, now: Instant) {
+        let full = self.interval * self.burst;
+        self.buckets
+            .retain(|_, b| now.saturating_duration_since(b.last_refill) < full);
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(DEFAULT_RATE_BURST, default_rate_interval())
+    }
+}
+
+/// A single address or network in a ban list, e.g. `10.0.0.7` or `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BanEntry {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl BanEntry {
+    /// Creates a ban entry covering all addresses whose first `prefix_len` bits match `addr`.
+    ///
+    /// Any bits of `addr` past the prefix are cleared, so `10.0.0.1/8` and `10.0.0.0/8` are the same
+    /// entry.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<BanEntry, NetError> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix_len > max_len {
+            return Err(NetError::InvalidData(format!(
+                "prefix length {} for {}",
+                prefix_len, addr
+            )));
+        }
+
+        fn mask(octets: &mut [u8], prefix_len: u8) {
+            for (i, o) in octets.iter_mut().enumerate() {
+                let keep = (prefix_len as usize).saturating_sub(i * 8).min(8);
+                *o &= (0xFF00u16 >> keep) as u8;
+            }
+        }
+
+        let addr = match addr {
+            IpAddr::V4(v4) => {
+                let mut octets = v4.octets();
+                mask(&mut octets, prefix_len);
+                IpAddr::from(octets)
+            }
+            IpAddr::V6(v6) => {
+                let mut octets = v6.octets();
+                mask(&mut octets, prefix_len);
+                IpAddr::from(octets)
+            }
+        };
+
+        Ok(BanEntry { addr, prefix_len })
+    }
+
+    /// Returns whether this entry covers `addr`.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+            let whole = prefix_len as usize / 8;
+            let rem = prefix_len % 8;
+
+            if a[..whole] != b[..whole] {
+                return false;
+            }
+
+            if rem == 0 {
+                return true;
+            }
+
+            let mask = 0xFFu8 << (8 - rem);
+            a[whole] & mask == b[whole] & mask
+        }
+
+        // treat IPv4-mapped IPv6 addresses as IPv4 so dual-stack sockets can't dodge a ban
+        let addr = match addr {
+            IpAddr::V6(v6) => match v6.to_ipv4() {
+                Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xFFFF] => IpAddr::V4(v4),
+                _ => addr,
+            },
+            v4 => v4,
+        };
+
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => {
+                prefix_eq(&net.octets(), &a.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                prefix_eq(&net.octets(), &a.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for BanEntry {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetError::InvalidData(format!("ban entry \"{}\"", s));
+
+        let (addr, prefix_len) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix_len = match prefix_len {
+            Some(p) => p.parse::<u8>().map_err(|_| invalid())?,
+            None => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+        };
+
+        BanEntry::new(addr, prefix_len)
+    }
+}
+
+impl fmt::Display for BanEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.addr, self.prefix_len) {
+            (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => write!(f, "{}", self.addr),
+            _ => write!(f, "{}/{}", self.addr, self.prefix_len),
+        }
+    }
+}
+
+/// A list of banned addresses, optionally backed by a file.
+///
+/// The file format is one entry per line; blank lines and lines beginning with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct BanList {
+    entries: Vec<BanEntry>,
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    /// Creates an empty ban list that is not saved anywhere.
+    pub fn new() -> BanList {
+        BanList::default()
+    }
+
+    /// Loads a ban list from `path`, which is also where it will be saved.
+    ///
+    /// A missing file is treated as an empty list.
+    pub fn load<P>(path: P) -> Result<BanList, NetError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            entries.push(line.parse()?);
+        }
+
+        Ok(BanList {
+            entries,
+            path: Some(path.to_owned()),
+        })
+    }
+
+    /// Writes the ban list back to the file it was loaded from, if any.
+    pub fn save(&self) -> Result<(), NetError> {
+        let path = match self.path {
+            Some(ref p) => p,
+            None => return Ok(()),
+        };
+
+        let mut text = String::from("# banned addresses, one per line\n");
+        for entry in self.entries.iter() {
+            text.push_str(&format!("{}\n", entry));
+        }
+
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Returns whether `addr` is covered by any entry in the list.
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.entries.iter().any(|e| e.contains(addr))
+    }
+
+    /// Adds an entry to the list. Returns `false` if it was already present.
+    pub fn ban(&mut self, entry: BanEntry) -> bool {
+        if self.entries.contains(&entry) {
+            return false;
+        }
+
+        self.entries.push(entry);
+        true
+    }
+
+    /// Removes an entry from the list. Returns `false` if it wasn't present.
+    pub fn unban(&mut self, entry: BanEntry) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| *e != entry);
+        self.entries.len() != len
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BanEntry> {
+        self.entries.iter()
+    }
+}
+
+/// The name of the saved ban list in the game directory.
+pub const BAN_FILE: &str = "banned.txt";
+
+/// Loads the ban list saved in the game directory of `vfs`.
+///
+/// If there is no game directory, or the file can't be read, the list is kept in memory only so
+/// that a bad file is never overwritten.
+pub fn load_bans(vfs: &Vfs) -> BanList {
+    let path = match vfs.game_dir() {
+        Some(dir) => dir.join(BAN_FILE),
+        None => return BanList::new(),
+    };
+
+    match BanList::load(&path) {
+        Ok(bans) => bans,
+        Err(e) => {
+            warn!("Couldn't load ban list from {}: {}", path.display(), e);
+            BanList::new()
+        }
+    }
+}
+
+/// Registers the `ban`, `unban` and `banlist` commands.
+///
+/// `bans` should be the list given to the server's listener with
+/// [`ConnectListener::set_bans`](super::connect::ConnectListener::set_bans); otherwise the
+/// commands edit a list that nothing checks.
+pub fn register_ban_commands(cmds: &mut CmdRegistry, bans: Rc<RefCell<BanList>>) {
+    cmds.insert_or_replace("ban", cmd_ban(bans.clone()))
+        .unwrap();
+    cmds.insert_or_replace("unban", cmd_unban(bans.clone()))
+        .unwrap();
+    cmds.insert_or_replace("banlist", cmd_banlist(bans))
+        .unwrap();
+}
+
+// implements the "ban" command
+fn cmd_ban(bans: Rc<RefCell<BanList>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: ban <address>[/<prefix length>]".to_owned();
+        }
+
+        let entry: BanEntry = match args[0].parse() {
+            Ok(e) => e,
+            Err(e) => return format!("{}", e),
+        };
+
+        let mut bans = bans.borrow_mut();
+        if !bans.ban(entry) {
+            return format!("{} is already banned", entry);
+        }
+
+        match bans.save() {
+            Ok(()) => format!("Banned {}", entry),
+            Err(e) => format!("Banned {}, but couldn't save ban list: {}", entry, e),
+        }
+    })
+}
+
+// implements the "unban" command
+fn cmd_unban(bans: Rc<RefCell<BanList>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: unban <address>[/<prefix length>]".to_owned();
+        }
+
+        let entry: BanEntry = match args[0].parse() {
+            Ok(e) => e,
+            Err(e) => return format!("{}", e),
+        };
+
+        let mut bans = bans.borrow_mut();
+        if !bans.unban(entry) {
+            return format!("{} is not banned", entry);
+        }
+
+        match bans.save() {
+            Ok(()) => format!("Unbanned {}", entry),
+            Err(e) => format!("Unbanned {}, but couldn't save ban list: {}", entry, e),
+        }
+    })
+}
+
+// implements the "banlist" command
+fn cmd_banlist(bans: Rc<RefCell<BanList>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let bans = bans.borrow();
+        let mut out = String::new();
+        for entry in bans.iter() {
+            out.push_str(&format!("{}\n", entry));
+        }
+
+        match out.len() {
+            0 => "No addresses are banned".to_owned(),
+            _ => out,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::common::util::TempDir;
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn test_rate_limiter_burst() {
+        let mut limiter = RateLimiter::new(3, Duration::seconds(1));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(v4(10, 0, 0, 1), now));
+        }
+        assert!(!limiter.check(v4(10, 0, 0, 1), now));
+
+        // other addresses are unaffected
+        assert!(limiter.check(v4(10, 0, 0, 2), now));
+    }
+
+    #[test]
+    fn test_rate_limiter_refill() {
+        let mut limiter = RateLimiter::new(2, Duration::milliseconds(100));
+        let start = Instant::now();
+        let at = |ms| start + std::time::Duration::from_millis(ms);
+        let addr = v4(10, 0, 0, 1);
+
+        assert!(limiter.check(addr, at(0)));
+        assert!(limiter.check(addr, at(0)));
+        assert!(!limiter.check(addr, at(50)));
+
+        // one token back after 100ms
+        assert!(limiter.check(addr, at(100)));
+        assert!(!limiter.check(addr, at(150)));
+
+        // never more than the burst size, however long the address is idle
+        assert!(limiter.check(addr, at(10_000)));
+        assert!(limiter.check(addr, at(10_000)));
+        assert!(!limiter.check(addr, at(10_000)));
+    }
+
+    #[test]
+    fn test_ban_entry_contains() {
+        let single: BanEntry = "192.168.1.7".parse().unwrap();
+        assert!(single.contains(v4(192, 168, 1, 7)));
+        assert!(!single.contains(v4(192, 168, 1, 8)));
+
+        let net: BanEntry = "192.168.0.0/16".parse().unwrap();
+        assert!(net.contains(v4(192, 168, 200, 1)));
+        assert!(!net.contains(v4(192, 169, 0, 1)));
+
+        let odd: BanEntry = "10.0.0.0/9".parse().unwrap();
+        assert!(odd.contains(v4(10, 127, 255, 255)));
+        assert!(!odd.contains(v4(10, 128, 0, 0)));
+
+        let everything: BanEntry = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(v4(1, 2, 3, 4)));
+        assert!(!everything.contains(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+
+        // IPv4-mapped IPv6 addresses are matched against IPv4 entries
+        assert!(single.contains(IpAddr::V6(Ipv4Addr::new(192, 168, 1, 7).to_ipv6_mapped())));
+
+        let v6_net: BanEntry = "2001:db8::/32".parse().unwrap();
+        assert!(v6_net.contains("2001:db8:1::5".parse().unwrap()));
+        assert!(!v6_net.contains("2001:db9::5".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ban_entry_parse_invalid() {
+        for s in &[
+            "",
+            "banana",
+            "10.0.0.1/33",
+            "::1/129",
+            "10.0.0.1/",
+            "10.0.0.1/x",
+        ] {
+            assert!(s.parse::<BanEntry>().is_err(), "{} parsed", s);
+        }
+    }
+
+    #[test]
+    fn test_ban_entry_display_parse_eq() {
+        for s in &["10.0.0.1", "10.0.0.0/8", "::1", "2001:db8::/32"] {
+            assert_eq!(&s.parse::<BanEntry>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_ban_entry_masks_host_bits() {
+        let entry: BanEntry = "10.0.0.1/8".parse().unwrap();
+        assert_eq!(entry, "10.0.0.0/8".parse().unwrap());
+        assert_eq!(entry.to_string(), "10.0.0.0/8");
+
+        let odd: BanEntry = "10.255.1.1/9".parse().unwrap();
+        assert_eq!(odd.to_string(), "10.128.0.0/9");
+
+        let v6: BanEntry = "2001:db8::1/32".parse().unwrap();
+        assert_eq!(v6.to_string(), "2001:db8::/32");
+
+        let mut bans = BanList::new();
+        assert!(bans.ban("10.0.0.1/8".parse().unwrap()));
+        assert!(!bans.ban("10.1.2.3/8".parse().unwrap()));
+        assert!(bans.unban("10.0.0.0/8".parse().unwrap()));
+        assert_eq!(bans.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_ban_list_save_load_eq() {
+        let dir = TempDir::new("bans");
+        let path = dir.path().join(BAN_FILE);
+
+        let mut bans = BanList::load(&path).unwrap();
+        assert_eq!(bans.iter().count(), 0);
+
+        assert!(bans.ban("10.0.0.1".parse().unwrap()));
+        assert!(bans.ban("2001:db8::/32".parse().unwrap()));
+        assert!(!bans.ban("10.0.0.1".parse().unwrap()));
+        bans.save().unwrap();
+
+        let mut loaded = BanList::load(&path).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            bans.iter().collect::<Vec<_>>()
+        );
+        assert!(loaded.is_banned(v4(10, 0, 0, 1)));
+
+        assert!(loaded.unban("10.0.0.1".parse().unwrap()));
+        assert!(!loaded.unban("10.0.0.1".parse().unwrap()));
+        assert!(!loaded.is_banned(v4(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn test_ban_commands() {
+        let bans = Rc::new(RefCell::new(BanList::new()));
+        let ban = cmd_ban(bans.clone());
+        let unban = cmd_unban(bans.clone());
+        let banlist = cmd_banlist(bans.clone());
+
+        assert_eq!(banlist(&[]), "No addresses are banned");
+        assert_eq!(ban(&["10.0.0.1"]), "Banned 10.0.0.1");
+        assert_eq!(ban(&["10.0.0.1"]), "10.0.0.1 is already banned");
+        assert!(bans.borrow().is_banned(v4(10, 0, 0, 1)));
+        assert_eq!(banlist(&[]), "10.0.0.1\n");
+        assert_eq!(unban(&["10.0.0.1"]), "Unbanned 10.0.0.1");
+        assert_eq!(unban(&["10.0.0.1"]), "10.0.0.1 is not banned");
+        assert!(ban(&[]).starts_with("usage"));
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reading of the FitzQuake network protocol (version 666).
+//!
+//! FitzQuake widens several of the original protocol's indices and adds a handful of commands of
+//! its own. Commands which are encoded the same way in both protocols are read as usual; the rest
+//! are translated into their closest protocol 15 equivalent.
+
+use std::io::{Cursor, Read};
+
+use crate::common::net::{
+    read_cstring, ClientUpdateFlags, NetError, ServerCmd, ServerCmdCode, SoundFlags, UpdateFlags,
+    FAST_UPDATE_FLAG,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use num::FromPrimitive;
+
+pub const PROTOCOL_VERSION: i32 = 666;
+
+// the largest entity protocol 15 can attach a sound to
+const MAX_SOUND_ENTITY: u16 = u16::MAX >> 3;
+
+// extended entity update bits
+const U_EXTEND1: u32 = 1 << 15;
+const U_ALPHA: u32 = 1 << 16;
+const U_FRAME2: u32 = 1 << 17;
+const U_MODEL2: u32 = 1 << 18;
+const U_LERPFINISH: u32 = 1 << 19;
+const U_SCALE: u32 = 1 << 20;
+const U_EXTEND2: u32 = 1 << 23;
+
+// extended client data bits
+const SU_EXTEND1: u32 = 1 << 15;
+const SU_WEAPON2: u32 = 1 << 16;
+const SU_ARMOR2: u32 = 1 << 17;
+const SU_AMMO2: u32 = 1 << 18;
+const SU_SHELLS2: u32 = 1 << 19;
+const SU_NAILS2: u32 = 1 << 20;
+const SU_ROCKETS2: u32 = 1 << 21;
+const SU_CELLS2: u32 = 1 << 22;
+const SU_EXTEND2: u32 = 1 << 23;
+const SU_WEAPONFRAME2: u32 = 1 << 24;
+const SU_WEAPONALPHA: u32 = 1 << 25;
+
+// extended sound bits
+const SND_LARGEENTITY: u8 = 1 << 3;
+const SND_LARGESOUND: u8 = 1 << 4;
+
+// extended baseline bits
+const B_LARGEMODEL: u8 = 1 << 0;
+const B_LARGEFRAME: u8 = 1 << 1;
+const B_ALPHA: u8 = 1 << 2;
+
+#[derive(Debug, FromPrimitive)]
+enum FitzCmdCode {
+    Skybox = 37,
+    Bf = 40,
+    Fog = 41,
+    SpawnBaseline2 = 42,
+    SpawnStatic2 = 43,
+    SpawnStaticSound2 = 44,
+}
+
+/// A protocol 666 command translated to protocol 15.
+#[derive(Debug)]
+pub enum Downgrade {
+    /// The command has a protocol 15 equivalent.
+    ///
+    /// `lost` describes anything that couldn't be carried over.
+    Cmd { cmd: ServerCmd, lost: Vec<String> },
+
+    /// The command can't be represented in protocol 15 at all.
+    Unrepresentable { name: &'static str, reason: String },
+}
+
+impl Downgrade {
+    fn exact(cmd: ServerCmd) -> Downgrade {
+        Downgrade::Cmd {
+            cmd,
+            lost: Vec::new(),
+        }
+    }
+}
+
+/// Reads a protocol 666 command and translates it to protocol 15.
+///
+/// Returns `Ok(None)` at the end of the message.
+pub fn read_downgraded(reader: &mut Cursor<&[u8]>) -> Result<Option<Downgrade>, NetError> {
+    let start = reader.position();
+    let code = match reader.read_u8() {
+        Ok(c) => c,
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(NetError::from(e)),
+    };
+
+    if code & FAST_UPDATE_FLAG != 0 {
+        return read_update(code, reader).map(Some);
+    }
+
+    if code == ServerCmdCode::PlayerData as u8 {
+        return read_client_data(reader).map(Some);
+    }
+
+    if code == ServerCmdCode::Sound as u8 {
+        return read_sound(reader).map(Some);
+    }
+
+    let fitz_code = match FitzCmdCode::from_u8(code) {
+        Some(c) => c,
+
+        // everything else is encoded the same way in both protocols
+        None => {
+            reader.set_position(start);
+            return Ok(ServerCmd::deserialize(reader)?.map(Downgrade::exact));
+        }
+    };
+
+    let downgrade = match fitz_code {
+        FitzCmdCode::Skybox => {
+            let name = read_cstring(reader)?;
+            Downgrade::Unrepresentable {
+                name: "Skybox",
+                reason: format!("skybox \"{}\" is not supported by protocol 15", name),
+            }
+        }
+
+        // the bonus flash is a console command in protocol 15
+        FitzCmdCode::Bf => Downgrade::exact(ServerCmd::StuffText {
+            text: "bf\n".to_owned(),
+        }),
+
+        FitzCmdCode::Fog => {
+            // density, color and fade time
+            let mut fog = [0; 6];
+            reader.read_exact(&mut fog)?;
+            Downgrade::Unrepresentable {
+                name: "Fog",
+                reason: "fog is not supported by protocol 15".to_owned(),
+            }
+        }
+
+        FitzCmdCode::SpawnBaseline2 => {
+            let ent_id = reader.read_u16::<LittleEndian>()?;
+            let mut buf = vec![ServerCmdCode::SpawnBaseline as u8];
+            buf.write_u16::<LittleEndian>(ent_id)?;
+            let lost = read_baseline(reader, &mut buf)?;
+            Downgrade::Cmd {
+                cmd: reread(&buf)?,
+                lost,
+            }
+        }
+
+        FitzCmdCode::SpawnStatic2 => {
+            let mut buf = vec![ServerCmdCode::SpawnStatic as u8];
+            let lost = read_baseline(reader, &mut buf)?;
+            Downgrade::Cmd {
+                cmd: reread(&buf)?,
+                lost,
+            }
+        }
+
+        FitzCmdCode::SpawnStaticSound2 => {
+            let mut origin = [0; 6];
+            reader.read_exact(&mut origin)?;
+            let sound_id = reader.read_u16::<LittleEndian>()?;
+            let volume = reader.read_u8()?;
+            let attenuation = reader.read_u8()?;
+
+            if sound_id > u8::MAX as u16 {
+                Downgrade::Unrepresentable {
+                    name: "SpawnStaticSound",
+                    reason: format!("sound index {} exceeds 255", sound_id),
+                }
+            } else {
+                let mut buf = vec![ServerCmdCode::SpawnStaticSound as u8];
+                buf.extend_from_slice(&origin);
+                buf.extend_from_slice(&[sound_id as u8, volume, attenuation]);
+                Downgrade::exact(reread(&buf)?)
+            }
+        }
+    };
+
+    Ok(Some(downgrade))
+}
+
+/// Reads a command that has been rewritten in protocol 15 encoding.
+fn reread(buf: &[u8]) -> Result<ServerCmd, NetError> {
+    match ServerCmd::deserialize(&mut Cursor::new(buf))? {
+        Some(cmd) => Ok(cmd),
+        None => Err(NetError::InvalidData("empty command".to_owned())),
+    }
+}
+
+/// Copies `len` bytes from `reader` to the end of `buf`.
+fn copy_bytes(reader: &mut Cursor<&[u8]>, buf: &mut Vec<u8>, len: usize) -> Result<(), NetError> {
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    reader.read_exact(&mut buf[start..])?;
+    Ok(())
+}
+
+fn read_update(code: u8, reader: &mut Cursor<&[u8]>) -> Result<Downgrade, NetError> {
+    let mut bits = (code & !FAST_UPDATE_FLAG) as u32;
+    if bits & UpdateFlags::MORE_BITS.bits() as u32 != 0 {
+        bits |= (reader.read_u8()? as u32) << 8;
+    }
+    if bits & U_EXTEND1 != 0 {
+        bits |= (reader.read_u8()? as u32) << 16;
+    }
+    if bits & U_EXTEND2 != 0 {
+        bits |= (reader.read_u8()? as u32) << 24;
+    }
+
+    // the extension bytes all follow the protocol 15 fields, so those can be copied as-is
+    let flags = UpdateFlags::from_bits_truncate(bits as u16);
+    let mut buf = vec![FAST_UPDATE_FLAG | flags.bits() as u8];
+    if flags.contains(UpdateFlags::MORE_BITS) {
+        buf.push((flags.bits() >> 8) as u8);
+    }
+
+    let mut len = match flags.contains(UpdateFlags::LONG_ENTITY) {
+        true => 2,
+        false => 1,
+    };
+    for (flag, size) in &[
+        (UpdateFlags::MODEL, 1),
+        (UpdateFlags::FRAME, 1),
+        (UpdateFlags::COLORMAP, 1),
+        (UpdateFlags::SKIN, 1),
+        (UpdateFlags::EFFECTS, 1),
+        (UpdateFlags::ORIGIN_X, 2),
+        (UpdateFlags::PITCH, 1),
+        (UpdateFlags::ORIGIN_Y, 2),
+        (UpdateFlags::YAW, 1),
+        (UpdateFlags::ORIGIN_Z, 2),
+        (UpdateFlags::ROLL, 1),
+    ] {
+        if flags.contains(*flag) {
+            len += size;
+        }
+    }
+    copy_bytes(reader, &mut buf, len)?;
+
+    let mut update = match reread(&buf)? {
+        ServerCmd::FastUpdate(u) => u,
+        cmd => return Err(NetError::InvalidData(format!("{:?}", cmd))),
+    };
+
+    let mut lost = Vec::new();
+    if bits & U_ALPHA != 0 {
+        reader.read_u8()?;
+        lost.push(format!("alpha of entity {}", update.ent_id));
+    }
+    if bits & U_SCALE != 0 {
+        reader.read_u8()?;
+        lost.push(format!("scale of entity {}", update.ent_id));
+    }
+    if bits & U_FRAME2 != 0 {
+        let high = reader.read_u8()?;
+        if high != 0 {
+            let frame_id = (high as u16) << 8 | update.frame_id.unwrap_or(0) as u16;
+            lost.push(format!("frame {} exceeds 255", frame_id));
+            update.frame_id = None;
+        }
+    }
+    if bits & U_MODEL2 != 0 {
+        let high = reader.read_u8()?;
+        if high != 0 {
+            let model_id = (high as u16) << 8 | update.model_id.unwrap_or(0) as u16;
+            lost.push(format!("model index {} exceeds 255", model_id));
+            update.model_id = None;
+        }
+    }
+    if bits & U_LERPFINISH != 0 {
+        // only an interpolation hint
+        reader.read_u8()?;
+    }
+
+    Ok(Downgrade::Cmd {
+        cmd: ServerCmd::FastUpdate(update),
+        lost,
+    })
+}
+
+fn read_client_data(reader: &mut Cursor<&[u8]>) -> Result<Downgrade, NetError> {
+    let mut bits = reader.read_u16::<LittleEndian>()? as u32;
+    if bits & SU_EXTEND1 != 0 {
+        bits |= (reader.read_u8()? as u32) << 16;
+    }
+    if bits & SU_EXTEND2 != 0 {
+        bits |= (reader.read_u8()? as u32) << 24;
+    }
+
+    let flags_bits = (bits & !SU_EXTEND1) as u16;
+    let flags = match ClientUpdateFlags::from_bits(flags_bits) {
+        Some(f) => f,
+        None => {
+            return Err(NetError::InvalidData(format!(
+                "client update flags: {:b}",
+                flags_bits
+            )))
+        }
+    };
+    let mut buf = vec![ServerCmdCode::PlayerData as u8];
+    buf.write_u16::<LittleEndian>(flags.bits())?;
+
+    // items, health, ammo counts and active weapon are always present
+    let mut len = 12;
+    for flag in &[
+        ClientUpdateFlags::VIEW_HEIGHT,
+        ClientUpdateFlags::IDEAL_PITCH,
+        ClientUpdateFlags::PUNCH_PITCH,
+        ClientUpdateFlags::VELOCITY_X,
+        ClientUpdateFlags::PUNCH_YAW,
+        ClientUpdateFlags::VELOCITY_Y,
+        ClientUpdateFlags::PUNCH_ROLL,
+        ClientUpdateFlags::VELOCITY_Z,
+        ClientUpdateFlags::WEAPON_FRAME,
+        ClientUpdateFlags::ARMOR,
+        ClientUpdateFlags::WEAPON,
+    ] {
+        if flags.contains(*flag) {
+            len += 1;
+        }
+    }
+    copy_bytes(reader, &mut buf, len)?;
+
+    let mut data = match reread(&buf)? {
+        ServerCmd::PlayerData(d) => d,
+        cmd => return Err(NetError::InvalidData(format!("{:?}", cmd))),
+    };
+
+    let mut lost = Vec::new();
+    let mut high_byte = |bit: u32, what: &str| -> Result<bool, NetError> {
+        if bits & bit == 0 {
+            return Ok(false);
+        }
+
+        let high = reader.read_u8()?;
+        if high != 0 {
+            lost.push(format!("{} exceeds 255", what));
+        }
+        Ok(high != 0)
+    };
+
+    if high_byte(SU_WEAPON2, "weapon model index")? {
+        data.weapon = None;
+    }
+    if high_byte(SU_ARMOR2, "armor")? {
+        data.armor = Some(u8::MAX);
+    }
+    if high_byte(SU_AMMO2, "ammo")? {
+        data.ammo = u8::MAX;
+    }
+    if high_byte(SU_SHELLS2, "shells")? {
+        data.ammo_shells = u8::MAX;
+    }
+    if high_byte(SU_NAILS2, "nails")? {
+        data.ammo_nails = u8::MAX;
+    }
+    if high_byte(SU_ROCKETS2, "rockets")? {
+        data.ammo_rockets = u8::MAX;
+    }
+    if high_byte(SU_CELLS2, "cells")? {
+        data.ammo_cells = u8::MAX;
+    }
+    if high_byte(SU_WEAPONFRAME2, "weapon frame")? {
+        data.weapon_frame = None;
+    }
+    if bits & SU_WEAPONALPHA != 0 {
+        reader.read_u8()?;
+        lost.push("weapon alpha".to_owned());
+    }
+
+    Ok(Downgrade::Cmd {
+        cmd: ServerCmd::PlayerData(data),
+        lost,
+    })
+}
+
+fn read_sound(reader: &mut Cursor<&[u8]>) -> Result<Downgrade, NetError> {
+    let flags = reader.read_u8()?;
+    let mut buf = vec![
+        ServerCmdCode::Sound as u8,
+        flags & !(SND_LARGEENTITY | SND_LARGESOUND),
+    ];
+
+    // volume and attenuation
+    for flag in &[SoundFlags::VOLUME, SoundFlags::ATTENUATION] {
+        if flags & flag.bits() != 0 {
+            copy_bytes(reader, &mut buf, 1)?;
+        }
+    }
+
+    let (entity_id, channel) = match flags & SND_LARGEENTITY {
+        0 => {
+            let entity_channel = reader.read_u16::<LittleEndian>()?;
+            (entity_channel >> 3, (entity_channel & 0b111) as u8)
+        }
+        _ => (reader.read_u16::<LittleEndian>()?, reader.read_u8()?),
+    };
+
+    let sound_id = match flags & SND_LARGESOUND {
+        0 => reader.read_u8()? as u16,
+        _ => reader.read_u16::<LittleEndian>()?,
+    };
+
+    let mut position = [0; 6];
+    reader.read_exact(&mut position)?;
+
+    let reason = if entity_id > MAX_SOUND_ENTITY {
+        format!("entity {} exceeds {}", entity_id, MAX_SOUND_ENTITY)
+    } else if channel > 0b111 {
+        format!("channel {} exceeds 7", channel)
+    } else if sound_id > u8::MAX as u16 {
+        format!("sound index {} exceeds 255", sound_id)
+    } else {
+        buf.write_u16::<LittleEndian>(entity_id << 3 | channel as u16)?;
+        buf.push(sound_id as u8);
+        buf.extend_from_slice(&position);
+        return Ok(Downgrade::exact(reread(&buf)?));
+    };
+
+    Ok(Downgrade::Unrepresentable {
+        name: "Sound",
+        reason,
+    })
+}
+
+/// Copies the body of an extended baseline to `buf` in protocol 15 encoding.
+fn read_baseline(reader: &mut Cursor<&[u8]>, buf: &mut Vec<u8>) -> Result<Vec<String>, NetError> {
+    let bits = reader.read_u8()?;
+    let mut lost = Vec::new();
+
+    let model_id = match bits & B_LARGEMODEL {
+        0 => reader.read_u8()? as u16,
+        _ => reader.read_u16::<LittleEndian>()?,
+    };
+    let frame_id = match bits & B_LARGEFRAME {
+        0 => reader.read_u8()? as u16,
+        _ => reader.read_u16::<LittleEndian>()?,
+    };
+
+    if model_id > u8::MAX as u16 {
+        lost.push(format!("model index {} exceeds 255", model_id));
+        buf.push(0);
+    } else {
+        buf.push(model_id as u8);
+    }
+
+    if frame_id > u8::MAX as u16 {
+        lost.push(format!("frame {} exceeds 255", frame_id));
+        buf.push(0);
+    } else {
+        buf.push(frame_id as u8);
+    }
+
+    // colormap, skin, then origin and angles
+    copy_bytes(reader, buf, 2 + 3 * 3)?;
+
+    if bits & B_ALPHA != 0 {
+        reader.read_u8()?;
+        lost.push("alpha".to_owned());
+    }
+
+    Ok(lost)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn downgrade(data: &[u8]) -> Downgrade {
+        let mut reader = Cursor::new(data);
+        let downgrade = read_downgraded(&mut reader).unwrap().unwrap();
+        assert_eq!(reader.position() as usize, data.len());
+        downgrade
+    }
+
+    #[test]
+    fn test_fitz_update_extended_frame() {
+        // MORE_BITS | FRAME, then EXTEND1 and MODEL, then FRAME2 | LERPFINISH
+        let data = [
+            0x80 | 0x41,
+            0x84,
+            ((U_FRAME2 | U_LERPFINISH) >> 16) as u8,
+            7,  // entity
+            3,  // model
+            44, // frame
+            1,  // frame2
+            0,  // lerpfinish
+        ];
+
+        match downgrade(&data) {
+            Downgrade::Cmd {
+                cmd: ServerCmd::FastUpdate(update),
+                lost,
+            } => {
+                assert_eq!(update.ent_id, 7);
+                assert_eq!(update.model_id, Some(3));
+                assert_eq!(update.frame_id, None);
+                assert_eq!(lost, vec!["frame 300 exceeds 255".to_owned()]);
+            }
+            d => panic!("{:?}", d),
+        }
+    }
+
+    #[test]
+    fn test_fitz_sound_large_index() {
+        let mut data = vec![ServerCmdCode::Sound as u8, SND_LARGEENTITY | SND_LARGESOUND];
+        data.extend_from_slice(&[1, 0, 2, 0x2c, 0x01, 0, 0, 0, 0, 0, 0]);
+
+        match downgrade(&data) {
+            Downgrade::Unrepresentable { name, reason } => {
+                assert_eq!(name, "Sound");
+                assert_eq!(reason, "sound index 300 exceeds 255");
+            }
+            d => panic!("{:?}", d),
+        }
+
+        // small enough to fit after all
+        data[6] = 0;
+        match downgrade(&data) {
+            Downgrade::Cmd {
+                cmd:
+                    ServerCmd::Sound {
+                        entity_id,
+                        channel,
+                        sound_id,
+                        ..
+                    },
+                lost,
+            } => {
+                assert_eq!((entity_id, channel, sound_id), (1, 2, 0x2c));
+                assert!(lost.is_empty());
+            }
+            d => panic!("{:?}", d),
+        }
+    }
+}
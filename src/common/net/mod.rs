@@ -21,6 +21,9 @@
 // TODO: need to figure out an equivalence relation for read_/write_coord and read_/write_angle
 
 pub mod connect;
+pub mod filter;
+pub mod fitz;
+pub mod msglog;
 
 use std::{
     collections::VecDeque,
@@ -30,7 +33,7 @@ use std::{
     net::{SocketAddr, UdpSocket},
 };
 
-use crate::common::{engine, util};
+use crate::common::engine;
 
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3, Zero};
@@ -67,6 +70,7 @@ pub const DEFAULT_VIEWHEIGHT: f32 = 22.0;
 pub enum NetError {
     Io(::std::io::Error),
     InvalidData(String),
+    Resolve(String),
     Other(String),
 }
 
@@ -87,6 +91,7 @@ impl fmt::Display for NetError {
                 err.fmt(f)
             }
             NetError::InvalidData(ref msg) => write!(f, "Invalid data: {}", msg),
+            NetError::Resolve(ref msg) => write!(f, "Couldn't resolve address: {}", msg),
             NetError::Other(ref msg) => write!(f, "{}", msg),
         }
     }
@@ -97,6 +102,7 @@ impl Error for NetError {
         match *self {
             NetError::Io(ref err) => err.description(),
             NetError::InvalidData(_) => "Invalid data",
+            NetError::Resolve(_) => "Couldn't resolve address",
             NetError::Other(ref msg) => &msg,
         }
     }
@@ -446,10 +452,12 @@ impl TempEntity {
                         color_start,
                         color_len,
                     } => {
-                        // write code and colors
+                        // write code, origin and colors
                         writer.write_u8(Code::ColorExplosion as u8)?;
+                        write_coord_vector3(writer, origin)?;
                         writer.write_u8(color_start)?;
                         writer.write_u8(color_len)?;
+                        return Ok(());
                     }
                 };
 
@@ -467,13 +475,17 @@ impl TempEntity {
                         1 => Code::Lightning1,
                         2 => Code::Lightning2,
                         3 => Code::Lightning3,
-                        // TODO: error
-                        _ => panic!("invalid lightning model id: {}", model_id),
+                        _ => {
+                            return Err(NetError::InvalidData(format!(
+                                "Lightning model id {}",
+                                model_id
+                            )))
+                        }
                     },
                     BeamEntityKind::Grapple => Code::Grapple,
                 };
-                writer.write_i16::<LittleEndian>(entity_id)?;
                 writer.write_u8(code as u8)?;
+                writer.write_i16::<LittleEndian>(entity_id)?;
                 write_coord_vector3(writer, start)?;
                 write_coord_vector3(writer, end)?;
             }
@@ -592,6 +604,109 @@ impl EntityUpdate {
             colormap: self.colormap.unwrap_or(baseline.colormap),
         }
     }
+
+    /// Writes this update to the given sink as a fast update command.
+    ///
+    /// Fast updates have no command code; instead, the first byte has the high bit set and the
+    /// remaining bits hold the low byte of the update flags.
+    pub fn write_entity_update<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = UpdateFlags::empty();
+
+        if self.ent_id > ::std::u8::MAX as u16 {
+            flags |= UpdateFlags::LONG_ENTITY;
+        }
+        if self.model_id.is_some() {
+            flags |= UpdateFlags::MODEL;
+        }
+        if self.frame_id.is_some() {
+            flags |= UpdateFlags::FRAME;
+        }
+        if self.colormap.is_some() {
+            flags |= UpdateFlags::COLORMAP;
+        }
+        if self.skin_id.is_some() {
+            flags |= UpdateFlags::SKIN;
+        }
+        if self.effects.is_some() {
+            flags |= UpdateFlags::EFFECTS;
+        }
+        if self.origin_x.is_some() {
+            flags |= UpdateFlags::ORIGIN_X;
+        }
+        if self.pitch.is_some() {
+            flags |= UpdateFlags::PITCH;
+        }
+        if self.origin_y.is_some() {
+            flags |= UpdateFlags::ORIGIN_Y;
+        }
+        if self.yaw.is_some() {
+            flags |= UpdateFlags::YAW;
+        }
+        if self.origin_z.is_some() {
+            flags |= UpdateFlags::ORIGIN_Z;
+        }
+        if self.roll.is_some() {
+            flags |= UpdateFlags::ROLL;
+        }
+        if self.no_lerp {
+            flags |= UpdateFlags::NO_LERP;
+        }
+
+        // any flags in the high byte need to be announced in the low byte
+        if flags.bits() > ::std::u8::MAX as u16 {
+            flags |= UpdateFlags::MORE_BITS;
+        }
+
+        writer.write_u8(flags.bits() as u8 | FAST_UPDATE_FLAG)?;
+        if flags.contains(UpdateFlags::MORE_BITS) {
+            writer.write_u8((flags.bits() >> 8) as u8)?;
+        }
+
+        if flags.contains(UpdateFlags::LONG_ENTITY) {
+            writer.write_u16::<LittleEndian>(self.ent_id)?;
+        } else {
+            writer.write_u8(self.ent_id as u8)?;
+        }
+
+        if let Some(m) = self.model_id {
+            writer.write_u8(m)?;
+        }
+        if let Some(f) = self.frame_id {
+            writer.write_u8(f)?;
+        }
+        if let Some(c) = self.colormap {
+            writer.write_u8(c)?;
+        }
+        if let Some(s) = self.skin_id {
+            writer.write_u8(s)?;
+        }
+        if let Some(e) = self.effects {
+            writer.write_u8(e.bits())?;
+        }
+        if let Some(x) = self.origin_x {
+            write_coord(writer, x)?;
+        }
+        if let Some(p) = self.pitch {
+            write_angle(writer, p)?;
+        }
+        if let Some(y) = self.origin_y {
+            write_coord(writer, y)?;
+        }
+        if let Some(y) = self.yaw {
+            write_angle(writer, y)?;
+        }
+        if let Some(z) = self.origin_z {
+            write_coord(writer, z)?;
+        }
+        if let Some(r) = self.roll {
+            write_angle(writer, r)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// A trait for in-game server and client network commands.
@@ -1033,8 +1148,9 @@ impl ServerCmd {
                     false => None,
                 };
 
-                let entity_channel = reader.read_i16::<LittleEndian>()?;
-                let entity_id = (entity_channel >> 3) as u16;
+                // the entity takes the top 13 bits, so shift unsigned to keep the sign bit out of it
+                let entity_channel = reader.read_u16::<LittleEndian>()?;
+                let entity_id = entity_channel >> 3;
                 let channel = (entity_channel & 0b111) as i8;
                 let sound_id = reader.read_u8()?;
                 let position = Vector3::new(
@@ -1059,19 +1175,13 @@ impl ServerCmd {
             }
 
             ServerCmdCode::Print => {
-                let text = match util::read_cstring(reader) {
-                    Ok(t) => t,
-                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
-                };
+                let text = read_cstring(reader)?;
 
                 ServerCmd::Print { text }
             }
 
             ServerCmdCode::StuffText => {
-                let text = match util::read_cstring(reader) {
-                    Ok(t) => t,
-                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
-                };
+                let text = read_cstring(reader)?;
 
                 ServerCmd::StuffText { text }
             }
@@ -1100,11 +1210,11 @@ impl ServerCmd {
                     }
                 };
 
-                let message = util::read_cstring(reader).unwrap();
+                let message = read_cstring(reader)?;
 
                 let mut model_precache = Vec::new();
                 loop {
-                    let model_name = util::read_cstring(reader).unwrap();
+                    let model_name = read_cstring(reader)?;
                     if model_name.is_empty() {
                         break;
                     }
@@ -1113,7 +1223,7 @@ impl ServerCmd {
 
                 let mut sound_precache = Vec::new();
                 loop {
-                    let sound_name = util::read_cstring(reader).unwrap();
+                    let sound_name = read_cstring(reader)?;
                     if sound_name.is_empty() {
                         break;
                     }
@@ -1132,13 +1242,13 @@ impl ServerCmd {
 
             ServerCmdCode::LightStyle => {
                 let id = reader.read_u8()?;
-                let value = util::read_cstring(reader).unwrap();
+                let value = read_cstring(reader)?;
                 ServerCmd::LightStyle { id, value }
             }
 
             ServerCmdCode::UpdateName => {
                 let player_id = reader.read_u8()?;
-                let new_name = util::read_cstring(reader).unwrap();
+                let new_name = read_cstring(reader)?;
                 ServerCmd::UpdateName {
                     player_id,
                     new_name,
@@ -1399,10 +1509,7 @@ impl ServerCmd {
             }
 
             ServerCmdCode::CenterPrint => {
-                let text = match util::read_cstring(reader) {
-                    Ok(t) => t,
-                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
-                };
+                let text = read_cstring(reader)?;
 
                 ServerCmd::CenterPrint { text }
             }
@@ -1427,10 +1534,7 @@ impl ServerCmd {
             ServerCmdCode::Intermission => ServerCmd::Intermission,
 
             ServerCmdCode::Finale => {
-                let text = match util::read_cstring(reader) {
-                    Ok(t) => t,
-                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
-                };
+                let text = read_cstring(reader)?;
 
                 ServerCmd::Finale { text }
             }
@@ -1444,10 +1548,7 @@ impl ServerCmd {
             ServerCmdCode::SellScreen => ServerCmd::SellScreen,
 
             ServerCmdCode::Cutscene => {
-                let text = match util::read_cstring(reader) {
-                    Ok(t) => t,
-                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
-                };
+                let text = read_cstring(reader)?;
 
                 ServerCmd::Cutscene { text }
             }
@@ -1460,6 +1561,11 @@ impl ServerCmd {
     where
         W: WriteBytesExt,
     {
+        // fast updates carry their flags in place of a command code
+        if let ServerCmd::FastUpdate(ref ent_update) = *self {
+            return ent_update.write_entity_update(writer);
+        }
+
         writer.write_u8(self.code())?;
 
         match *self {
@@ -1503,12 +1609,12 @@ impl ServerCmd {
                 }
 
                 if let Some(a) = attenuation {
-                    writer.write_u8(a as u8 * SOUND_ATTENUATION_WRITE_FACTOR)?;
+                    writer.write_u8((a * SOUND_ATTENUATION_WRITE_FACTOR as f32) as u8)?;
                 }
 
-                // TODO: document this better. The entity and channel fields are combined in Sound commands.
-                let ent_channel = (entity_id as i16) << 3 | channel as i16 & 0b111;
-                writer.write_i16::<LittleEndian>(ent_channel)?;
+                // the entity ID takes the top 13 bits of the field and the channel the bottom 3
+                let ent_channel = entity_id << 3 | channel as u16 & 0b111;
+                writer.write_u16::<LittleEndian>(ent_channel)?;
 
                 writer.write_u8(sound_id)?;
 
@@ -1832,8 +1938,8 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            // TODO
-            ServerCmd::FastUpdate(_) => unimplemented!(),
+            // handled above
+            ServerCmd::FastUpdate(_) => unreachable!(),
         }
 
         Ok(())
@@ -1899,7 +2005,14 @@ impl ClientCmd {
             ClientCmdCode::NoOp => ClientCmd::NoOp,
             ClientCmdCode::Disconnect => ClientCmd::Disconnect,
             ClientCmdCode::Move => {
-                let send_time = engine::duration_from_f32(reader.read_f32::<LittleEndian>()?);
+                let send_time_f32 = reader.read_f32::<LittleEndian>()?;
+                if !send_time_f32.is_finite() {
+                    return Err(NetError::InvalidData(format!(
+                        "Invalid move send time: {}",
+                        send_time_f32
+                    )));
+                }
+                let send_time = engine::duration_from_f32(send_time_f32);
                 let angles = Vector3::new(
                     read_angle(reader)?,
                     read_angle(reader)?,
@@ -1930,7 +2043,7 @@ impl ClientCmd {
                 }
             }
             ClientCmdCode::StringCmd => {
-                let cmd = util::read_cstring(reader).unwrap();
+                let cmd = read_cstring(reader)?;
                 ClientCmd::StringCmd { cmd }
             }
         };
@@ -2254,7 +2367,7 @@ impl QSocket {
                 }
 
                 MsgKind::Ack => {
-                    if sequence != self.send_sequence - 1 {
+                    if sequence != self.send_sequence.wrapping_sub(1) {
                         println!("Stale ACK received");
                     } else if sequence != self.ack_sequence {
                         println!("Duplicate ACK received");
@@ -2311,6 +2424,23 @@ impl QSocket {
     }
 }
 
+/// Read a null-terminated string.
+///
+/// Unlike [`util::read_cstring`](crate::common::util::read_cstring), this never panics: a missing terminator simply ends the string
+/// at the end of the input, and invalid UTF-8 is reported as `NetError::InvalidData`.
+fn read_cstring<R>(reader: &mut R) -> Result<String, NetError>
+where
+    R: BufRead,
+{
+    let mut bytes = Vec::new();
+    reader.read_until(0, &mut bytes)?;
+    if bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    String::from_utf8(bytes).map_err(|e| NetError::InvalidData(format!("string: {}", e)))
+}
+
 fn read_coord<R>(reader: &mut R) -> Result<f32, NetError>
 where
     R: BufRead + ReadBytesExt,
@@ -2370,7 +2500,7 @@ fn write_angle<W>(writer: &mut W, angle: Deg<f32>) -> Result<(), NetError>
 where
     W: WriteBytesExt,
 {
-    writer.write_u8(((angle.0 as i32 * 256 / 360) & 0xFF) as u8)?;
+    writer.write_u8(((angle.0 * 256.0 / 360.0).round() as i32 & 0xFF) as u8)?;
     Ok(())
 }
 
@@ -2391,6 +2521,15 @@ mod test {
 
     use std::io::BufReader;
 
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    // number of commands to generate for each randomized round-trip test
+    const ROUND_TRIP_ITERATIONS: usize = 4096;
+
+    // number of byte strings to feed to the parsers in each fuzz test
+    const FUZZ_ITERATIONS: usize = 16384;
+    const FUZZ_MAX_LEN: usize = 64;
+
     #[test]
     fn test_server_cmd_update_stat_read_write_eq() {
         let src = ServerCmd::UpdateStat {
@@ -2534,6 +2673,28 @@ mod test {
         assert_eq!(src, dst);
     }
 
+    #[test]
+    fn test_server_cmd_sound_high_entity_read_write_eq() {
+        // entity IDs of 4096 and up set the top bit of the combined field
+        for entity_id in &[4096, 8191] {
+            let src = ServerCmd::Sound {
+                volume: None,
+                attenuation: None,
+                entity_id: *entity_id,
+                channel: 5,
+                sound_id: 1,
+                position: Vector3::new(0.0, 0.0, 0.0),
+            };
+
+            let mut packet = Vec::new();
+            src.serialize(&mut packet).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+            assert_eq!(src, dst);
+        }
+    }
+
     #[test]
     fn test_server_cmd_stop_sound_read_write_eq() {
         let src = ServerCmd::StopSound {
@@ -2672,6 +2833,410 @@ mod test {
         assert_eq!(src, dst);
     }
 
+    // The generators below only produce values that survive the wire format's quantization, so
+    // that deserialize(serialize(x)) == x holds exactly.
+
+    fn gen_string(rng: &mut SmallRng, min_len: usize) -> String {
+        let len = rng.gen_range(min_len, 32);
+        (0..len)
+            .map(|_| rng.gen_range(b' ', b'~' + 1) as char)
+            .collect()
+    }
+
+    fn gen_coord(rng: &mut SmallRng) -> f32 {
+        rng.gen::<i16>() as f32 / 8.0
+    }
+
+    fn gen_coord_vector3(rng: &mut SmallRng) -> Vector3<f32> {
+        Vector3::new(gen_coord(rng), gen_coord(rng), gen_coord(rng))
+    }
+
+    fn gen_angle(rng: &mut SmallRng) -> Deg<f32> {
+        Deg(rng.gen::<i8>() as f32 * (360.0 / 256.0))
+    }
+
+    fn gen_angle_vector3(rng: &mut SmallRng) -> Vector3<Deg<f32>> {
+        Vector3::new(gen_angle(rng), gen_angle(rng), gen_angle(rng))
+    }
+
+    fn gen_option<T, F>(rng: &mut SmallRng, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut SmallRng) -> T,
+    {
+        if rng.gen() {
+            Some(f(rng))
+        } else {
+            None
+        }
+    }
+
+    fn gen_temp_entity(rng: &mut SmallRng) -> TempEntity {
+        match rng.gen_range(0, 12) {
+            0..=9 => TempEntity::Point {
+                kind: match rng.gen_range(0, 10) {
+                    0 => PointEntityKind::Spike,
+                    1 => PointEntityKind::SuperSpike,
+                    2 => PointEntityKind::Gunshot,
+                    3 => PointEntityKind::Explosion,
+                    4 => PointEntityKind::ColorExplosion {
+                        color_start: rng.gen(),
+                        color_len: rng.gen(),
+                    },
+                    5 => PointEntityKind::TarExplosion,
+                    6 => PointEntityKind::WizSpike,
+                    7 => PointEntityKind::KnightSpike,
+                    8 => PointEntityKind::LavaSplash,
+                    _ => PointEntityKind::Teleport,
+                },
+                origin: gen_coord_vector3(rng),
+            },
+            _ => TempEntity::Beam {
+                kind: match rng.gen() {
+                    true => BeamEntityKind::Lightning {
+                        model_id: rng.gen_range(1, 4),
+                    },
+                    false => BeamEntityKind::Grapple,
+                },
+                entity_id: rng.gen(),
+                start: gen_coord_vector3(rng),
+                end: gen_coord_vector3(rng),
+            },
+        }
+    }
+
+    fn gen_entity_update(rng: &mut SmallRng) -> EntityUpdate {
+        EntityUpdate {
+            ent_id: rng.gen(),
+            model_id: gen_option(rng, |r| r.gen()),
+            frame_id: gen_option(rng, |r| r.gen()),
+            colormap: gen_option(rng, |r| r.gen()),
+            skin_id: gen_option(rng, |r| r.gen()),
+            effects: gen_option(rng, |r| EntityEffects::from_bits_truncate(r.gen())),
+            origin_x: gen_option(rng, gen_coord),
+            pitch: gen_option(rng, gen_angle),
+            origin_y: gen_option(rng, gen_coord),
+            yaw: gen_option(rng, gen_angle),
+            origin_z: gen_option(rng, gen_coord),
+            roll: gen_option(rng, gen_angle),
+            no_lerp: rng.gen(),
+        }
+    }
+
+    fn gen_player_data(rng: &mut SmallRng) -> PlayerData {
+        PlayerData {
+            view_height: gen_option(rng, |r| r.gen::<i8>() as f32),
+            ideal_pitch: gen_option(rng, |r| Deg(r.gen::<i8>() as f32)),
+            punch_pitch: gen_option(rng, |r| Deg(r.gen::<i8>() as f32)),
+            velocity_x: gen_option(rng, |r| r.gen::<i8>() as f32 * VELOCITY_READ_FACTOR),
+            punch_yaw: gen_option(rng, |r| Deg(r.gen::<i8>() as f32)),
+            velocity_y: gen_option(rng, |r| r.gen::<i8>() as f32 * VELOCITY_READ_FACTOR),
+            punch_roll: gen_option(rng, |r| Deg(r.gen::<i8>() as f32)),
+            velocity_z: gen_option(rng, |r| r.gen::<i8>() as f32 * VELOCITY_READ_FACTOR),
+            items: ItemFlags::from_bits_truncate(rng.gen()),
+            on_ground: rng.gen(),
+            in_water: rng.gen(),
+            weapon_frame: gen_option(rng, |r| r.gen()),
+            armor: gen_option(rng, |r| r.gen()),
+            weapon: gen_option(rng, |r| r.gen()),
+            health: rng.gen(),
+            ammo: rng.gen(),
+            ammo_shells: rng.gen(),
+            ammo_nails: rng.gen(),
+            ammo_rockets: rng.gen(),
+            ammo_cells: rng.gen(),
+            active_weapon: rng.gen(),
+        }
+    }
+
+    fn gen_server_cmd(rng: &mut SmallRng) -> ServerCmd {
+        match rng.gen_range(0, 35) {
+            0 => ServerCmd::Bad,
+            1 => ServerCmd::NoOp,
+            2 => ServerCmd::Disconnect,
+            3 => ServerCmd::UpdateStat {
+                stat: ClientStat::from_u8(rng.gen_range(0, 15)).unwrap(),
+                value: rng.gen(),
+            },
+            4 => ServerCmd::Version { version: rng.gen() },
+            5 => ServerCmd::SetView { ent_id: rng.gen() },
+            6 => ServerCmd::Sound {
+                volume: gen_option(rng, |r| r.gen()),
+                attenuation: gen_option(rng, |r| {
+                    r.gen::<u8>() as f32 * SOUND_ATTENUATION_READ_FACTOR
+                }),
+                // entity and channel share 16 bits, leaving 13 for the entity
+                entity_id: rng.gen_range(0, 1 << 13),
+                channel: rng.gen_range(0, 8),
+                sound_id: rng.gen(),
+                position: gen_coord_vector3(rng),
+            },
+            7 => ServerCmd::Time {
+                time: rng.gen::<f32>() * 10000.0,
+            },
+            8 => ServerCmd::Print {
+                text: gen_string(rng, 0),
+            },
+            9 => ServerCmd::StuffText {
+                text: gen_string(rng, 0),
+            },
+            10 => ServerCmd::SetAngle {
+                angles: gen_angle_vector3(rng),
+            },
+            11 => ServerCmd::ServerInfo {
+                protocol_version: rng.gen(),
+                max_clients: rng.gen(),
+                game_type: GameType::from_u8(rng.gen_range(0, 2)).unwrap(),
+                message: gen_string(rng, 0),
+                // an empty name terminates the precache list
+                model_precache: (0..rng.gen_range(0, 8))
+                    .map(|_| gen_string(rng, 1))
+                    .collect(),
+                sound_precache: (0..rng.gen_range(0, 8))
+                    .map(|_| gen_string(rng, 1))
+                    .collect(),
+            },
+            12 => ServerCmd::LightStyle {
+                id: rng.gen(),
+                value: gen_string(rng, 0),
+            },
+            13 => ServerCmd::UpdateName {
+                player_id: rng.gen(),
+                new_name: gen_string(rng, 0),
+            },
+            14 => ServerCmd::UpdateFrags {
+                player_id: rng.gen(),
+                new_frags: rng.gen(),
+            },
+            15 => ServerCmd::PlayerData(gen_player_data(rng)),
+            16 => ServerCmd::StopSound {
+                // entity and channel share a u16, leaving 13 bits for the entity
+                entity_id: rng.gen_range(0, 1 << 13),
+                channel: rng.gen_range(0, 8),
+            },
+            17 => ServerCmd::UpdateColors {
+                player_id: rng.gen(),
+                new_colors: PlayerColor::new(rng.gen_range(0, 16), rng.gen_range(0, 16)),
+            },
+            18 => ServerCmd::Particle {
+                origin: gen_coord_vector3(rng),
+                direction: Vector3::new(
+                    rng.gen::<i8>() as f32 * PARTICLE_DIRECTION_READ_FACTOR,
+                    rng.gen::<i8>() as f32 * PARTICLE_DIRECTION_READ_FACTOR,
+                    rng.gen::<i8>() as f32 * PARTICLE_DIRECTION_READ_FACTOR,
+                ),
+                count: rng.gen(),
+                color: rng.gen(),
+            },
+            19 => ServerCmd::Damage {
+                armor: rng.gen(),
+                blood: rng.gen(),
+                source: gen_coord_vector3(rng),
+            },
+            20 => ServerCmd::SpawnStatic {
+                model_id: rng.gen(),
+                frame_id: rng.gen(),
+                colormap: rng.gen(),
+                skin_id: rng.gen(),
+                origin: gen_coord_vector3(rng),
+                angles: gen_angle_vector3(rng),
+            },
+            21 => ServerCmd::SpawnBaseline {
+                ent_id: rng.gen(),
+                model_id: rng.gen(),
+                frame_id: rng.gen(),
+                colormap: rng.gen(),
+                skin_id: rng.gen(),
+                origin: gen_coord_vector3(rng),
+                angles: gen_angle_vector3(rng),
+            },
+            22 => ServerCmd::TempEntity {
+                temp_entity: gen_temp_entity(rng),
+            },
+            23 => ServerCmd::SetPause { paused: rng.gen() },
+            24 => ServerCmd::SignOnStage {
+                stage: SignOnStage::from_u8(rng.gen_range(0, 5)).unwrap(),
+            },
+            25 => ServerCmd::CenterPrint {
+                text: gen_string(rng, 0),
+            },
+            26 => ServerCmd::KilledMonster,
+            27 => ServerCmd::FoundSecret,
+            28 => ServerCmd::SpawnStaticSound {
+                origin: gen_coord_vector3(rng),
+                sound_id: rng.gen(),
+                volume: rng.gen(),
+                attenuation: rng.gen(),
+            },
+            29 => ServerCmd::Intermission,
+            30 => ServerCmd::Finale {
+                text: gen_string(rng, 0),
+            },
+            31 => ServerCmd::CdTrack {
+                track: rng.gen(),
+                loop_: rng.gen(),
+            },
+            32 => ServerCmd::SellScreen,
+            33 => ServerCmd::Cutscene {
+                text: gen_string(rng, 0),
+            },
+            _ => ServerCmd::FastUpdate(gen_entity_update(rng)),
+        }
+    }
+
+    fn gen_client_cmd(rng: &mut SmallRng) -> ClientCmd {
+        match rng.gen_range(0, 5) {
+            0 => ClientCmd::Bad,
+            1 => ClientCmd::NoOp,
+            2 => ClientCmd::Disconnect,
+            3 => ClientCmd::Move {
+                // multiples of 1/64 second are exactly representable as f32 seconds
+                send_time: Duration::microseconds(rng.gen_range(0, 1024) * 15625),
+                angles: gen_angle_vector3(rng),
+                fwd_move: rng.gen(),
+                side_move: rng.gen(),
+                up_move: rng.gen(),
+                button_flags: ButtonFlags::from_bits_truncate(rng.gen()),
+                impulse: rng.gen(),
+            },
+            _ => ClientCmd::StringCmd {
+                cmd: gen_string(rng, 0),
+            },
+        }
+    }
+
+    // Generates a byte string that is likely to get past the command code check.
+    fn gen_fuzz_bytes(rng: &mut SmallRng) -> Vec<u8> {
+        let len = rng.gen_range(0, FUZZ_MAX_LEN);
+        let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        if let Some(b) = bytes.first_mut() {
+            if rng.gen() {
+                *b = rng.gen_range(0, ServerCmdCode::Cutscene as u8 + 1);
+            }
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn test_server_cmd_random_read_write_eq() {
+        let mut rng = SmallRng::seed_from_u64(0x5E4F_E4C0);
+
+        for _ in 0..ROUND_TRIP_ITERATIONS {
+            let src = gen_server_cmd(&mut rng);
+
+            let mut packet = Vec::new();
+            src.serialize(&mut packet).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+            assert_eq!(src, dst);
+
+            // the command must consume exactly the bytes it wrote
+            assert!(ServerCmd::deserialize(&mut reader).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_server_cmd_random_sequence_read_write_eq() {
+        let mut rng = SmallRng::seed_from_u64(0x5E0_0E4C);
+
+        for _ in 0..ROUND_TRIP_ITERATIONS / 16 {
+            let src: Vec<ServerCmd> = (0..16).map(|_| gen_server_cmd(&mut rng)).collect();
+
+            let mut packet = Vec::new();
+            for cmd in src.iter() {
+                cmd.serialize(&mut packet).unwrap();
+            }
+
+            let mut reader = BufReader::new(packet.as_slice());
+            let mut dst = Vec::new();
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+                dst.push(cmd);
+            }
+
+            assert_eq!(src, dst);
+        }
+    }
+
+    #[test]
+    fn test_client_cmd_random_read_write_eq() {
+        let mut rng = SmallRng::seed_from_u64(0xC11E_4C0);
+
+        for _ in 0..ROUND_TRIP_ITERATIONS {
+            let src = gen_client_cmd(&mut rng);
+
+            let mut packet = Vec::new();
+            src.serialize(&mut packet).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = ClientCmd::deserialize(&mut reader).unwrap();
+
+            assert_eq!(src, dst);
+        }
+    }
+
+    #[test]
+    fn test_server_cmd_fuzz_no_panic() {
+        let mut rng = SmallRng::seed_from_u64(0xF022_5E4F);
+
+        for _ in 0..FUZZ_ITERATIONS {
+            let bytes = gen_fuzz_bytes(&mut rng);
+            let mut reader = BufReader::new(bytes.as_slice());
+
+            // parse until the input is exhausted or rejected
+            while let Ok(Some(_)) = ServerCmd::deserialize(&mut reader) {}
+        }
+    }
+
+    #[test]
+    fn test_server_cmd_mutated_no_panic() {
+        let mut rng = SmallRng::seed_from_u64(0x3A7E_5E4F);
+
+        for _ in 0..FUZZ_ITERATIONS {
+            let mut packet = Vec::new();
+            gen_server_cmd(&mut rng).serialize(&mut packet).unwrap();
+
+            // corrupt a few bytes and possibly truncate the message
+            for _ in 0..rng.gen_range(1, 4) {
+                let i = rng.gen_range(0, packet.len());
+                packet[i] = rng.gen();
+            }
+            let len = rng.gen_range(0, packet.len() + 1);
+            packet.truncate(len);
+
+            let mut reader = BufReader::new(packet.as_slice());
+            while let Ok(Some(_)) = ServerCmd::deserialize(&mut reader) {}
+        }
+    }
+
+    #[test]
+    fn test_client_cmd_fuzz_no_panic() {
+        let mut rng = SmallRng::seed_from_u64(0xF022_C11E);
+
+        for _ in 0..FUZZ_ITERATIONS {
+            let len = rng.gen_range(0, FUZZ_MAX_LEN);
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            if let Some(b) = bytes.first_mut() {
+                *b = rng.gen_range(0, ClientCmdCode::StringCmd as u8 + 1);
+            }
+
+            let mut reader = BufReader::new(bytes.as_slice());
+            let _ = ClientCmd::deserialize(&mut reader);
+        }
+    }
+
+    #[test]
+    fn test_temp_entity_invalid_lightning_model_fails() {
+        let src = TempEntity::Beam {
+            kind: BeamEntityKind::Lightning { model_id: 4 },
+            entity_id: 1,
+            start: Vector3::zero(),
+            end: Vector3::zero(),
+        };
+
+        let mut packet = Vec::new();
+        assert!(src.write_temp_entity(&mut packet).is_err());
+    }
+
     fn gen_qsocket_pair() -> (QSocket, QSocket) {
         let src_udp = UdpSocket::bind("localhost:0").unwrap();
         let src_addr = src_udp.local_addr().unwrap();
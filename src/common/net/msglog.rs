@@ -0,0 +1,421 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Human-readable logs of protocol traffic.
+//!
+//! A message log records every command a client receives from or sends to a server. Each command
+//! occupies a single line of the form
+//!
+//! ```text
+//! <seconds> <direction> <command> <raw bytes> <decoded command>
+//! ```
+//!
+//! where `<seconds>` is the wall-clock time elapsed since the log was opened, `<direction>` is
+//! either `recv` or `send`, and `<raw bytes>` is the command's wire representation in hexadecimal.
+//! Lines beginning with `#` are comments.
+//!
+//! If a received message can't be decoded, the rest of it is logged under the command name
+//! [`DECODE_ERROR`] with the error in place of the decoded command.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufWriter, Lines, Write},
+    path::Path,
+    str::FromStr,
+    time::Instant,
+};
+
+use crate::common::net::{ClientCmd, NetError, ServerCmd};
+
+use chrono::{Duration, Utc};
+
+/// The direction in which a logged command was travelling.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// A command received from the server.
+    Recv,
+
+    /// A command sent to the server.
+    Send,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Direction::Recv => "recv",
+            Direction::Send => "send",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recv" => Ok(Direction::Recv),
+            "send" => Ok(Direction::Send),
+            _ => Err(NetError::InvalidData(format!("message direction: {}", s))),
+        }
+    }
+}
+
+/// The command name logged for bytes that couldn't be decoded.
+pub const DECODE_ERROR: &str = "!error";
+
+/// Extracts the variant name from the debug representation of a command.
+pub fn cmd_name(decoded: &str) -> &str {
+    decoded
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or("")
+}
+
+/// Writes commands to a message log.
+pub struct MessageLog<W>
+where
+    W: Write,
+{
+    writer: W,
+    start: Instant,
+}
+
+impl MessageLog<BufWriter<File>> {
+    /// Creates a new message log at the specified path, truncating any existing file.
+    pub fn create<P>(path: P) -> Result<MessageLog<BufWriter<File>>, NetError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        MessageLog::new(BufWriter::new(file))
+    }
+}
+
+impl<W> MessageLog<W>
+where
+    W: Write,
+{
+    /// Creates a new message log which writes to the given sink.
+    pub fn new(mut writer: W) -> Result<MessageLog<W>, NetError> {
+        writeln!(writer, "# message log started {}", Utc::now().to_rfc3339())?;
+        writeln!(writer, "# time direction command raw decoded")?;
+
+        Ok(MessageLog {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    /// Records a command received from the server along with its wire representation.
+    pub fn log_server_cmd(&mut self, cmd: &ServerCmd, raw: &[u8]) -> Result<(), NetError> {
+        let decoded = format!("{:?}", cmd);
+        self.log(Direction::Recv, cmd_name(&decoded), &decoded, raw)
+    }
+
+    /// Records bytes received from the server that failed to decode, along with the error.
+    ///
+    /// The log is flushed immediately, since the error will usually end the connection.
+    pub fn log_server_error(&mut self, raw: &[u8], error: &NetError) -> Result<(), NetError> {
+        self.log(Direction::Recv, DECODE_ERROR, &error.to_string(), raw)?;
+        self.flush()
+    }
+
+    /// Records a command sent to the server along with its wire representation.
+    pub fn log_client_cmd(&mut self, cmd: &ClientCmd, raw: &[u8]) -> Result<(), NetError> {
+        let decoded = format!("{:?}", cmd);
+        self.log(Direction::Send, cmd_name(&decoded), &decoded, raw)
+    }
+
+    fn log(
+        &mut self,
+        direction: Direction,
+        name: &str,
+        decoded: &str,
+        raw: &[u8],
+    ) -> Result<(), NetError> {
+        let elapsed = self.start.elapsed();
+        let hex: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+
+        writeln!(
+            self.writer,
+            "{}.{:06} {} {} {} {}",
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            direction.as_str(),
+            name,
+            hex,
+            decoded,
+        )?;
+
+        Ok(())
+    }
+
+    /// Flushes any buffered entries to the underlying sink.
+    pub fn flush(&mut self) -> Result<(), NetError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Consumes the log, returning the underlying sink.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// A single command read back from a message log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageLogEntry {
+    /// Time elapsed between the opening of the log and this command.
+    pub time: Duration,
+
+    /// Whether this command was received or sent.
+    pub direction: Direction,
+
+    /// The name of the command, e.g. `Print` or `FastUpdate`.
+    pub name: String,
+
+    /// The wire representation of the command.
+    pub raw: Vec<u8>,
+
+    /// The debug representation of the command at the time it was logged.
+    pub decoded: String,
+}
+
+impl MessageLogEntry {
+    /// Parses a line of a message log.
+    ///
+    /// Returns `None` if the line is blank or a comment.
+    pub fn parse(line: &str) -> Result<Option<MessageLogEntry>, NetError> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let mut fields = line.splitn(5, ' ');
+        let mut next_field = |name| {
+            fields
+                .next()
+                .ok_or_else(|| NetError::InvalidData(format!("message log entry missing {}", name)))
+        };
+
+        let time_str = next_field("time")?;
+        let direction = next_field("direction")?.parse()?;
+        let name = next_field("command name")?.to_owned();
+        let hex = next_field("raw bytes")?;
+        let decoded = next_field("decoded command")?.to_owned();
+
+        let time = {
+            let (secs, micros) = match time_str.find('.') {
+                Some(i) => (&time_str[..i], &time_str[i + 1..]),
+                None => (time_str, "0"),
+            };
+
+            let parse = |s: &str| {
+                s.parse::<i64>()
+                    .map_err(|_| NetError::InvalidData(format!("message log time: {}", time_str)))
+            };
+
+            // right-pad the fractional part so that e.g. "0.5" is half a second
+            let micros = format!("{:0<6}", micros);
+            let micros = micros.get(..6).unwrap_or(&micros);
+
+            Duration::seconds(parse(secs)?) + Duration::microseconds(parse(micros)?)
+        };
+
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(NetError::InvalidData(format!("message log bytes: {}", hex)));
+        }
+
+        let mut raw = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            raw.push(
+                u8::from_str_radix(&hex[i..i + 2], 16)
+                    .map_err(|_| NetError::InvalidData(format!("message log bytes: {}", hex)))?,
+            );
+        }
+
+        Ok(Some(MessageLogEntry {
+            time,
+            direction,
+            name,
+            raw,
+            decoded,
+        }))
+    }
+
+    /// Decodes the raw bytes of this entry as a server command.
+    pub fn server_cmd(&self) -> Result<Option<ServerCmd>, NetError> {
+        ServerCmd::deserialize(&mut self.raw.as_slice())
+    }
+
+    /// Decodes the raw bytes of this entry as a client command.
+    pub fn client_cmd(&self) -> Result<ClientCmd, NetError> {
+        ClientCmd::deserialize(&mut self.raw.as_slice())
+    }
+}
+
+/// Reads entries from a message log.
+pub struct MessageLogReader<R>
+where
+    R: BufRead,
+{
+    lines: Lines<R>,
+}
+
+impl<R> MessageLogReader<R>
+where
+    R: BufRead,
+{
+    pub fn new(reader: R) -> MessageLogReader<R> {
+        MessageLogReader {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R> Iterator for MessageLogReader<R>
+where
+    R: BufRead,
+{
+    type Item = Result<MessageLogEntry, NetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(l) => l,
+                Err(e) => return Some(Err(NetError::from(e))),
+            };
+
+            match MessageLogEntry::parse(&line) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{Deg, Vector3};
+
+    use crate::common::net::ButtonFlags;
+
+    #[test]
+    fn test_message_log_read_write_eq() {
+        let server_cmds = vec![
+            ServerCmd::Print {
+                text: String::from("message log test"),
+            },
+            ServerCmd::Time { time: 1.5 },
+            ServerCmd::NoOp,
+        ];
+        let client_cmd = ClientCmd::Move {
+            send_time: Duration::milliseconds(1500),
+            angles: Vector3::new(Deg(90.0), Deg(-90.0), Deg(0.0)),
+            fwd_move: 200,
+            side_move: 0,
+            up_move: 0,
+            button_flags: ButtonFlags::ATTACK,
+            impulse: 0,
+        };
+
+        let mut log = MessageLog::new(Vec::new()).unwrap();
+        for cmd in server_cmds.iter() {
+            let mut raw = Vec::new();
+            cmd.serialize(&mut raw).unwrap();
+            log.log_server_cmd(cmd, &raw).unwrap();
+        }
+        let mut raw = Vec::new();
+        client_cmd.serialize(&mut raw).unwrap();
+        log.log_client_cmd(&client_cmd, &raw).unwrap();
+
+        let text = log.into_inner();
+        let entries: Vec<MessageLogEntry> = MessageLogReader::new(text.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), server_cmds.len() + 1);
+
+        for (entry, cmd) in entries.iter().zip(server_cmds.iter()) {
+            assert_eq!(entry.direction, Direction::Recv);
+            assert_eq!(entry.decoded, format!("{:?}", cmd));
+            assert_eq!(entry.server_cmd().unwrap().as_ref(), Some(cmd));
+        }
+
+        let last = entries.last().unwrap();
+        assert_eq!(last.direction, Direction::Send);
+        assert_eq!(last.name, "Move");
+        assert_eq!(last.client_cmd().unwrap(), client_cmd);
+    }
+
+    #[test]
+    fn test_message_log_server_error() {
+        let mut log = MessageLog::new(Vec::new()).unwrap();
+        let raw = [0x07, 0x00, 0x00];
+        let error = ServerCmd::deserialize(&mut &raw[..]).unwrap_err();
+        log.log_server_error(&raw, &error).unwrap();
+
+        let text = log.into_inner();
+        let entries: Vec<MessageLogEntry> = MessageLogReader::new(text.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].direction, Direction::Recv);
+        assert_eq!(entries[0].name, DECODE_ERROR);
+        assert_eq!(entries[0].raw, raw);
+        assert_eq!(entries[0].decoded, error.to_string());
+    }
+
+    #[test]
+    fn test_message_log_entry_parse() {
+        let entry = MessageLogEntry::parse("12.000250 recv Time 070000c03f Time { time: 1.5 }")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            entry.time,
+            Duration::seconds(12) + Duration::microseconds(250)
+        );
+        assert_eq!(entry.name, "Time");
+        assert_eq!(entry.raw, vec![0x07, 0x00, 0x00, 0xc0, 0x3f]);
+        assert_eq!(
+            entry.server_cmd().unwrap(),
+            Some(ServerCmd::Time { time: 1.5 })
+        );
+    }
+
+    #[test]
+    fn test_message_log_entry_parse_short_fraction() {
+        let entry = MessageLogEntry::parse("0.5 recv NoOp 01 NoOp")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.time, Duration::milliseconds(500));
+    }
+
+    #[test]
+    fn test_message_log_entry_parse_comment() {
+        assert!(MessageLogEntry::parse("# comment").unwrap().is_none());
+        assert!(MessageLogEntry::parse("").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_message_log_entry_parse_bad_hex_fails() {
+        assert!(MessageLogEntry::parse("0.0 recv NoOp 0 NoOp").is_err());
+    }
+}
@@ -81,3 +81,71 @@ where
         size_of::<T>() / size_of::<u32>(),
     )
 }
+
+/// A scratch directory for tests, removed along with its contents when dropped.
+#[cfg(test)]
+pub struct TempDir {
+    path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+    /// Creates an empty directory under the system temporary directory.
+    ///
+    /// `name` only makes the directory easier to identify; each call gets a distinct path.
+    pub fn new(name: &str) -> TempDir {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "richter-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Returns the path of `rel_path` within the directory, creating its parent directories.
+    pub fn file<P>(&self, rel_path: P) -> std::path::PathBuf
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = self.path.join(rel_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        path
+    }
+
+    /// Writes `data` to `rel_path` within the directory, creating its parent directories.
+    pub fn write<P, D>(&self, rel_path: P, data: D) -> std::path::PathBuf
+    where
+        P: AsRef<std::path::Path>,
+        D: AsRef<[u8]>,
+    {
+        let path = self.file(rel_path);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// Returns a virtual filesystem that reads from the directory.
+    pub fn vfs(&self) -> crate::common::vfs::Vfs {
+        let mut vfs = crate::common::vfs::Vfs::new();
+        vfs.add_directory(&self.path).unwrap();
+        vfs
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -16,7 +16,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
 };
@@ -118,6 +118,16 @@ impl Vfs {
         Ok(())
     }
 
+    /// Returns the most recently added directory, if any.
+    ///
+    /// Files the engine writes out (demos, configs and the like) belong here.
+    pub fn game_dir(&self) -> Option<&Path> {
+        self.components.iter().rev().find_map(|c| match c {
+            VfsComponent::Directory(path) => Some(path.as_path()),
+            _ => None,
+        })
+    }
+
     pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile, VfsError>
     where
         S: AsRef<str>,
@@ -146,6 +156,58 @@ impl Vfs {
 
         Err(VfsError::NoSuchFile(vp.to_owned()))
     }
+
+    /// Returns the names of the files in virtual directory `dir` with extension `ext`.
+    ///
+    /// Names are returned without the directory or extension, sorted and with duplicates
+    /// removed. Pass an empty `dir` to list the top level.
+    pub fn list(&self, dir: &str, ext: &str) -> Vec<String> {
+        let prefix = match dir {
+            "" => String::new(),
+            d => format!("{}/", d.trim_end_matches('/')),
+        };
+        let suffix = format!(".{}", ext);
+
+        let mut names = Vec::new();
+        for c in self.components.iter() {
+            match c {
+                VfsComponent::Pak(pak) => {
+                    for (path, _) in pak.iter() {
+                        let name = path
+                            .strip_prefix(prefix.as_str())
+                            .and_then(|n| n.strip_suffix(suffix.as_str()));
+                        if let Some(name) = name {
+                            // skip files in subdirectories
+                            if !name.contains('/') {
+                                names.push(name.to_owned());
+                            }
+                        }
+                    }
+                }
+
+                VfsComponent::Directory(path) => {
+                    let entries = match fs::read_dir(path.join(&prefix)) {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+
+                    for entry in entries.filter_map(Result::ok) {
+                        if let Some(name) = entry
+                            .file_name()
+                            .to_str()
+                            .and_then(|n| n.strip_suffix(suffix.as_str()))
+                        {
+                            names.push(name.to_owned());
+                        }
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
 }
 
 pub enum VirtualFile<'a> {
@@ -15,10 +15,13 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+mod cvars;
 pub mod precache;
 pub mod progs;
 pub mod world;
 
+pub use self::cvars::register_cvars;
+
 use std::{
     cell::{Ref, RefCell},
     collections::HashMap,
@@ -27,10 +30,11 @@ use std::{
 
 use crate::{
     common::{
-        console::CvarRegistry,
+        console::{CvarListenerId, CvarRegistry},
         engine::{duration_from_f32, duration_to_f32},
         math::Hyperplane,
         model::Model,
+        net::{NetError, ServerCmd},
         parse,
         vfs::Vfs,
     },
@@ -78,6 +82,9 @@ pub struct ClientActive {
 
     /// ID of the entity controlled by this client.
     entity_id: EntityId,
+
+    /// Reliable data waiting to be sent to this client.
+    message: Vec<u8>,
 }
 
 bitflags! {
@@ -115,6 +122,22 @@ impl ClientSlots {
         self.slots.get(id)?.as_ref()
     }
 
+    /// Returns a mutable reference to the client in a slot.
+    ///
+    /// If the slot is unoccupied, or if `id` is greater than `self.limit()`,
+    /// returns `None`.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut ClientState> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
+    /// Returns an iterator over the active clients.
+    pub fn active_mut(&mut self) -> impl Iterator<Item = &mut ClientActive> {
+        self.slots.iter_mut().filter_map(|slot| match slot {
+            Some(ClientState::Active(active)) => Some(active),
+            _ => None,
+        })
+    }
+
     /// Returns the maximum number of simultaneous clients.
     pub fn limit(&self) -> usize {
         self.slots.len()
@@ -209,6 +232,12 @@ pub struct SessionActive {
 pub struct Session {
     persist: SessionPersistent,
     state: SessionState,
+
+    cvars: Rc<RefCell<CvarRegistry>>,
+    notify_listener: CvarListenerId,
+
+    /// Messages to be sent reliably to every active client.
+    broadcasts: Rc<RefCell<Vec<ServerCmd>>>,
 }
 
 impl Session {
@@ -220,14 +249,68 @@ impl Session {
         models: Vec<Model>,
         entmap: String,
     ) -> Session {
+        let broadcasts = Rc::new(RefCell::new(Vec::new()));
+
+        // tell clients when a notify cvar like sv_gravity or teamplay changes
+        let notify_broadcasts = broadcasts.clone();
+        let notify_listener = cvars
+            .borrow()
+            .add_notify_listener(Box::new(move |name, val| {
+                notify_broadcasts.borrow_mut().push(ServerCmd::Print {
+                    text: format!("Server cvar \"{}\" changed to \"{}\"\n", name, val),
+                })
+            }));
+
         Session {
             persist: SessionPersistent::new(max_clients),
             state: SessionState::Loading(SessionLoading {
-                level: LevelState::new(vfs, cvars, progs, models, entmap),
+                level: LevelState::new(vfs, cvars.clone(), progs, models, entmap),
             }),
+            cvars,
+            notify_listener,
+            broadcasts,
         }
     }
 
+    /// Appends the pending broadcasts to the reliable data of every active client.
+    ///
+    /// Clients that are still connecting get the current values in the serverinfo instead.
+    fn flush_broadcasts(&mut self) -> Result<(), NetError> {
+        let broadcasts = self.broadcasts.replace(Vec::new());
+        if broadcasts.is_empty() {
+            return Ok(());
+        }
+
+        let mut msg = Vec::new();
+        for cmd in broadcasts.iter() {
+            cmd.serialize(&mut msg)?;
+        }
+
+        for client in self.persist.client_slots.active_mut() {
+            client.message.extend_from_slice(&msg);
+        }
+
+        Ok(())
+    }
+
+    /// Takes the reliable data waiting to be sent to the client in `slot`, including anything
+    /// broadcast since the last call.
+    ///
+    /// Returns `None` if the slot has no active client.
+    pub fn take_reliable(&mut self, slot: usize) -> Result<Option<Vec<u8>>, NetError> {
+        self.flush_broadcasts()?;
+
+        Ok(match self.persist.client_slots.get_mut(slot) {
+            Some(ClientState::Active(active)) => Some(std::mem::take(&mut active.message)),
+            _ => None,
+        })
+    }
+
+    /// Returns the serverinfo string built from the notify cvars.
+    pub fn serverinfo(&self) -> String {
+        self.cvars.borrow().serverinfo()
+    }
+
     /// Returns the maximum number of clients allowed on the server.
     pub fn max_clients(&self) -> usize {
         self.persist.client_slots.limit()
@@ -295,6 +378,12 @@ impl Session {
     }
 }
 
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.cvars.borrow().remove_listener(self.notify_listener);
+    }
+}
+
 /// Server-side level state.
 #[derive(Debug)]
 pub struct LevelState {
@@ -1607,3 +1696,89 @@ impl LevelState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{
+        common::bsp,
+        server::{
+            progs::{globals::GLOBAL_DYNAMIC_START, Functions},
+            world::EntityTypeDef,
+        },
+    };
+
+    // enough to load a level with no QuakeC code or map entities
+    fn empty_progs() -> LoadProgs {
+        let string_table = Rc::new(RefCell::new(StringTable::new(vec![0])));
+        let functions = Rc::new(Functions {
+            string_table: string_table.clone(),
+            defs: Box::new([]),
+            statements: Box::new([]),
+        });
+
+        LoadProgs {
+            cx: ExecutionContext::create(string_table.clone(), functions),
+            globals: Globals::new(
+                string_table.clone(),
+                Box::new([]),
+                vec![[0; 4]; GLOBAL_DYNAMIC_START].into_boxed_slice(),
+            ),
+            // room for every field the engine uses
+            entity_def: Rc::new(
+                EntityTypeDef::new(string_table.clone(), 128, Box::new([])).unwrap(),
+            ),
+            string_table,
+        }
+    }
+
+    #[test]
+    fn test_session_broadcasts_notify_cvars() {
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(Rc::new(RefCell::new(
+            Vec::new(),
+        )))));
+        register_cvars(&cvars.borrow()).unwrap();
+
+        let (models, _) = bsp::load(Cursor::new(bsp::test_bsp())).unwrap();
+        let mut session = Session::new(
+            2,
+            Rc::new(Vfs::new()),
+            cvars.clone(),
+            empty_progs(),
+            models,
+            String::new(),
+        );
+
+        // only the client in slot 0 has finished connecting
+        session.persist.client_slots.slots[0] = Some(ClientState::Active(ClientActive {
+            privileged: false,
+            entity_id: EntityId(1),
+            message: Vec::new(),
+        }));
+        session.persist.client_slots.slots[1] = Some(ClientState::Connecting);
+
+        cvars.borrow().set("sv_gravity", "100").unwrap();
+        cvars.borrow().set("skill", "3").unwrap();
+        assert!(session.serverinfo().contains("\\sv_gravity\\100\\"));
+
+        // only the change to the notify cvar is sent
+        let mut expected = Vec::new();
+        ServerCmd::Print {
+            text: "Server cvar \"sv_gravity\" changed to \"100\"\n".to_owned(),
+        }
+        .serialize(&mut expected)
+        .unwrap();
+        assert_eq!(session.take_reliable(0).unwrap(), Some(expected));
+        assert_eq!(session.take_reliable(1).unwrap(), None);
+        assert_eq!(session.take_reliable(0).unwrap(), Some(Vec::new()));
+
+        // nothing is broadcast once the session is gone
+        let broadcasts = session.broadcasts.clone();
+        drop(session);
+        cvars.borrow().set("teamplay", "1").unwrap();
+        assert!(broadcasts.borrow().is_empty());
+    }
+}